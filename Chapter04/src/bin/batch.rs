@@ -0,0 +1,197 @@
+extern crate tetris;
+
+use std::env;
+use std::process;
+use std::thread;
+
+use tetris::bot::{self, Controller, Weights};
+use tetris::cli::{option_value, parsed_option};
+use tetris::game::Tetris;
+use tetris::json::Json;
+use tetris::tbp::ExternalBot;
+
+// Plays many games without a screen and prints statistics about them:
+//
+//   batch [--games 100] [--seed 0] [--bot builtin] [--pieces 1000]
+//     [--weights <file>] [--threads <cores>] [--format json|csv]
+//
+// Game n is played with the seed `seed + n`, so two runs with the same
+// options see the same pieces. The bot is "builtin" or the command of a
+// Tetris Bot Protocol program, which plays the games one after the other.
+struct Results {
+  games: Vec<Tetris>,
+}
+
+struct Summary {
+  mean: f64,
+  median: f64,
+  min: u32,
+  max: u32,
+}
+
+impl Summary {
+  fn of(mut values: Vec<u32>) -> Summary {
+    values.sort();
+    let len = values.len();
+    let median = match len {
+      0 => 0.,
+      _ if 0 == len % 2 => {
+        (values[len / 2 - 1] as f64 + values[len / 2] as f64) / 2.
+      }
+      _ => values[len / 2] as f64,
+    };
+    Summary {
+      mean: values.iter().map(|&value| value as f64).sum::<f64>() /
+        len.max(1) as f64,
+      median,
+      min: values.first().cloned().unwrap_or(0),
+      max: values.last().cloned().unwrap_or(0),
+    }
+  }
+}
+
+const STATISTICS: [&str; 4] = ["lines", "score", "pieces", "frames"];
+
+impl Results {
+  fn top_out_rate(&self) -> f64 {
+    let top_outs = self.games.iter().filter(|tetris| tetris.game_over)
+      .count();
+    top_outs as f64 / self.games.len().max(1) as f64
+  }
+
+  fn summary(&self, statistic: &str) -> Summary {
+    Summary::of(self.games.iter().map(|tetris| match statistic {
+      "lines" => tetris.nb_lines,
+      "score" => tetris.score,
+      // The piece in the grid when the game stopped wasn't placed.
+      "pieces" => tetris.nb_pieces - tetris.current_piece.is_some() as u32,
+      _ => tetris.frame,
+    }).collect())
+  }
+
+  fn to_json(&self) -> Json {
+    let mut fields = vec![
+      ("games", Json::Number(self.games.len() as f64)),
+      ("top_out_rate", Json::Number(self.top_out_rate())),
+    ];
+    for statistic in STATISTICS.iter() {
+      let summary = self.summary(statistic);
+      fields.push((statistic, Json::object(vec![
+        ("mean", Json::Number(summary.mean)),
+        ("median", Json::Number(summary.median)),
+        ("min", Json::Number(summary.min as f64)),
+        ("max", Json::Number(summary.max as f64)),
+      ])));
+    }
+    Json::object(fields)
+  }
+
+  // A header and a single row, one column per value.
+  fn to_csv(&self) -> String {
+    let mut header = vec!["games".to_owned(), "top_out_rate".to_owned()];
+    let mut row = vec![
+      self.games.len().to_string(),
+      self.top_out_rate().to_string(),
+    ];
+    for statistic in STATISTICS.iter() {
+      let summary = self.summary(statistic);
+      for &(name, value) in [
+        ("mean", summary.mean),
+        ("median", summary.median),
+        ("min", summary.min as f64),
+        ("max", summary.max as f64),
+      ].iter() {
+        header.push(format!("{}_{}", name, statistic));
+        row.push(value.to_string());
+      }
+    }
+    format!("{}\n{}\n", header.join(","), row.join(","))
+  }
+}
+
+// The builtin bot plays a move per frame, the games spread over the threads.
+fn play_builtin(
+  seeds: Vec<u64>,
+  weights: Weights,
+  pieces: u32,
+  threads: usize
+) -> Vec<Tetris> {
+  let chunk_size = seeds.len().div_ceil(threads);
+  let handles = seeds.chunks(chunk_size.max(1)).map(|chunk| {
+    let chunk = chunk.to_vec();
+    thread::spawn(move || {
+      chunk.iter().map(|seed| bot::play_game(*seed, &weights, pieces))
+        .collect::<Vec<_>>()
+    })
+  }).collect::<Vec<_>>();
+  handles.into_iter()
+    .flat_map(|handle| handle.join().expect("A batch thread panicked"))
+    .collect()
+}
+
+fn play(external: &mut ExternalBot, seed: u64, pieces: u32) -> Tetris {
+  external.new_game();
+  let mut tetris = Tetris::with_seed(seed);
+  while !tetris.game_over && tetris.nb_pieces <= pieces {
+    let inputs = external.inputs(&tetris);
+    tetris.step(&inputs);
+  }
+  tetris
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let games = parsed_option::<u64>(&args, "--games").unwrap_or(100);
+  let seed = parsed_option::<u64>(&args, "--seed").unwrap_or(0);
+  let pieces = parsed_option::<u32>(&args, "--pieces").unwrap_or(1000);
+  let cores = thread::available_parallelism()
+    .map(|cores| cores.get())
+    .unwrap_or(1);
+  let threads = parsed_option::<usize>(&args, "--threads")
+    .unwrap_or(cores)
+    .max(1);
+  let weights = match option_value(&args, "--weights") {
+    Some(file_name) => match Weights::load(file_name) {
+      Ok(weights) => weights,
+      Err(e) => {
+        eprintln!("Couldn't load the weights {}: {}", file_name, e);
+        process::exit(2);
+      }
+    },
+    None => Weights::default(),
+  };
+  let format = option_value(&args, "--format").unwrap_or("json");
+  if "json" != format && "csv" != format {
+    eprintln!("Usage: batch [--games 100] [--seed 0] [--bot builtin] \
+      [--pieces 1000] [--weights <file>] [--threads <cores>] \
+      [--format json|csv]");
+    process::exit(2);
+  }
+
+  let seeds = (0..games).map(|nb| seed.wrapping_add(nb)).collect::<Vec<_>>();
+  let results = match option_value(&args, "--bot").unwrap_or("builtin") {
+    "builtin" => Results {
+      games: play_builtin(seeds, weights, pieces, threads),
+    },
+    command => {
+      let mut external = match ExternalBot::spawn(command) {
+        Ok(external) => external,
+        Err(e) => {
+          eprintln!("Couldn't start the bot {}: {}", command, e);
+          process::exit(2);
+        }
+      };
+      external.blocking = true;
+      Results {
+        games: seeds.iter()
+          .map(|seed| play(&mut external, *seed, pieces))
+          .collect(),
+      }
+    }
+  };
+  if "csv" == format {
+    print!("{}", results.to_csv());
+  } else {
+    println!("{}", results.to_json());
+  }
+}
@@ -0,0 +1,86 @@
+extern crate tetris;
+
+use std::env;
+use std::process;
+
+use tetris::cli::parsed_option;
+use tetris::fumen::{self, Page};
+use tetris::replay::Replay;
+use tetris::tetrimino::piece_letter;
+
+// Converts between replays and fumen strings:
+//
+//   fumen export <replay> [--player <index>]
+//   fumen show <fumen>
+//
+// `export` prints the fumen of a recorded game, a page for every piece.
+// `show` prints the pages of a fumen, the grid with the piece of the page
+// in lowercase.
+fn print_page(nb: usize, page: &Page) {
+  println!("Page {}{}", nb + 1, if page.comment.is_empty() {
+    String::new()
+  } else {
+    format!(": {}", page.comment)
+  });
+  let cells = page.operation.map(|op| op.cells()).unwrap_or_default();
+  let top = page.field.iter().enumerate()
+    .position(|(row, line)| {
+      line.iter().any(|case| 0 != *case) ||
+        cells.iter().any(|&(_, y)| row as isize == y)
+    })
+    .unwrap_or(page.field.len());
+  for (row, line) in page.field.iter().enumerate().skip(top) {
+    let row = line.iter().enumerate().map(|(x, case)| {
+      match page.operation {
+        Some(op) if cells.contains(&(x as isize, row as isize)) => {
+          piece_letter(op.color).to_lowercase()
+        }
+        _ if 0 == *case => ".".to_owned(),
+        _ => piece_letter(*case).to_owned(),
+      }
+    }).collect::<String>();
+    println!("  {}", row);
+  }
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let usage = "Usage: fumen export <replay> [--player <index>]\n       \
+    fumen show <fumen>";
+  match (args.get(1).map(|command| command.as_str()), args.get(2)) {
+    (Some("export"), Some(file_name)) => {
+      let replay = match Replay::find(file_name) {
+        Ok(replay) => replay,
+        Err(e) => {
+          eprintln!("Couldn't load the replay {}: {}", file_name, e);
+          process::exit(2);
+        }
+      };
+      let player = parsed_option::<usize>(&args, "--player").unwrap_or(0);
+      match replay.players.get(player) {
+        Some(recording) => {
+          println!("{}", fumen::encode(&fumen::game_pages(recording)));
+        }
+        None => {
+          eprintln!("No player {} in the replay", player);
+          process::exit(2);
+        }
+      }
+    }
+    (Some("show"), Some(data)) => match fumen::decode(data) {
+      Ok(pages) => {
+        for (nb, page) in pages.iter().enumerate() {
+          print_page(nb, page);
+        }
+      }
+      Err(e) => {
+        eprintln!("Couldn't read the fumen: {}", e);
+        process::exit(2);
+      }
+    },
+    _ => {
+      eprintln!("{}", usage);
+      process::exit(2);
+    }
+  }
+}
@@ -0,0 +1,33 @@
+extern crate tetris;
+
+use std::env;
+use std::process;
+
+use tetris::lobby::Lobby;
+use tetris::net::DEFAULT_LOBBY_PORT;
+
+// Pairs the players of network games:
+//
+//   lobby [port]
+fn main() {
+  let port = match env::args().nth(1) {
+    Some(port) => match port.parse::<u16>() {
+      Ok(port) => port,
+      Err(_) => {
+        eprintln!("Usage: lobby [port]");
+        process::exit(2);
+      }
+    },
+    None => DEFAULT_LOBBY_PORT,
+  };
+  match Lobby::bind(port) {
+    Ok(mut lobby) => {
+      println!("Lobby listening on port {}", port);
+      lobby.run();
+    }
+    Err(e) => {
+      eprintln!("Couldn't start the lobby: {}", e);
+      process::exit(2);
+    }
+  }
+}
@@ -0,0 +1,110 @@
+extern crate tetris;
+
+use std::env;
+use std::process;
+
+use tetris::bot::{Bot, Controller, Weights};
+use tetris::cli::{option_value, parsed_option};
+use tetris::game::{Input, Tetris};
+use tetris::tbp::ExternalBot;
+
+// Plays bots against each other without a screen:
+//
+//   match --p1 <bot> [--p2 <bot>] [--seed 0] [--pieces 1000]
+//     [--weights <file>]
+//
+// A bot is either "builtin" or the command starting a program speaking the
+// Tetris Bot Protocol, like --p1 "python3 my_bot.py". With a single bot it
+// plays alone until it tops out or places all its pieces.
+fn controller(command: &str, weights: Weights)
+  -> Result<(String, Box<dyn Controller>), String>
+{
+  if "builtin" == command {
+    return Ok(("builtin".to_owned(), Box::new(Bot::with_weights(0, weights))));
+  }
+  match ExternalBot::spawn(command) {
+    Ok(mut external) => {
+      external.blocking = true;
+      Ok((external.name.clone(), Box::new(external)))
+    }
+    Err(e) => Err(format!("Couldn't start the bot {}: {}", command, e)),
+  }
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let seed = parsed_option::<u64>(&args, "--seed").unwrap_or(0);
+  let pieces = parsed_option::<u32>(&args, "--pieces").unwrap_or(1000);
+  let weights = match option_value(&args, "--weights") {
+    Some(file_name) => match Weights::load(file_name) {
+      Ok(weights) => weights,
+      Err(e) => {
+        eprintln!("Couldn't load the weights {}: {}", file_name, e);
+        process::exit(2);
+      }
+    },
+    None => Weights::default(),
+  };
+  let commands = ["--p1", "--p2"].iter()
+    .filter_map(|name| option_value(&args, name))
+    .collect::<Vec<_>>();
+  if commands.is_empty() {
+    eprintln!("Usage: match --p1 <bot> [--p2 <bot>] [--seed 0] \
+      [--pieces 1000] [--weights <file>]");
+    process::exit(2);
+  }
+  let mut players = Vec::new();
+  for command in commands {
+    match controller(command, weights) {
+      Ok((name, controller)) => {
+        players.push((name, controller, Tetris::with_seed(seed)));
+      }
+      Err(e) => {
+        eprintln!("{}", e);
+        process::exit(2);
+      }
+    }
+  }
+
+  let versus = 1 < players.len();
+  loop {
+    let garbage = players.iter_mut()
+      .map(|&mut (_, _, ref mut tetris)| tetris.take_garbage())
+      .collect::<Vec<_>>();
+    for (nb, &mut (_, ref mut controller, ref mut tetris)) in
+      players.iter_mut().enumerate()
+    {
+      let mut inputs = controller.inputs(tetris);
+      if versus && 0 < garbage[1 - nb] {
+        inputs.push(Input::Garbage(garbage[1 - nb]));
+      }
+      tetris.step(&inputs);
+    }
+    let over = players.iter().any(|(_, _, tetris)| tetris.game_over);
+    let done = players.iter()
+      .all(|(_, _, tetris)| pieces < tetris.nb_pieces);
+    if over || done {
+      break
+    }
+  }
+
+  let all_over = players.iter().all(|(_, _, tetris)| tetris.game_over);
+  for (name, _, tetris) in &players {
+    let result = if !versus {
+      ""
+    } else if all_over {
+      "DRAW "
+    } else if tetris.game_over {
+      "LOSE "
+    } else if players.iter().any(|(_, _, other)| other.game_over) {
+      "WIN "
+    } else {
+      "DRAW "
+    };
+    println!(
+      "{}{}: score {}, {} lines, {} pieces, {} frames",
+      result, name, tetris.score, tetris.nb_lines, tetris.nb_pieces,
+      tetris.frame
+    );
+  }
+}
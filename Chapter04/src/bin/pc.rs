@@ -0,0 +1,176 @@
+extern crate tetris;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::process;
+
+use tetris::cli::{option_value, parsed_option};
+use tetris::fumen;
+use tetris::game::{Line, HEIGHT, WIDTH};
+use tetris::movegen;
+use tetris::perfect_clear;
+use tetris::tetrimino::{color_of_letter, piece_letter, Tetrimino};
+
+// Looks for a perfect clear:
+//
+//   pc <board file> --queue <pieces> [--hold <piece>] [--lines 4]
+//   pc <fumen> [--page 1] [--queue <pieces>] [--hold <piece>] [--lines 4]
+//
+// The board file has a row per line, the top one first, with `.` for the
+// empty cases and anything else for blocks. Only the bottom rows need to be
+// there. Pieces are written with their letters, IJLOSTZ, the first piece of
+// the queue being the one in the grid. The queue and the hold of a fumen
+// come from the quiz of its page when not given. Prints the board after
+// every move, and exits with 1 when there is no solution.
+fn invalid(message: String) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn load_board(file_name: &str) -> io::Result<Vec<Line>> {
+  let mut f = File::open(file_name)?;
+  let mut content = String::new();
+  f.read_to_string(&mut content)?;
+  let rows = content.lines().filter(|row| !row.trim().is_empty())
+    .collect::<Vec<_>>();
+  if HEIGHT < rows.len() {
+    return Err(invalid(format!("more than {} rows", HEIGHT)));
+  }
+  let mut game_map = vec![[0; WIDTH]; HEIGHT - rows.len()];
+  for row in rows {
+    let row = row.trim_end();
+    if WIDTH < row.chars().count() {
+      return Err(invalid(format!("more than {} columns: {}", WIDTH, row)));
+    }
+    let mut line = [0; WIDTH];
+    for (x, case) in row.chars().enumerate() {
+      if '.' != case && ' ' != case {
+        line[x] = color_of_letter(case).unwrap_or(8);
+      }
+    }
+    game_map.push(line);
+  }
+  Ok(game_map)
+}
+
+// The queue and the hold of a quiz, as letters.
+type Quiz = (String, String);
+
+// The grid of a page of a fumen, with its quiz.
+fn load_fumen(data: &str, page: usize)
+  -> io::Result<(Vec<Line>, Option<Quiz>)>
+{
+  let pages = fumen::decode(data)?;
+  let page = pages.get(page.max(1) - 1)
+    .ok_or_else(|| invalid(format!("only {} pages", pages.len())))?;
+  let quiz = page.quiz().map(|(held, queue)| {
+    let letters = |colors: Vec<u8>| colors.into_iter()
+      .map(piece_letter)
+      .collect::<String>();
+    (letters(queue), letters(held.into_iter().collect()))
+  });
+  Ok((page.game_map()?, quiz))
+}
+
+fn pieces(letters: &str) -> Option<Vec<Tetrimino>> {
+  letters.chars()
+    .map(|letter| color_of_letter(letter).and_then(Tetrimino::from_color))
+    .collect()
+}
+
+fn print_board(game_map: &[Line]) {
+  let top = game_map.iter()
+    .position(|line| line.iter().any(|case| 0 != *case))
+    .unwrap_or(HEIGHT);
+  for line in &game_map[top..] {
+    let row = line.iter().map(|case| match *case {
+      0 => ".",
+      color => piece_letter(color),
+    }).collect::<String>();
+    println!("  {}", row);
+  }
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let usage = "Usage: pc <board file|fumen> [--page 1] --queue <pieces> \
+    [--hold <piece>] [--lines 4]";
+  let file_name = match args.get(1) {
+    Some(file_name) if !file_name.starts_with("--") => file_name,
+    _ => {
+      eprintln!("{}", usage);
+      process::exit(2);
+    }
+  };
+  let board = if file_name.contains("v115@") {
+    let page = parsed_option::<usize>(&args, "--page").unwrap_or(1);
+    load_fumen(file_name, page)
+  } else {
+    load_board(file_name).map(|game_map| (game_map, None))
+  };
+  let (mut game_map, quiz) = match board {
+    Ok(board) => board,
+    Err(e) => {
+      eprintln!("Couldn't load the board {}: {}", file_name, e);
+      process::exit(2);
+    }
+  };
+  let queue = match option_value(&args, "--queue") {
+    Some(queue) => queue.to_owned(),
+    None => match quiz {
+      Some((ref queue, _)) => queue.clone(),
+      None => {
+        eprintln!("{}", usage);
+        process::exit(2);
+      }
+    },
+  };
+  let queue = match pieces(&queue) {
+    Some(ref queue) if !queue.is_empty() => queue.clone(),
+    _ => {
+      eprintln!("Bad queue {}, expected letters among IJLOSTZ", queue);
+      process::exit(2);
+    }
+  };
+  let hold = option_value(&args, "--hold").map(|hold| hold.to_owned())
+    .or_else(|| quiz.map(|(_, hold)| hold).filter(|hold| !hold.is_empty()));
+  let held = match hold.map(|hold| pieces(&hold)) {
+    Some(Some(ref piece)) if 1 == piece.len() => Some(piece[0]),
+    Some(_) => {
+      eprintln!("Bad hold piece, expected one of IJLOSTZ");
+      process::exit(2);
+    }
+    None => None,
+  };
+  let max_lines = parsed_option::<usize>(&args, "--lines").unwrap_or(4);
+
+  let solution = perfect_clear::solve(
+    &game_map, queue[0], held, &queue[1..], max_lines
+  );
+  let moves = match solution {
+    Some(moves) => moves,
+    None => {
+      println!("No perfect clear within {} lines", max_lines);
+      process::exit(1);
+    }
+  };
+  for (nb, step) in moves.iter().enumerate() {
+    let inputs = step.inputs.iter().map(|input| input.encode())
+      .collect::<Vec<_>>();
+    println!(
+      "{}. {}{} with {}",
+      nb + 1,
+      if step.hold { "hold, " } else { "" },
+      piece_letter(step.piece.color()),
+      inputs.join(",")
+    );
+    for (x, y) in movegen::cells(&step.piece) {
+      game_map[y][x as usize] = step.piece.color();
+    }
+    print_board(&game_map);
+    game_map.retain(|line| line.contains(&0));
+    while game_map.len() < HEIGHT {
+      game_map.insert(0, [0; WIDTH]);
+    }
+  }
+}
@@ -0,0 +1,121 @@
+extern crate tetris;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use tetris::cli::option_value;
+use tetris::leaderboard::{self, History, Leaderboard, Merged};
+use tetris::storage::Loaded;
+
+// Exports the leaderboard or the history of the games of the data directory
+// and merges the ones of teammates:
+//
+//   scores export [--history] [--mode <mode>] [--format json|csv]
+//     [--output <file>]
+//   scores import <file> [--history]
+//
+// Exports go to the standard output without --output. Imports take a
+// leaderboard or history file of the game, or an export in JSON or CSV.
+// Entries already there are skipped, and the leaderboard keeps the best 10
+// scores of every mode.
+// What was in the file, exiting when it can't be read.
+fn load<T: Default>(
+  what: &str,
+  path: &Path,
+  loaded: io::Result<Loaded<T>>
+) -> T {
+  let loaded = match loaded {
+    Ok(loaded) => loaded,
+    Err(e) => {
+      eprintln!("Couldn't read the {} {}: {}", what, path.display(), e);
+      process::exit(2);
+    }
+  };
+  if let Some((ref corrupt, ref e)) = loaded.corrupt {
+    eprintln!(
+      "The {} {} is corrupted ({}), it was moved to {}",
+      what, path.display(), e, corrupt.display()
+    );
+  }
+  loaded.value.unwrap_or_default()
+}
+
+fn export(args: &[String]) {
+  let mode = option_value(args, "--mode");
+  let leaderboard;
+  let history;
+  let entries = if args.iter().any(|arg| "--history" == arg) {
+    history = load("history", &History::path(), History::load());
+    history.games(mode)
+  } else {
+    leaderboard =
+      load("leaderboard", &Leaderboard::path(), Leaderboard::load());
+    match mode {
+      Some(mode) => leaderboard.table(mode),
+      None => leaderboard.entries(),
+    }
+  };
+  let content = match option_value(args, "--format").unwrap_or("json") {
+    "json" => format!("{}\n", leaderboard::to_json(&entries)),
+    "csv" => leaderboard::to_csv(&entries),
+    format => {
+      eprintln!("Unknown format {}, expected json or csv", format);
+      process::exit(2);
+    }
+  };
+  match option_value(args, "--output") {
+    Some(file_name) => {
+      if let Err(e) = fs::write(file_name, content) {
+        eprintln!("Couldn't write {}: {}", file_name, e);
+        process::exit(2);
+      }
+    }
+    None => print!("{}", content),
+  }
+}
+
+fn import(args: &[String], file_name: &str) {
+  let entries = fs::read_to_string(file_name)
+    .and_then(|content| leaderboard::import(&content))
+    .unwrap_or_else(|e| {
+      eprintln!("Couldn't import {}: {}", file_name, e);
+      process::exit(2);
+    });
+  let (merged, saved): (Merged, io::Result<()>) =
+    if args.iter().any(|arg| "--history" == arg) {
+      let mut history = load("history", &History::path(), History::load());
+      let merged = history.merge(entries);
+      (merged, history.save())
+    } else {
+      let mut leaderboard =
+        load("leaderboard", &Leaderboard::path(), Leaderboard::load());
+      let merged = leaderboard.merge(entries);
+      (merged, leaderboard.save())
+    };
+  if let Err(e) = saved {
+    eprintln!("Couldn't save: {}", e);
+    process::exit(2);
+  }
+  println!("{} added, {} already there", merged.added, merged.duplicates);
+  if 0 < merged.dropped {
+    println!("{} not good enough to rank", merged.dropped);
+  }
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let usage = "Usage: scores export [--history] [--mode <mode>] \
+    [--format json|csv] [--output <file>]\n       \
+    scores import <file> [--history]";
+  match (args.get(1).map(|command| command.as_str()), args.get(2)) {
+    (Some("export"), _) => export(&args),
+    (Some("import"), Some(file_name)) => import(&args, file_name),
+    _ => {
+      eprintln!("{}", usage);
+      process::exit(2);
+    }
+  }
+}
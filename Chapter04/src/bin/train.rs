@@ -0,0 +1,202 @@
+extern crate tetris;
+
+use std::env;
+use std::thread;
+
+use tetris::bot::{self, Weights, WEIGHT_NAMES};
+use tetris::cli::parsed_option;
+use tetris::rng::Rng;
+
+// Evolves the weights of the bot:
+//
+//   train [--population 32] [--generations 20] [--games 4] [--pieces 500]
+//     [--validation 8] [--threads <cores>] [--seed 0] [--output weights.txt]
+//
+// Every generation, each set of weights plays the same seeded games and its
+// fitness is the number of lines it cleared. The best ones are bred to
+// replace the worst ones. The best of the generation and the weights saved
+// so far then play the same validation games, drawn once for the whole
+// training, and the output file, which the game loads with --weights, only
+// gets the new weights if they do at least as well there.
+struct Settings {
+  population: usize,
+  generations: u32,
+  games: u32,
+  validation: u32,
+  pieces: u32,
+  threads: usize,
+  seed: u64,
+  output: String,
+}
+
+fn random_unit(rng: &mut Rng) -> f64 {
+  rng.next_u32() as f64 / u32::MAX as f64
+}
+
+// Weights only matter relatively to each other.
+fn normalize(values: [f64; 5]) -> [f64; 5] {
+  let norm = values.iter().map(|value| value * value).sum::<f64>().sqrt();
+  let mut normalized = values;
+  if 0. < norm {
+    for value in normalized.iter_mut() {
+      *value /= norm;
+    }
+  }
+  normalized
+}
+
+fn random_weights(rng: &mut Rng) -> Weights {
+  let mut values = [0.; 5];
+  for value in values.iter_mut() {
+    *value = random_unit(rng) - 0.5;
+  }
+  Weights::from_array(normalize(values))
+}
+
+// Lines cleared by every set of weights, spread over the threads.
+fn evaluate(population: &[Weights], seeds: &[u64], settings: &Settings)
+  -> Vec<u32>
+{
+  let chunk_size = population.len().div_ceil(settings.threads);
+  let handles = population.chunks(chunk_size).map(|chunk| {
+    let chunk = chunk.to_vec();
+    let seeds = seeds.to_vec();
+    let pieces = settings.pieces;
+    thread::spawn(move || {
+      chunk.iter().map(|weights| {
+        seeds.iter()
+          .map(|seed| bot::play_game(*seed, weights, pieces).nb_lines)
+          .sum::<u32>()
+      }).collect::<Vec<_>>()
+    })
+  }).collect::<Vec<_>>();
+  handles.into_iter()
+    .flat_map(|handle| handle.join().expect("A trainer thread panicked"))
+    .collect()
+}
+
+// The best of a few random players.
+fn tournament(rng: &mut Rng, fitness: &[u32]) -> usize {
+  let size = (fitness.len() / 10).max(2);
+  (0..size)
+    .map(|_| rng.gen_range(fitness.len() as u32) as usize)
+    .max_by_key(|&index| fitness[index])
+    .unwrap()
+}
+
+// Average of the parents weighted by their fitness, sometimes mutated.
+fn breed(
+  rng: &mut Rng,
+  first: (&Weights, u32),
+  second: (&Weights, u32)
+) -> Weights {
+  let total = first.1 + second.1;
+  let share = if 0 == total {
+    0.5
+  } else {
+    first.1 as f64 / total as f64
+  };
+  let mut values = [0.; 5];
+  for (nb, value) in values.iter_mut().enumerate() {
+    *value = first.0.to_array()[nb] * share +
+      second.0.to_array()[nb] * (1. - share);
+  }
+  if rng.gen_range(100) < 5 {
+    let nb = rng.gen_range(5) as usize;
+    values[nb] += (random_unit(rng) - 0.5) * 0.4;
+  }
+  Weights::from_array(normalize(values))
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let cores = thread::available_parallelism()
+    .map(|cores| cores.get())
+    .unwrap_or(1);
+  let settings = Settings {
+    population: parsed_option(&args, "--population").unwrap_or(32).max(4),
+    generations: parsed_option(&args, "--generations").unwrap_or(20),
+    games: parsed_option(&args, "--games").unwrap_or(4).max(1),
+    validation: parsed_option(&args, "--validation").unwrap_or(8).max(1),
+    pieces: parsed_option(&args, "--pieces").unwrap_or(500),
+    threads: parsed_option(&args, "--threads").unwrap_or(cores).max(1),
+    seed: parsed_option(&args, "--seed").unwrap_or(0),
+    output: parsed_option(&args, "--output")
+      .unwrap_or_else(|| "weights.txt".to_owned()),
+  };
+  println!(
+    "Training {} bots for {} generations on {} threads",
+    settings.population,
+    settings.generations,
+    settings.threads
+  );
+
+  let mut rng = Rng::new(settings.seed);
+  let validation = (0..settings.validation)
+    .map(|_| rng.next_u32() as u64)
+    .collect::<Vec<_>>();
+  let mut population = (0..settings.population)
+    .map(|_| random_weights(&mut rng))
+    .collect::<Vec<_>>();
+  let mut best: Option<(Weights, u32)> = None;
+
+  for generation in 1..settings.generations + 1 {
+    let seeds = (0..settings.games)
+      .map(|_| rng.next_u32() as u64)
+      .collect::<Vec<_>>();
+    let fitness = evaluate(&population, &seeds, &settings);
+    let (champion, &champion_fitness) = fitness.iter().enumerate()
+      .max_by_key(|&(_, fitness)| *fitness)
+      .unwrap();
+    println!(
+      "Generation {}: best {} lines, average {:.1} lines",
+      generation,
+      champion_fitness,
+      fitness.iter().sum::<u32>() as f64 / fitness.len() as f64
+    );
+    let weights = population[champion];
+    println!("  {}", WEIGHT_NAMES.iter().zip(weights.to_array().iter())
+      .map(|(name, weight)| format!("{} {:.3}", name, weight))
+      .collect::<Vec<_>>().join(", "));
+    // The fitness of the generation comes from games it was picked on.
+    let candidates = match best {
+      Some((incumbent, _)) => vec![weights, incumbent],
+      None => vec![weights],
+    };
+    let validated = evaluate(&candidates, &validation, &settings);
+    println!("  {} lines in the validation games", validated[0]);
+    if validated.get(1).map(|&saved| saved <= validated[0]).unwrap_or(true) {
+      best = Some((weights, validated[0]));
+      if let Err(e) = weights.save(&settings.output) {
+        eprintln!("Couldn't write {}: {}", settings.output, e);
+      }
+    } else {
+      println!("  The saved weights do better, with {} lines", validated[1]);
+    }
+
+    // The offspring replace the worst 30%.
+    let nb_children = settings.population * 3 / 10;
+    let children = (0..nb_children).map(|_| {
+      let first = tournament(&mut rng, &fitness);
+      let second = tournament(&mut rng, &fitness);
+      breed(
+        &mut rng,
+        (&population[first], fitness[first]),
+        (&population[second], fitness[second])
+      )
+    }).collect::<Vec<_>>();
+    let mut ranking = (0..population.len()).collect::<Vec<_>>();
+    ranking.sort_by_key(|&index| fitness[index]);
+    for (index, child) in ranking.into_iter().zip(children) {
+      population[index] = child;
+    }
+  }
+
+  if let Some((_, fitness)) = best {
+    println!(
+      "Best weights ({} validation lines) written to {}",
+      fitness,
+      settings.output
+    );
+  }
+}
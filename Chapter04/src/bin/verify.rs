@@ -0,0 +1,77 @@
+extern crate tetris;
+
+use std::env;
+use std::process;
+
+use tetris::cli::parsed_option;
+use tetris::game::FRAMES_PER_SECOND;
+use tetris::replay::{Claim, Replay};
+
+fn usage() -> ! {
+  eprintln!("Usage: verify <replay> [--player <index>] \
+    [--score <score> --lines <lines> --frames <frames> [--level <level>] \
+    [--unfinished]]");
+  process::exit(2);
+}
+
+// Checks a score submission against its replay:
+//
+//   verify <replay> [--player <index>] [--score <score> --lines <lines>
+//     --frames <frames> [--level <level>] [--unfinished]]
+//
+// The replay can also be the name of a file of the replay directory, as
+// the leaderboard has it. Without --score, --lines and --frames, the result
+// stored in the replay is checked. The game has to be over at the end of
+// the replay, unless --unfinished says it stopped before.
+// Exits with 0 when the submission is accepted, 1 when it is rejected and 2
+// when the replay can't be read or the options are wrong.
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let file_name = match args.get(1) {
+    Some(file_name) => file_name,
+    None => usage(),
+  };
+  let replay = match Replay::find(file_name) {
+    Ok(replay) => replay,
+    Err(e) => {
+      eprintln!("UNREADABLE: {}", e);
+      process::exit(2);
+    }
+  };
+  let player = parsed_option(&args, "--player").unwrap_or(0);
+  let claim = match (
+    parsed_option(&args, "--score"),
+    parsed_option(&args, "--lines"),
+    parsed_option(&args, "--frames")
+  ) {
+    (Some(score), Some(lines), Some(frames)) => Some(Claim {
+      score,
+      lines,
+      level: parsed_option(&args, "--level"),
+      frames,
+      over: !args.iter().any(|arg| "--unfinished" == arg),
+    }),
+    (None, None, None) => replay.recorded_claim(player),
+    _ => usage(),
+  };
+  let claim = match claim {
+    Some(claim) => claim,
+    None => {
+      println!("REJECTED: nothing to check, the replay has no result");
+      process::exit(1);
+    }
+  };
+  match replay.verify(player, &claim) {
+    Ok(tetris) => println!(
+      "ACCEPTED: score {}, {} lines, level {}, {:.1} seconds",
+      tetris.score,
+      tetris.nb_lines,
+      tetris.current_level,
+      tetris.frame as f64 / FRAMES_PER_SECOND as f64
+    ),
+    Err(reason) => {
+      println!("REJECTED: {}", reason);
+      process::exit(1);
+    }
+  }
+}
@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+
+use game::{Input, Line, Tetris, HEIGHT, WIDTH};
+use movegen::{self, Placement};
+use tetrimino::Tetrimino;
+
+pub const WEIGHT_NAMES: [&str; 5] =
+  ["aggregate_height", "lines", "holes", "bumpiness", "wells"];
+
+// How much each feature of a stack counts. Positive features are good.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weights {
+  // Sum of the heights of the columns.
+  pub aggregate_height: f64,
+  // Lines cleared by the placement.
+  pub lines: f64,
+  // Empty cases with a block above them.
+  pub holes: f64,
+  // Sum of the height differences between neighbour columns.
+  pub bumpiness: f64,
+  // Sum of the depths of the columns lower than both neighbours.
+  pub wells: f64,
+}
+
+impl Default for Weights {
+  fn default() -> Weights {
+    Weights {
+      aggregate_height: -0.51,
+      lines: 0.76,
+      holes: -0.36,
+      bumpiness: -0.18,
+      wells: -0.05,
+    }
+  }
+}
+
+impl Weights {
+  pub fn to_array(&self) -> [f64; 5] {
+    [
+      self.aggregate_height,
+      self.lines,
+      self.holes,
+      self.bumpiness,
+      self.wells,
+    ]
+  }
+
+  pub fn from_array(values: [f64; 5]) -> Weights {
+    Weights {
+      aggregate_height: values[0],
+      lines: values[1],
+      holes: values[2],
+      bumpiness: values[3],
+      wells: values[4],
+    }
+  }
+
+  // One `<name> <weight>` per line, missing weights keep their default.
+  pub fn encode(&self) -> String {
+    WEIGHT_NAMES.iter().zip(self.to_array().iter())
+      .map(|(name, weight)| format!("{} {}\n", name, weight))
+      .collect()
+  }
+
+  pub fn decode(content: &str) -> io::Result<Weights> {
+    let mut values = Weights::default().to_array();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+      let mut words = line.split_whitespace();
+      let name = words.next().unwrap_or("");
+      let invalid = |message: &str| {
+        io::Error::new(ErrorKind::InvalidData, format!("{} {}", message, name))
+      };
+      let index = WEIGHT_NAMES.iter().position(|known| *known == name)
+        .ok_or_else(|| invalid("unknown weight"))?;
+      values[index] = words.next()
+        .and_then(|weight| weight.parse::<f64>().ok())
+        .ok_or_else(|| invalid("bad weight"))?;
+    }
+    Ok(Weights::from_array(values))
+  }
+
+  pub fn load(file_name: &str) -> io::Result<Weights> {
+    let mut f = File::open(file_name)?;
+    let mut content = String::new();
+    f.read_to_string(&mut content)?;
+    Weights::decode(&content)
+  }
+
+  pub fn save(&self, file_name: &str) -> io::Result<()> {
+    let mut f = File::create(file_name)?;
+    f.write_all(self.encode().as_bytes())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Features {
+  pub aggregate_height: f64,
+  pub lines: f64,
+  pub holes: f64,
+  pub bumpiness: f64,
+  pub wells: f64,
+}
+
+impl Features {
+  pub fn of(game_map: &[Line], lines: u32) -> Features {
+    let mut heights = [0; WIDTH];
+    let mut holes = 0;
+    for x in 0..WIDTH {
+      let top = (0..HEIGHT).find(|&y| 0 != game_map[y][x]);
+      if let Some(top) = top {
+        heights[x] = HEIGHT - top;
+        holes += (top..HEIGHT).filter(|&y| 0 == game_map[y][x]).count();
+      }
+    }
+    let bumpiness = heights.windows(2)
+      .map(|pair| (pair[0] as isize - pair[1] as isize).abs())
+      .sum::<isize>();
+    let wells = (0..WIDTH).map(|x| {
+      let left = if 0 == x { HEIGHT } else { heights[x - 1] };
+      let right = if WIDTH - 1 == x { HEIGHT } else { heights[x + 1] };
+      left.min(right).saturating_sub(heights[x])
+    }).sum::<usize>();
+    Features {
+      aggregate_height: heights.iter().sum::<usize>() as f64,
+      lines: lines as f64,
+      holes: holes as f64,
+      bumpiness: bumpiness as f64,
+      wells: wells as f64,
+    }
+  }
+
+  pub fn score(&self, weights: &Weights) -> f64 {
+    weights.aggregate_height * self.aggregate_height +
+      weights.lines * self.lines +
+      weights.holes * self.holes +
+      weights.bumpiness * self.bumpiness +
+      weights.wells * self.wells
+  }
+}
+
+// Anything playing a board instead of the keyboard.
+pub trait Controller {
+  // The inputs for the next frame of `tetris`.
+  fn inputs(&mut self, tetris: &Tetris) -> Vec<Input>;
+}
+
+// A computer player. When a piece enters the grid it tries every reachable
+// placement of it and of the held piece, keeps the one leaving the best
+// stack, then presses the keys to get there like a human would, one every
+// `delay` frames. When gravity moves the piece meanwhile, the way to the
+// placement is found again from where the piece is.
+pub struct Bot {
+  pub delay: u32,
+  pub weights: Weights,
+  wait: u32,
+  planned_piece: u32,
+  plan: VecDeque<Input>,
+  // Where the current piece goes, and where the plan expects it to be.
+  target: Option<Tetrimino>,
+  expected: Option<(isize, usize, u8)>,
+}
+
+fn position(piece: &Tetrimino) -> (isize, usize, u8) {
+  (piece.x, piece.y, piece.current_state)
+}
+
+impl Bot {
+  pub fn new(delay: u32) -> Bot {
+    Bot::with_weights(delay, Weights::default())
+  }
+
+  pub fn with_weights(delay: u32, weights: Weights) -> Bot {
+    Bot {
+      delay,
+      weights,
+      wait: 0,
+      planned_piece: 0,
+      plan: VecDeque::new(),
+      target: None,
+      expected: None,
+    }
+  }
+
+  fn plan(&mut self, tetris: &Tetris) {
+    let (plan, target) = best_plan(tetris, &self.weights);
+    self.plan = plan.into_iter().collect();
+    self.target = target;
+  }
+
+  // The inputs of the bot for the next frame of `tetris`.
+  pub fn inputs(&mut self, tetris: &Tetris) -> Vec<Input> {
+    let piece = match tetris.current_piece {
+      Some(piece) if !tetris.game_over => piece,
+      _ => return Vec::new(),
+    };
+    if self.planned_piece != tetris.nb_pieces {
+      self.planned_piece = tetris.nb_pieces;
+      self.plan(tetris);
+      self.expected = Some(position(&piece));
+      self.wait = self.delay;
+    }
+    if 0 < self.wait {
+      self.wait -= 1;
+      return Vec::new();
+    }
+    self.wait = self.delay;
+    if Some(position(&piece)) != self.expected {
+      let path = self.target.as_ref()
+        .and_then(|target| movegen::path_to(&tetris.game_map, piece, target));
+      match path {
+        Some(path) => self.plan = path.into_iter().collect(),
+        None => self.plan(tetris),
+      }
+    }
+    let input = self.plan.pop_front();
+    self.expected = input
+      .and_then(|input| movegen::apply(&tetris.game_map, &piece, input))
+      .or(Some(piece))
+      .as_ref()
+      .map(position);
+    input.into_iter().collect()
+  }
+}
+
+impl Controller for Bot {
+  fn inputs(&mut self, tetris: &Tetris) -> Vec<Input> {
+    Bot::inputs(self, tetris)
+  }
+}
+
+// The inputs for the best move of `tetris`. Either a hold, after which the
+// new piece gets its own plan, or the inputs placing the current piece,
+// ending with a hard drop.
+pub fn best_move(tetris: &Tetris, weights: &Weights) -> Vec<Input> {
+  best_plan(tetris, weights).0
+}
+
+// The inputs of the best move, with where they lock the current piece.
+fn best_plan(tetris: &Tetris, weights: &Weights)
+  -> (Vec<Input>, Option<Tetrimino>)
+{
+  let (score, placement) = match best_placement(tetris, weights) {
+    Some(best) => best,
+    None => return (vec![Input::HardDrop], None),
+  };
+  let mut held = tetris.clone();
+  if held.hold() && !held.game_over {
+    if let Some((held_score, _)) = best_placement(&held, weights) {
+      if score < held_score {
+        return (vec![Input::Hold], None);
+      }
+    }
+  }
+  (placement.inputs, Some(placement.piece))
+}
+
+// The best placement of the current piece of `tetris`, with its score.
+pub fn best_placement(
+  tetris: &Tetris,
+  weights: &Weights
+) -> Option<(f64, Placement)> {
+  let piece = tetris.current_piece?;
+  let mut best: Option<(f64, Placement)> = None;
+  for placement in movegen::placements(&tetris.game_map, piece) {
+    let score = evaluate(tetris, placement.piece, weights);
+    if best.as_ref().map(|&(best, _)| best < score).unwrap_or(true) {
+      best = Some((score, placement));
+    }
+  }
+  best
+}
+
+// How good the stack is once `piece` is locked, higher is better.
+fn evaluate(tetris: &Tetris, piece: Tetrimino, weights: &Weights) -> f64 {
+  let mut after = tetris.clone();
+  after.current_piece = Some(piece);
+  after.make_permanent();
+  let lines = after.nb_lines - tetris.nb_lines;
+  Features::of(&after.game_map, lines).score(weights)
+}
+
+// Plays a whole game without a screen, a move per frame, stopping after
+// `max_pieces` pieces if the bot is still alive.
+pub fn play_game(seed: u64, weights: &Weights, max_pieces: u32) -> Tetris {
+  let mut tetris = Tetris::with_seed(seed);
+  while !tetris.game_over && tetris.nb_pieces <= max_pieces {
+    let inputs = if tetris.current_piece.is_some() {
+      best_move(&tetris, weights)
+    } else {
+      Vec::new()
+    };
+    tetris.step(&inputs);
+  }
+  tetris
+}
+
+#[cfg(test)]
+mod tests {
+  use game::{Input, Tetris, HEIGHT, WIDTH};
+  use movegen;
+  use tetrimino::{TetriminoGenerator, TetriminoI};
+  use super::{play_game, Bot, Weights};
+
+  #[test]
+  fn clears_lines_and_survives() {
+    let mut tetris = Tetris::with_seed(11);
+    let mut bot = Bot::new(0);
+    while tetris.nb_pieces < 200 {
+      let inputs = bot.inputs(&tetris);
+      tetris.step(&inputs);
+      assert!(!tetris.game_over);
+    }
+    assert!(40 < tetris.nb_lines);
+  }
+
+  #[test]
+  fn weights_round_trip() {
+    let weights = Weights::from_array([-0.5, 0.75, -0.25, -0.125, 1.5]);
+    assert_eq!(weights, Weights::decode(&weights.encode()).unwrap());
+    let partial = Weights::decode("holes -2\n\n").unwrap();
+    assert_eq!(-2.0, partial.holes);
+    assert_eq!(Weights::default().lines, partial.lines);
+    assert!(Weights::decode("speed 1").is_err());
+    assert!(Weights::decode("holes many").is_err());
+  }
+
+  #[test]
+  fn games_stop_after_the_pieces_asked_for() {
+    let tetris = play_game(3, &Weights::default(), 30);
+    assert!(!tetris.game_over);
+    assert_eq!(31, tetris.nb_pieces);
+    assert_eq!(tetris.nb_lines, play_game(3, &Weights::default(), 30).nb_lines);
+  }
+
+  #[test]
+  fn gravity_doesnt_send_slow_bots_astray() {
+    // A wall in column 3, the piece has to go over it early.
+    let mut map = vec![[0; WIDTH]; HEIGHT];
+    for row in map.iter_mut().skip(6) {
+      row[3] = 8;
+    }
+    let mut tetris = Tetris::with_seed(5);
+    tetris.game_map = map;
+    tetris.next_piece = Some(TetriminoI::new());
+    tetris.step(&[]);
+    // Gravity moves the piece two rows for every input.
+    let mut bot = Bot::new(120);
+    bot.inputs(&tetris);
+    let piece = tetris.current_piece.unwrap();
+    let left = movegen::placements(&tetris.game_map, piece).into_iter()
+      .find(|placement| {
+        placement.cells().iter().all(|&(x, _)| 0 == x)
+      })
+      .unwrap();
+    bot.plan = left.inputs.into_iter().collect();
+    bot.target = Some(left.piece);
+
+    while 1 == tetris.nb_pieces {
+      let inputs = bot.inputs(&tetris);
+      tetris.step(&inputs);
+      assert!(!inputs.contains(&Input::Hold));
+    }
+    for (x, y) in movegen::cells(&bot.target.unwrap()) {
+      assert_eq!(1, tetris.game_map[y][x as usize]);
+    }
+  }
+}
@@ -0,0 +1,31 @@
+use std::str::FromStr;
+
+// The value following `name` in the command line arguments, as in
+// `--seed 3`.
+pub fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+  args.iter().position(|arg| arg == name)
+    .and_then(|index| args.get(index + 1))
+    .map(|value| value.as_str())
+}
+
+// The value of an option, if it parses.
+pub fn parsed_option<T: FromStr>(args: &[String], name: &str) -> Option<T> {
+  option_value(args, name).and_then(|value| value.parse::<T>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{option_value, parsed_option};
+
+  #[test]
+  fn options_take_the_next_argument() {
+    let args = ["batch", "--games", "12", "--seed", "x", "--bot"].iter()
+      .map(|arg| arg.to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(Some("12"), option_value(&args, "--games"));
+    assert_eq!(Some(12), parsed_option::<u32>(&args, "--games"));
+    assert_eq!(None, parsed_option::<u64>(&args, "--seed"));
+    assert_eq!(None, option_value(&args, "--bot"));
+    assert_eq!(None, option_value(&args, "--pieces"));
+  }
+}
@@ -0,0 +1,261 @@
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use fumen::{self, Page};
+use game::{Line, Tetris, GARBAGE, HEIGHT, WIDTH};
+use puzzle::{Goal, Puzzle};
+use tetrimino::{piece_letter, Tetrimino};
+
+// The goals a board can be given, in the order they come round.
+const GOALS: [Goal; 7] = [
+  Goal::Lines(1),
+  Goal::Lines(2),
+  Goal::Lines(3),
+  Goal::Lines(4),
+  Goal::PerfectClear,
+  Goal::TSpin(1),
+  Goal::TSpin(2),
+];
+
+// A board being set up by hand: the grid, the pieces to deal and the held
+// one, and the goal if it becomes a puzzle.
+pub struct Editor {
+  pub game_map: Vec<Line>,
+  pub queue: Vec<u8>,
+  pub hold: Option<u8>,
+  pub goal: Goal,
+  pub name: String,
+  // The color painted, 0 erases.
+  pub color: u8,
+  // Where the board is saved, the file it came from if any.
+  pub file_name: Option<String>,
+}
+
+impl Default for Editor {
+  fn default() -> Editor {
+    Editor::new()
+  }
+}
+
+impl Editor {
+  pub fn new() -> Editor {
+    Editor {
+      game_map: vec![[0; WIDTH]; HEIGHT],
+      queue: Vec::new(),
+      hold: None,
+      goal: Goal::Lines(1),
+      name: "Custom".to_owned(),
+      color: GARBAGE,
+      file_name: None,
+    }
+  }
+
+  pub fn from_puzzle(puzzle: Puzzle, file_name: Option<String>) -> Editor {
+    Editor {
+      game_map: puzzle.board,
+      queue: puzzle.pieces,
+      hold: puzzle.hold,
+      goal: puzzle.goal,
+      name: puzzle.name,
+      color: GARBAGE,
+      file_name,
+    }
+  }
+
+  // The grid of a page of a fumen, and the pieces of its quiz.
+  pub fn from_page(page: &Page) -> io::Result<Editor> {
+    let mut editor = Editor::new();
+    editor.game_map = page.game_map()?;
+    if let Some((hold, queue)) = page.quiz() {
+      editor.hold = hold;
+      editor.queue = queue;
+    }
+    Ok(editor)
+  }
+
+  // A puzzle file, a puzzle by name or a fumen string.
+  pub fn open(name: &str) -> io::Result<Editor> {
+    if name.contains(fumen::VERSION) {
+      let pages = fumen::decode(name)?;
+      return match pages.first() {
+        Some(page) => Editor::from_page(page),
+        None => Err(io::Error::new(ErrorKind::InvalidData, "no page")),
+      };
+    }
+    let file_name = Puzzle::file_name(name);
+    let puzzle = Puzzle::load(&file_name)?;
+    Ok(Editor::from_puzzle(puzzle, Some(file_name)))
+  }
+
+  pub fn paint(&mut self, x: usize, y: usize, color: u8) {
+    if x < WIDTH && y < HEIGHT {
+      self.game_map[y][x] = color;
+    }
+  }
+
+  // An empty row at `y`, the rows above go up and the top one is lost.
+  pub fn insert_row(&mut self, y: usize) {
+    if y < HEIGHT {
+      self.game_map.remove(0);
+      self.game_map.insert(y, [0; WIDTH]);
+    }
+  }
+
+  // Takes the row `y` out, the rows above come down.
+  pub fn remove_row(&mut self, y: usize) {
+    if y < HEIGHT {
+      self.game_map.remove(y);
+      self.game_map.insert(0, [0; WIDTH]);
+    }
+  }
+
+  pub fn push_piece(&mut self, color: u8) {
+    if 0 < color && color < GARBAGE {
+      self.queue.push(color);
+    }
+  }
+
+  pub fn pop_piece(&mut self) {
+    self.queue.pop();
+  }
+
+  // The first piece of the queue goes aside, or the held one comes back.
+  pub fn toggle_hold(&mut self) {
+    match self.hold.take() {
+      Some(hold) => self.queue.insert(0, hold),
+      None if !self.queue.is_empty() => self.hold = Some(self.queue.remove(0)),
+      None => {}
+    }
+  }
+
+  pub fn next_goal(&mut self) {
+    let index = GOALS.iter().position(|goal| self.goal == *goal)
+      .map(|index| (index + 1) % GOALS.len())
+      .unwrap_or(0);
+    self.goal = GOALS[index];
+  }
+
+  // The pieces as letters, the held one between brackets.
+  pub fn describe_queue(&self) -> String {
+    let queue = self.queue.iter().map(|color| piece_letter(*color))
+      .collect::<String>();
+    match self.hold {
+      Some(hold) => format!("[{}] {}", piece_letter(hold), queue),
+      None => queue,
+    }
+  }
+
+  pub fn to_puzzle(&self) -> Puzzle {
+    Puzzle {
+      name: self.name.clone(),
+      goal: self.goal,
+      pieces: self.queue.clone(),
+      hold: self.hold,
+      board: self.game_map.clone(),
+    }
+  }
+
+  // A game from the board, dealing the queue first then random pieces.
+  pub fn to_game(&self, seed: u64) -> Tetris {
+    let pieces = self.queue.iter()
+      .filter_map(|color| Tetrimino::from_color(*color))
+      .collect::<Vec<_>>();
+    let mut tetris = Tetris::with_board(seed, self.game_map.clone(), &pieces);
+    tetris.held_piece = self.hold.and_then(Tetrimino::from_color);
+    tetris
+  }
+
+  // A page of the board, with the pieces in a quiz comment.
+  pub fn to_fumen(&self) -> String {
+    let mut page = Page::new(&self.game_map);
+    if !self.queue.is_empty() {
+      let letters = self.queue.iter().map(|color| piece_letter(*color))
+        .collect::<Vec<_>>();
+      page.comment = format!(
+        "#Q=[{}]({}){}",
+        self.hold.map(piece_letter).unwrap_or(""),
+        letters[0],
+        letters[1..].concat()
+      );
+    }
+    fumen::encode(&[page])
+  }
+
+  // Saves the puzzle in its file, or in a new file among the puzzles of the
+  // user. A puzzle of `PUZZLE_DIRECTORY` is saved among the puzzles of the
+  // user instead. Returns the file name.
+  pub fn save(&mut self) -> io::Result<String> {
+    if self.queue.is_empty() {
+      return Err(io::Error::new(ErrorKind::InvalidData, "no pieces"));
+    }
+    let file_name = match self.file_name {
+      Some(ref file_name) => Puzzle::writable_file(file_name),
+      None => {
+        let mut nb = 1;
+        while Path::new(&Editor::custom_file(nb)).exists() {
+          nb += 1;
+        }
+        self.name = format!("Custom {}", nb);
+        Editor::custom_file(nb)
+      }
+    };
+    self.to_puzzle().save(&file_name)?;
+    self.file_name = Some(file_name.clone());
+    Ok(file_name)
+  }
+
+  fn custom_file(nb: u32) -> String {
+    Puzzle::user_directory().join(format!("custom_{}.txt", nb))
+      .to_string_lossy()
+      .into_owned()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use fumen;
+  use game::{GARBAGE, HEIGHT};
+  use puzzle::{Goal, Puzzle};
+  use super::Editor;
+
+  fn editor() -> Editor {
+    let mut editor = Editor::new();
+    for x in 0..9 {
+      editor.paint(x, HEIGHT - 1, GARBAGE);
+    }
+    editor.paint(0, HEIGHT - 2, 2);
+    editor.push_piece(1);
+    editor.push_piece(7);
+    editor.push_piece(GARBAGE);
+    editor
+  }
+
+  #[test]
+  fn boards_become_puzzles_and_fumens() {
+    let mut editor = editor();
+    editor.toggle_hold();
+    assert_eq!("[I] T", editor.describe_queue());
+    editor.next_goal();
+    assert_eq!(Goal::Lines(2), editor.goal);
+
+    let puzzle = Puzzle::decode(&editor.to_puzzle().encode()).unwrap();
+    assert_eq!(editor.game_map, puzzle.board);
+    assert_eq!((vec![7], Some(1)), (puzzle.pieces, puzzle.hold));
+
+    let pages = fumen::decode(&editor.to_fumen()).unwrap();
+    let from_fumen = Editor::from_page(&pages[0]).unwrap();
+    assert_eq!(editor.game_map, from_fumen.game_map);
+    assert_eq!(editor.describe_queue(), from_fumen.describe_queue());
+  }
+
+  #[test]
+  fn rows_come_and_go() {
+    let mut editor = editor();
+    let game_map = editor.game_map.clone();
+    editor.insert_row(HEIGHT - 1);
+    assert_eq!(game_map[HEIGHT - 1], editor.game_map[HEIGHT - 2]);
+    assert!(editor.game_map[HEIGHT - 1].iter().all(|case| 0 == *case));
+    editor.remove_row(HEIGHT - 1);
+    assert_eq!(game_map, editor.game_map);
+  }
+}
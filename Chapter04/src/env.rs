@@ -0,0 +1,255 @@
+use game::{Input, Line, Tetris, HEIGHT, WIDTH};
+use movegen::{self, Placement};
+use tetrimino::Tetrimino;
+
+// Number of kinds of pieces, for the one-hot encodings.
+const NB_PIECES: usize = 7;
+// The actions of `ActionSpace::Keys`, the first one presses nothing.
+pub const KEYS: [Option<Input>; 7] = [
+  None,
+  Some(Input::Left),
+  Some(Input::Right),
+  Some(Input::SoftDrop),
+  Some(Input::Rotate),
+  Some(Input::HardDrop),
+  Some(Input::Hold),
+];
+
+// Parts of the observation, concatenated in the order they are configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+  // 1 for every taken case of the grid, row by row from the top.
+  Board,
+  // The cases of the current piece, like `Board`.
+  Piece,
+  // Height of every column, divided by the height of the grid.
+  Heights,
+  // One-hot current, next and held pieces, all zeroes when there is none.
+  Queue,
+}
+
+impl Encoding {
+  pub fn size(&self) -> usize {
+    match *self {
+      Encoding::Board | Encoding::Piece => WIDTH * HEIGHT,
+      Encoding::Heights => WIDTH,
+      Encoding::Queue => 3 * NB_PIECES,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionSpace {
+  // An action is an index in `KEYS`, each step is a frame.
+  Keys,
+  // An action is an index in `Env::placements`, or its length to hold. Each
+  // step plays a whole piece.
+  Placements,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+  pub encodings: Vec<Encoding>,
+  pub actions: ActionSpace,
+  // Taken from the reward of the step ending the game.
+  pub game_over_penalty: f64,
+  // Ends the episode after this many pieces if the game is still going.
+  pub max_pieces: Option<u32>,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      encodings: vec![Encoding::Board, Encoding::Heights, Encoding::Queue],
+      actions: ActionSpace::Placements,
+      game_over_penalty: 10.,
+      max_pieces: None,
+    }
+  }
+}
+
+// The engine wrapped for reinforcement learning: the real rules without a
+// screen.
+//
+// - An observation has `observation_size` values between 0 and 1, the
+//   encodings of the config one after the other.
+// - An action is an index below `nb_actions`. With placements, the number
+//   of actions changes with every piece.
+// - The reward of a step is the score it made, less `game_over_penalty` if
+//   it topped out. Reaching `max_pieces` ends the episode without penalty.
+// - Once done, steps change nothing and their reward is 0 until `reset`.
+//
+// Everything comes from the seed of `reset`: the same seed and the same
+// actions give the same observations and rewards.
+pub struct Env {
+  pub config: Config,
+  pub tetris: Tetris,
+  placements: Vec<Placement>,
+}
+
+fn one_hot(observation: &mut Vec<f32>, piece: &Option<Tetrimino>) {
+  let start = observation.len();
+  observation.extend((0..NB_PIECES).map(|_| 0.));
+  if let Some(ref piece) = *piece {
+    observation[start + piece.color() as usize - 1] = 1.;
+  }
+}
+
+fn heights(game_map: &[Line]) -> Vec<f32> {
+  (0..WIDTH).map(|x| {
+    let top = (0..HEIGHT).find(|&y| 0 != game_map[y][x]).unwrap_or(HEIGHT);
+    (HEIGHT - top) as f32 / HEIGHT as f32
+  }).collect()
+}
+
+impl Env {
+  pub fn new(config: Config) -> Env {
+    let mut env = Env {
+      config,
+      tetris: Tetris::with_seed(0),
+      placements: Vec::new(),
+    };
+    env.reset(0);
+    env
+  }
+
+  // Starts a new game, returns its first observation.
+  pub fn reset(&mut self, seed: u64) -> Vec<f32> {
+    self.tetris = Tetris::with_seed(seed);
+    self.spawn();
+    self.observation()
+  }
+
+  pub fn observation_size(&self) -> usize {
+    self.config.encodings.iter().map(|encoding| encoding.size()).sum()
+  }
+
+  pub fn nb_actions(&self) -> usize {
+    match self.config.actions {
+      ActionSpace::Keys => KEYS.len(),
+      ActionSpace::Placements => self.placements.len() + 1,
+    }
+  }
+
+  // Where the current piece can go, the actions of `ActionSpace::Placements`.
+  pub fn placements(&self) -> &[Placement] {
+    &self.placements
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.tetris.game_over || self.config.max_pieces
+      .map(|max| max < self.tetris.nb_pieces)
+      .unwrap_or(false)
+  }
+
+  // Plays `action`. Actions out of range press nothing, or hard drop the
+  // piece where it is with placements.
+  pub fn step(&mut self, action: usize) -> (Vec<f32>, f64, bool) {
+    let score = self.tetris.score;
+    if !self.is_done() {
+      match self.config.actions {
+        ActionSpace::Keys => {
+          let inputs = KEYS.get(action).and_then(|key| *key)
+            .into_iter().collect::<Vec<_>>();
+          self.tetris.step(&inputs);
+        }
+        ActionSpace::Placements => {
+          let inputs = match self.placements.get(action) {
+            Some(placement) => placement.inputs.clone(),
+            None if action == self.placements.len() => vec![Input::Hold],
+            None => vec![Input::HardDrop],
+          };
+          self.tetris.step(&inputs);
+          self.spawn();
+        }
+      }
+    }
+    let done = self.is_done();
+    let mut reward = (self.tetris.score - score) as f64;
+    if self.tetris.game_over && done {
+      reward -= self.config.game_over_penalty;
+    }
+    (self.observation(), reward, done)
+  }
+
+  // Lets the frames go by until a piece is in the grid.
+  fn spawn(&mut self) {
+    while self.tetris.current_piece.is_none() && !self.tetris.game_over {
+      self.tetris.step(&[]);
+    }
+    self.placements = match (self.config.actions, self.tetris.current_piece) {
+      (ActionSpace::Placements, Some(piece)) => {
+        movegen::placements(&self.tetris.game_map, piece)
+      }
+      _ => Vec::new(),
+    };
+  }
+
+  pub fn observation(&self) -> Vec<f32> {
+    let mut observation = Vec::with_capacity(self.observation_size());
+    for encoding in &self.config.encodings {
+      match *encoding {
+        Encoding::Board => {
+          observation.extend(self.tetris.game_map.iter()
+            .flat_map(|line| line.iter())
+            .map(|case| if 0 == *case { 0. } else { 1. }));
+        }
+        Encoding::Piece => {
+          let start = observation.len();
+          observation.extend((0..WIDTH * HEIGHT).map(|_| 0.));
+          if let Some(ref piece) = self.tetris.current_piece {
+            for (x, y) in movegen::cells(piece) {
+              observation[start + y * WIDTH + x as usize] = 1.;
+            }
+          }
+        }
+        Encoding::Heights => {
+          observation.extend(heights(&self.tetris.game_map));
+        }
+        Encoding::Queue => {
+          one_hot(&mut observation, &self.tetris.current_piece);
+          one_hot(&mut observation, &self.tetris.next_piece);
+          one_hot(&mut observation, &self.tetris.held_piece);
+        }
+      }
+    }
+    observation
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ActionSpace, Config, Env};
+
+  // The observations and rewards of a game, with actions cycling through
+  // every index and one past.
+  fn episode(config: &Config, seed: u64) -> Vec<(Vec<f32>, f64, bool)> {
+    let mut env = Env::new(config.clone());
+    let first = env.reset(seed);
+    let mut steps = vec![(first, 0., false)];
+    for step in 0..300 {
+      let action = step * 7 % (env.nb_actions() + 1);
+      let (observation, reward, done) = env.step(action);
+      assert_eq!(env.observation_size(), observation.len());
+      steps.push((observation, reward, done));
+      if done {
+        break
+      }
+    }
+    steps
+  }
+
+  #[test]
+  fn same_seed_and_actions_give_the_same_episode() {
+    for actions in &[ActionSpace::Keys, ActionSpace::Placements] {
+      let config = Config {
+        actions: *actions,
+        max_pieces: Some(40),
+        ..Config::default()
+      };
+      let played = episode(&config, 9);
+      assert!(10 < played.len());
+      assert_eq!(played, episode(&config, 9));
+    }
+  }
+}
@@ -0,0 +1,320 @@
+use game::{Input, Line, Tetris, HEIGHT, WIDTH};
+use movegen::{self, Placement};
+use rng::Rng;
+use tetrimino::Tetrimino;
+
+// A locked piece, with the presses used to get it there and the fewest that
+// would have done.
+#[derive(Clone, Copy)]
+pub struct Placed {
+  pub piece: Tetrimino,
+  pub presses: u32,
+  pub needed: u32,
+}
+
+impl Placed {
+  pub fn is_fault(&self) -> bool {
+    self.needed < self.presses
+  }
+}
+
+fn is_press(input: Input) -> bool {
+  matches!(input, Input::Left | Input::Right | Input::Rotate)
+}
+
+// The fewest presses of left, right and rotate locking `piece` on the
+// cases of `target`. Soft drops don't count, the key is held down.
+pub fn needed_presses(
+  game_map: &[Line],
+  piece: Tetrimino,
+  target: &Tetrimino
+) -> Option<u32> {
+  movegen::path_to(game_map, piece, target).map(|inputs| {
+    inputs.into_iter().filter(|input| is_press(*input)).count() as u32
+  })
+}
+
+// Counts the finesse faults of a game: pieces locked with more presses than
+// needed. Only the presses moving the piece count, pushing it against a wall
+// is harmless. A held piece starts over when it comes back.
+pub struct Finesse {
+  pub faults: u32,
+  pub pieces: u32,
+  presses: u32,
+  tracked_piece: u32,
+  // The grid and the piece when it entered.
+  start: Option<(Vec<Line>, Tetrimino)>,
+}
+
+impl Default for Finesse {
+  fn default() -> Finesse {
+    Finesse::new()
+  }
+}
+
+impl Finesse {
+  pub fn new() -> Finesse {
+    Finesse {
+      faults: 0,
+      pieces: 0,
+      presses: 0,
+      tracked_piece: 0,
+      start: None,
+    }
+  }
+
+  // Steps `tetris` with `inputs`. Returns the piece locked during the frame,
+  // if any.
+  pub fn step(&mut self, tetris: &mut Tetris, inputs: &[Input])
+    -> Option<Placed>
+  {
+    // The frame starts with gravity and a new piece, then the inputs move
+    // the piece. A copy of the game tells where the inputs begin.
+    let mut before = tetris.clone();
+    before.step(&[]);
+    let mut placed = None;
+    if self.start.is_some() && (
+      before.current_piece.is_none() || before.nb_pieces != self.tracked_piece
+    ) {
+      placed = self.judge(before.last_locked);
+    }
+    let mut moved = match before.current_piece {
+      Some(piece) => piece,
+      None => {
+        tetris.step(inputs);
+        return placed;
+      }
+    };
+    if self.tracked_piece != before.nb_pieces {
+      self.tracked_piece = before.nb_pieces;
+      self.presses = 0;
+      self.start = Some((before.game_map.clone(), moved));
+    }
+    for input in inputs {
+      match *input {
+        Input::Hold if before.can_hold => {
+          self.start = None;
+          break
+        }
+        Input::HardDrop => break,
+        input if is_press(input) => {
+          if let Some(piece) = movegen::apply(&before.game_map, &moved, input)
+          {
+            moved = piece;
+            self.presses += 1;
+          }
+        }
+        _ => {}
+      }
+    }
+    tetris.step(inputs);
+    if self.start.is_some() && tetris.current_piece.is_none() {
+      placed = self.judge(tetris.last_locked).or(placed);
+    }
+    placed
+  }
+
+  fn judge(&mut self, locked: Option<Tetrimino>) -> Option<Placed> {
+    let (game_map, start) = self.start.take()?;
+    let locked = locked?;
+    let needed = needed_presses(&game_map, start, &locked)
+      .unwrap_or(self.presses);
+    let placed = Placed {
+      piece: locked,
+      presses: self.presses,
+      needed,
+    };
+    self.pieces += 1;
+    if placed.is_fault() {
+      self.faults += 1;
+    }
+    Some(placed)
+  }
+}
+
+#[derive(Clone, Copy)]
+pub enum Outcome {
+  Perfect,
+  Fault(Placed),
+  // The piece went somewhere else.
+  Missed,
+}
+
+// Finesse practice: a piece and where to put it on an empty grid, again and
+// again, with no more presses than needed. Only the places a hard drop gets
+// to are asked for.
+pub struct Drill {
+  pub tetris: Tetris,
+  pub target: Placement,
+  pub attempts: u32,
+  pub perfect: u32,
+  pub streak: u32,
+  pub last: Option<Outcome>,
+  finesse: Finesse,
+  rng: Rng,
+}
+
+impl Drill {
+  pub fn new(seed: u64) -> Drill {
+    let mut rng = Rng::new(seed);
+    let (tetris, target) = Drill::next_target(&mut rng);
+    Drill {
+      tetris,
+      target,
+      attempts: 0,
+      perfect: 0,
+      streak: 0,
+      last: None,
+      finesse: Finesse::new(),
+      rng,
+    }
+  }
+
+  fn next_target(rng: &mut Rng) -> (Tetris, Placement) {
+    let color = rng.gen_range(7) as u8 + 1;
+    let piece = Tetrimino::from_color(color).expect("No such piece");
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    let mut placements = movegen::placements(&game_map, piece).into_iter()
+      .filter(|placement| !placement.inputs.contains(&Input::SoftDrop))
+      .collect::<Vec<_>>();
+    let index = rng.gen_range(placements.len() as u32) as usize;
+    let target = placements.swap_remove(index);
+    let seed = rng.next_u32() as u64;
+    (Tetris::with_board(seed, game_map, &[piece]), target)
+  }
+
+  // Plays a frame, the next target comes once the piece is locked. The hold
+  // is off.
+  pub fn step(&mut self, inputs: &[Input]) {
+    let inputs = inputs.iter().cloned()
+      .filter(|input| Input::Hold != *input)
+      .collect::<Vec<_>>();
+    let placed = match self.finesse.step(&mut self.tetris, &inputs) {
+      Some(placed) => placed,
+      None => return,
+    };
+    let mut target = self.target.cells();
+    target.sort();
+    let mut cells = movegen::cells(&placed.piece);
+    cells.sort();
+    let outcome = if target != cells {
+      Outcome::Missed
+    } else if placed.is_fault() {
+      Outcome::Fault(placed)
+    } else {
+      Outcome::Perfect
+    };
+    self.attempts += 1;
+    match outcome {
+      Outcome::Perfect => {
+        self.perfect += 1;
+        self.streak += 1;
+      }
+      _ => self.streak = 0,
+    }
+    self.last = Some(outcome);
+    let (tetris, target) = Drill::next_target(&mut self.rng);
+    self.tetris = tetris;
+    self.target = target;
+    self.finesse = Finesse::new();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{needed_presses, Drill, Finesse, Outcome};
+  use game::{Input, Tetris, HEIGHT, WIDTH};
+  use tetrimino::{TetriminoGenerator, TetriminoO, TetriminoT};
+
+  // Plays the inputs a frame each, then lets the piece lock.
+  fn play(finesse: &mut Finesse, tetris: &mut Tetris, inputs: &[Input]) {
+    for input in inputs {
+      finesse.step(tetris, &[*input]);
+    }
+    finesse.step(tetris, &[Input::HardDrop]);
+    finesse.step(tetris, &[]);
+  }
+
+  #[test]
+  fn counts_the_presses_needed() {
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    let mut target = TetriminoO::new();
+    target.x = 0;
+    target.y = HEIGHT - 2;
+    assert_eq!(Some(5), needed_presses(&game_map, TetriminoO::new(), &target));
+    target.x = 5;
+    assert_eq!(Some(0), needed_presses(&game_map, TetriminoO::new(), &target));
+    let mut upside_down = TetriminoT::new();
+    upside_down.current_state = 2;
+    upside_down.y = HEIGHT - 2;
+    let needed = needed_presses(&game_map, TetriminoT::new(), &upside_down);
+    assert_eq!(Some(2), needed);
+    // Pieces don't lock in the air.
+    target.y = 0;
+    assert_eq!(None, needed_presses(&game_map, TetriminoO::new(), &target));
+  }
+
+  #[test]
+  fn counts_the_pieces_locked_with_extra_presses() {
+    let pieces = [TetriminoO::new(); 4];
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    let mut tetris = Tetris::with_board(1, game_map, &pieces);
+    let mut finesse = Finesse::new();
+    play(&mut finesse, &mut tetris, &[Input::Left; 5]);
+    assert_eq!((1, 0), (finesse.pieces, finesse.faults));
+    // Pushing against the wall doesn't move the piece, it's no fault.
+    play(&mut finesse, &mut tetris, &[Input::Left; 8]);
+    assert_eq!((2, 0), (finesse.pieces, finesse.faults));
+    play(&mut finesse, &mut tetris, &[Input::Left, Input::Right]);
+    assert_eq!((3, 1), (finesse.pieces, finesse.faults));
+  }
+
+  #[test]
+  fn held_pieces_start_over() {
+    let pieces = [TetriminoO::new(), TetriminoT::new(), TetriminoO::new()];
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    let mut tetris = Tetris::with_board(1, game_map, &pieces);
+    let mut finesse = Finesse::new();
+    finesse.step(&mut tetris, &[Input::Left]);
+    finesse.step(&mut tetris, &[Input::Right]);
+    finesse.step(&mut tetris, &[Input::Hold]);
+    play(&mut finesse, &mut tetris, &[]);
+    assert_eq!((1, 0), (finesse.pieces, finesse.faults));
+    // The held piece comes back, the presses before the hold are forgotten.
+    finesse.step(&mut tetris, &[Input::Hold]);
+    play(&mut finesse, &mut tetris, &[Input::Left]);
+    assert_eq!((2, 0), (finesse.pieces, finesse.faults));
+  }
+
+  #[test]
+  fn drill_outcomes() {
+    let mut drill = Drill::new(5);
+    for input in drill.target.inputs.clone() {
+      drill.step(&[input]);
+    }
+    drill.step(&[]);
+    assert!(matches!(drill.last, Some(Outcome::Perfect)));
+    assert_eq!((1, 1, 1), (drill.attempts, drill.perfect, drill.streak));
+
+    // Away and back from the middle is always a fault.
+    let inputs = drill.target.inputs.clone();
+    for input in [Input::Right, Input::Left].iter().chain(&inputs) {
+      drill.step(&[*input]);
+    }
+    drill.step(&[]);
+    assert!(matches!(drill.last, Some(Outcome::Fault(_))));
+    assert_eq!((2, 1, 0), (drill.attempts, drill.perfect, drill.streak));
+
+    // A piece dropped a column off the target misses it.
+    let shift = if drill.target.piece.x < 3 { Input::Right } else {
+      Input::Left
+    };
+    let inputs = drill.target.inputs.clone();
+    for input in Some(shift).iter().chain(&inputs) {
+      drill.step(&[*input]);
+    }
+    drill.step(&[]);
+    assert!(matches!(drill.last, Some(Outcome::Missed)));
+    assert_eq!((3, 1, 0), (drill.attempts, drill.perfect, drill.streak));
+  }
+}
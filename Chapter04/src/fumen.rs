@@ -0,0 +1,710 @@
+use std::io::{self, ErrorKind};
+
+use game::{Line, Tetris, GARBAGE, HEIGHT, WIDTH};
+use movegen;
+use replay::Recording;
+use tetrimino::{color_of_letter, piece_letter, Tetrimino};
+
+// Fumen strings share boards as pages, each one a grid, a piece and a
+// comment:
+//
+//   v115@vhAAgH                         the version then the pages
+//
+// The pages are written with the 64 characters of `DIGITS`, numbers taking
+// a few of them, lowest digit first. A page starts with the changes of its
+// grid from the grid the page before left, as runs of cases, then the piece
+// and the flags of the page, then the comment when it changed.
+pub const VERSION: &str = "v115@";
+const DIGITS: &str =
+  "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+// The characters of the comments, 4 to a number of 5 digits.
+const COMMENT_CHARS: &str = " !\"#$%&'()*+,-./0123456789:;<=>?@\
+  ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const COMMENT_BASE: u32 = 96;
+const MAX_COMMENT: usize = 4095;
+// Fumen grids are 23 rows high, with a row of garbage under them.
+pub const FIELD_HEIGHT: usize = 23;
+const FIELD_CASES: usize = (FIELD_HEIGHT + 1) * WIDTH;
+// The pieces in fumen order, from 1. Garbage is 8.
+const FUMEN_PIECES: &str = "ILOZTJS";
+const FUMEN_GARBAGE: u8 = 8;
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn to_fumen(color: u8) -> u8 {
+  match color {
+    0 => 0,
+    color => FUMEN_PIECES.find(piece_letter(color))
+      .map(|index| index as u8 + 1)
+      .unwrap_or(FUMEN_GARBAGE),
+  }
+}
+
+fn from_fumen(piece: u8) -> u8 {
+  match piece {
+    0 => 0,
+    piece => FUMEN_PIECES.chars().nth(piece as usize - 1)
+      .and_then(color_of_letter)
+      .unwrap_or(GARBAGE),
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+  Spawn,
+  Right,
+  Reverse,
+  Left,
+}
+
+impl Rotation {
+  fn encode(self) -> u32 {
+    match self {
+      Rotation::Reverse => 0,
+      Rotation::Right => 1,
+      Rotation::Spawn => 2,
+      Rotation::Left => 3,
+    }
+  }
+
+  fn decode(value: u32) -> Rotation {
+    match value % 4 {
+      0 => Rotation::Reverse,
+      1 => Rotation::Right,
+      2 => Rotation::Spawn,
+      _ => Rotation::Left,
+    }
+  }
+}
+
+// A piece of a page, around the case (x, y) with y going up from the bottom
+// row of the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Operation {
+  pub color: u8,
+  pub rotation: Rotation,
+  pub x: isize,
+  pub y: isize,
+}
+
+// Where the cases of a piece are around its center, y going up. I, O, S
+// and Z have the same cases upside down, and O in every direction.
+fn offsets(letter: &str, rotation: Rotation) -> [(isize, isize); 4] {
+  let flat = Rotation::Spawn == rotation || Rotation::Reverse == rotation;
+  let spawn = match letter {
+    "I" if flat => return [(-1, 0), (0, 0), (1, 0), (2, 0)],
+    "I" => return [(0, 1), (0, 0), (0, -1), (0, -2)],
+    "O" => return [(0, 0), (1, 0), (0, -1), (1, -1)],
+    "S" if flat => return [(-1, -1), (0, -1), (0, 0), (1, 0)],
+    "S" => return [(-1, 1), (-1, 0), (0, 0), (0, -1)],
+    "Z" if flat => return [(-1, 0), (0, 0), (0, -1), (1, -1)],
+    "Z" => return [(0, -1), (0, 0), (1, 0), (1, 1)],
+    "L" => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+    "J" => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+    _ => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+  };
+  let mut offsets = spawn;
+  for (offset, &(x, y)) in offsets.iter_mut().zip(spawn.iter()) {
+    *offset = match rotation {
+      Rotation::Spawn => (x, y),
+      Rotation::Right => (y, -x),
+      Rotation::Reverse => (-x, -y),
+      Rotation::Left => (-y, x),
+    };
+  }
+  offsets
+}
+
+// Cases in the same shape, wherever they are.
+fn normalized(cells: &[(isize, isize)]) -> Vec<(isize, isize)> {
+  let min_x = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+  let min_y = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+  let mut cells = cells.iter().map(|&(x, y)| (x - min_x, y - min_y))
+    .collect::<Vec<_>>();
+  cells.sort();
+  cells
+}
+
+impl Operation {
+  // The cases of the piece as (x, row), the rows of the fumen grid counting
+  // from the top one.
+  pub fn cells(&self) -> Vec<(isize, isize)> {
+    offsets(piece_letter(self.color), self.rotation).iter()
+      .map(|&(x, y)| {
+        (self.x + x, FIELD_HEIGHT as isize - 1 - (self.y + y))
+      })
+      .collect()
+  }
+
+  // The piece of the game, in the bottom rows of the fumen grid.
+  pub fn of_piece(piece: &Tetrimino) -> Option<Operation> {
+    let top = (FIELD_HEIGHT - HEIGHT) as isize;
+    let mut cells = movegen::cells(piece).into_iter()
+      .map(|(x, y)| (x, top + y as isize))
+      .collect::<Vec<_>>();
+    cells.sort();
+    let color = piece.color();
+    let rotations = [
+      Rotation::Spawn, Rotation::Right, Rotation::Reverse, Rotation::Left,
+    ];
+    for rotation in rotations.iter() {
+      for &(offset_x, offset_y) in offsets(piece_letter(color), *rotation)
+        .iter()
+      {
+        let operation = Operation {
+          color,
+          rotation: *rotation,
+          x: cells[0].0 - offset_x,
+          y: FIELD_HEIGHT as isize - 1 - cells[0].1 - offset_y,
+        };
+        let mut found = operation.cells();
+        found.sort();
+        if cells == found {
+          return Some(operation);
+        }
+      }
+    }
+    None
+  }
+
+  // The piece in the grid of the game, if it is in its rows.
+  pub fn piece(&self) -> Option<Tetrimino> {
+    let top = (FIELD_HEIGHT - HEIGHT) as isize;
+    let cells = self.cells().into_iter()
+      .map(|(x, row)| (x, row - top))
+      .collect::<Vec<_>>();
+    if cells.iter().any(|&(x, y)| {
+      x < 0 || WIDTH as isize <= x || y < 0 || HEIGHT as isize <= y
+    }) {
+      return None;
+    }
+    let mut piece = Tetrimino::from_color(self.color)?;
+    let shape = normalized(&cells);
+    for state in 0..piece.states.len() {
+      piece.current_state = state as u8;
+      piece.x = 0;
+      piece.y = 0;
+      let state_cells = movegen::cells(&piece).into_iter()
+        .map(|(x, y)| (x, y as isize))
+        .collect::<Vec<_>>();
+      if shape != normalized(&state_cells) {
+        continue
+      }
+      let min_x = |cells: &[(isize, isize)]| {
+        cells.iter().map(|&(x, _)| x).min().unwrap_or(0)
+      };
+      let min_y = |cells: &[(isize, isize)]| {
+        cells.iter().map(|&(_, y)| y).min().unwrap_or(0)
+      };
+      piece.x = min_x(&cells) - min_x(&state_cells);
+      let y = min_y(&cells) - min_y(&state_cells);
+      if y < 0 {
+        return None;
+      }
+      piece.y = y as usize;
+      return Some(piece);
+    }
+    None
+  }
+
+  fn position(&self) -> u32 {
+    let row = FIELD_HEIGHT as isize - 1 - self.y;
+    (self.x + row * WIDTH as isize) as u32
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+  // The rows of the fumen grid, the top one first, with the colors of the
+  // game.
+  pub field: Vec<Line>,
+  // The row under the grid, pushed up by `rise`.
+  pub garbage: Line,
+  pub operation: Option<Operation>,
+  pub comment: String,
+  // The piece is part of the grid of the next page, which is then cleared,
+  // raised and mirrored as the flags say.
+  pub lock: bool,
+  pub rise: bool,
+  pub mirror: bool,
+}
+
+impl Page {
+  // A page of a grid of the game, in the bottom rows.
+  pub fn new(game_map: &[Line]) -> Page {
+    let mut field = vec![[0; WIDTH]; FIELD_HEIGHT - game_map.len()];
+    field.extend_from_slice(game_map);
+    Page {
+      field,
+      garbage: [0; WIDTH],
+      operation: None,
+      comment: String::new(),
+      lock: true,
+      rise: false,
+      mirror: false,
+    }
+  }
+
+  // The grid of `tetris`, with its piece.
+  pub fn of_game(tetris: &Tetris) -> Page {
+    let mut page = Page::new(&tetris.game_map);
+    page.operation = tetris.current_piece.as_ref()
+      .and_then(Operation::of_piece);
+    page.lock = false;
+    page
+  }
+
+  // The grid in the rows of the game. The cases above them have to be
+  // empty, the row of garbage is left out.
+  pub fn game_map(&self) -> io::Result<Vec<Line>> {
+    let top = FIELD_HEIGHT - HEIGHT;
+    if self.field[..top].iter().any(|line| line.iter().any(|case| 0 != *case))
+    {
+      return Err(invalid(format!("blocks above the {} rows", HEIGHT)));
+    }
+    Ok(self.field[top..].to_vec())
+  }
+
+  // A game from the grid of the page, dealing the pieces of its quiz first,
+  // or its piece.
+  pub fn to_game(&self, seed: u64) -> io::Result<Tetris> {
+    let (held, pieces) = match self.quiz() {
+      Some(quiz) => quiz,
+      None => (None, self.operation.iter().map(|op| op.color).collect()),
+    };
+    let pieces = pieces.into_iter().filter_map(Tetrimino::from_color)
+      .collect::<Vec<_>>();
+    let mut tetris = Tetris::with_board(seed, self.game_map()?, &pieces);
+    tetris.held_piece = held.and_then(Tetrimino::from_color);
+    Ok(tetris)
+  }
+
+  // The pieces of a quiz comment, `#Q=[hold](current)next`, as colors.
+  pub fn quiz(&self) -> Option<(Option<u8>, Vec<u8>)> {
+    let quiz = self.comment.trim();
+    if !quiz.starts_with("#Q=") {
+      return None;
+    }
+    let quiz = &quiz[3..];
+    let hold_end = quiz.find(']')?;
+    let hold = quiz.get(1..hold_end)?.chars().next()
+      .and_then(color_of_letter);
+    let pieces = quiz[hold_end + 1..].chars()
+      .filter(|letter| '(' != *letter && ')' != *letter)
+      .map(color_of_letter)
+      .collect::<Option<Vec<_>>>()?;
+    Some((hold, pieces))
+  }
+
+  // The grid and the row of garbage, in fumen pieces.
+  fn cases(&self) -> Vec<u8> {
+    self.field.iter().chain(Some(&self.garbage))
+      .flat_map(|line| line.iter().map(|case| to_fumen(*case)))
+      .collect()
+  }
+
+  fn set_cases(&mut self, cases: &[u8]) {
+    for (index, case) in cases.iter().enumerate() {
+      let color = from_fumen(*case);
+      match index / WIDTH {
+        FIELD_HEIGHT => self.garbage[index % WIDTH] = color,
+        row => self.field[row][index % WIDTH] = color,
+      }
+    }
+  }
+
+  // The grid the next page starts from.
+  fn next_field(&self) -> Page {
+    let mut next = self.clone();
+    if !self.lock {
+      return next;
+    }
+    for (x, row) in self.operation.iter().flat_map(|op| op.cells()) {
+      if 0 <= x && x < WIDTH as isize && 0 <= row {
+        match row as usize {
+          FIELD_HEIGHT => next.garbage[x as usize] = self.operation_color(),
+          row if row < FIELD_HEIGHT => {
+            next.field[row][x as usize] = self.operation_color();
+          }
+          _ => {}
+        }
+      }
+    }
+    next.field.retain(|line| line.contains(&0));
+    while next.field.len() < FIELD_HEIGHT {
+      next.field.insert(0, [0; WIDTH]);
+    }
+    if self.rise {
+      next.field.remove(0);
+      next.field.push(next.garbage);
+      next.garbage = [0; WIDTH];
+    }
+    if self.mirror {
+      for line in next.field.iter_mut() {
+        line.reverse();
+      }
+    }
+    next
+  }
+
+  fn operation_color(&self) -> u8 {
+    self.operation.map(|op| op.color).unwrap_or(0)
+  }
+}
+
+struct Digits {
+  values: Vec<u32>,
+  index: usize,
+}
+
+impl Digits {
+  fn poll(&mut self, nb: usize) -> io::Result<u32> {
+    if self.values.len() < self.index + nb {
+      return Err(invalid("the data ends too early".to_owned()));
+    }
+    let mut value = 0;
+    for digit in self.values[self.index..self.index + nb].iter().rev() {
+      value = value * 64 + digit;
+    }
+    self.index += nb;
+    Ok(value)
+  }
+}
+
+fn push(values: &mut Vec<u32>, mut value: u32, nb: usize) {
+  for _ in 0..nb {
+    values.push(value % 64);
+    value /= 64;
+  }
+}
+
+// Comments are written like JavaScript's escape does.
+fn escape(comment: &str) -> String {
+  let mut escaped = String::new();
+  for unit in comment.encode_utf16() {
+    match unit as u8 as char {
+      letter if unit < 128 && (letter.is_ascii_alphanumeric() ||
+        "@*_+-./".contains(letter)) => escaped.push(letter),
+      _ if unit < 256 => escaped.push_str(&format!("%{:02X}", unit)),
+      _ => escaped.push_str(&format!("%u{:04X}", unit)),
+    }
+  }
+  escaped
+}
+
+fn unescape(escaped: &str) -> String {
+  let mut units = Vec::new();
+  let mut index = 0;
+  while index < escaped.len() {
+    let rest = &escaped[index..];
+    let code = if rest.starts_with("%u") {
+      rest.get(2..6).and_then(|hex| u16::from_str_radix(hex, 16).ok())
+        .map(|unit| (unit, 6))
+    } else if rest.starts_with('%') {
+      rest.get(1..3).and_then(|hex| u16::from_str_radix(hex, 16).ok())
+        .map(|unit| (unit, 3))
+    } else {
+      None
+    };
+    let (unit, length) = code.unwrap_or((rest.as_bytes()[0] as u16, 1));
+    units.push(unit);
+    index += length;
+  }
+  String::from_utf16_lossy(&units)
+}
+
+// Writes the changes from `previous` to `page`. Nothing changed when the
+// whole grid is a single run of unchanged cases.
+fn encode_field(previous: &[u8], page: &[u8]) -> (bool, Vec<u32>) {
+  let mut values = Vec::new();
+  let diffs = previous.iter().zip(page.iter())
+    .map(|(previous, case)| 8 + *case as u32 - *previous as u32)
+    .collect::<Vec<_>>();
+  let mut start = 0;
+  while start < diffs.len() {
+    let length = diffs[start..].iter()
+      .take_while(|diff| diffs[start] == **diff)
+      .count();
+    push(&mut values, diffs[start] * FIELD_CASES as u32 + length as u32 - 1, 2);
+    start += length;
+  }
+  (diffs.iter().any(|diff| 8 != *diff), values)
+}
+
+pub fn encode(pages: &[Page]) -> String {
+  let mut values = Vec::new();
+  let mut previous = Page::new(&[]);
+  // Where the number of pages repeating the grid is written.
+  let mut repeat: Option<usize> = None;
+  for (nb, page) in pages.iter().enumerate() {
+    let (changed, field) = encode_field(&previous.cases(), &page.cases());
+    match repeat {
+      Some(index) if !changed && values[index] < 63 => values[index] += 1,
+      _ if !changed => {
+        values.extend(field);
+        values.push(0);
+        repeat = Some(values.len() - 1);
+      }
+      _ => {
+        values.extend(field);
+        repeat = None;
+      }
+    }
+
+    let comment = if 0 == nb {
+      !page.comment.is_empty()
+    } else {
+      page.comment != previous.comment
+    };
+    let mut action = !page.lock as u32;
+    for flag in [comment, true, page.mirror, page.rise].iter() {
+      action = action * 2 + *flag as u32;
+    }
+    let (position, rotation, piece) = match page.operation {
+      Some(op) => (op.position(), op.rotation.encode(), to_fumen(op.color)),
+      None => (0, 0, 0),
+    };
+    action = (action * FIELD_CASES as u32 + position) * 4 + rotation;
+    push(&mut values, action * 8 + piece as u32, 3);
+
+    if comment {
+      let mut comment = page.comment.clone();
+      while MAX_COMMENT < escape(&comment).len() {
+        comment.pop();
+      }
+      let escaped = escape(&comment)
+        .chars()
+        .map(|letter| COMMENT_CHARS.find(letter).unwrap_or(0) as u32)
+        .collect::<Vec<_>>();
+      push(&mut values, escaped.len() as u32, 2);
+      for chunk in escaped.chunks(4) {
+        let value = chunk.iter().rev()
+          .fold(0, |value, letter| value * COMMENT_BASE + letter);
+        push(&mut values, value, 5);
+      }
+    }
+    previous = page.next_field();
+  }
+  let data = values.iter()
+    .map(|value| DIGITS.as_bytes()[*value as usize] as char)
+    .collect::<String>();
+  format!("{}{}", VERSION, data)
+}
+
+// Reads the pages of a fumen string, or of an address ending with one.
+pub fn decode(fumen: &str) -> io::Result<Vec<Page>> {
+  let data = match fumen.find(VERSION) {
+    Some(index) => &fumen[index + VERSION.len()..],
+    None => return Err(invalid("not a v115 fumen".to_owned())),
+  };
+  let values = data.chars()
+    .filter(|letter| '?' != *letter && !letter.is_whitespace())
+    .map(|letter| DIGITS.find(letter).map(|value| value as u32))
+    .collect::<Option<Vec<_>>>()
+    .ok_or_else(|| invalid("bad character in the fumen".to_owned()))?;
+  let mut digits = Digits { values, index: 0 };
+
+  let mut pages = Vec::new();
+  let mut previous = Page::new(&[]);
+  let mut repeat = 0;
+  while digits.index < digits.values.len() {
+    let mut page = previous.clone();
+    if 0 < repeat {
+      repeat -= 1;
+    } else {
+      let before = previous.cases();
+      let mut cases = Vec::with_capacity(FIELD_CASES);
+      let mut unchanged = false;
+      while cases.len() < FIELD_CASES {
+        let value = digits.poll(2)?;
+        let diff = value / FIELD_CASES as u32;
+        let length = value as usize % FIELD_CASES + 1;
+        unchanged = 8 == diff && FIELD_CASES == length;
+        if FIELD_CASES < cases.len() + length {
+          return Err(invalid("the grid is too big".to_owned()));
+        }
+        let run = before.iter().enumerate().skip(cases.len()).take(length);
+        for (index, case) in run {
+          let case = *case as u32 + diff;
+          let highest = 8 + FUMEN_GARBAGE as u32;
+          if case < 8 || highest < case {
+            return Err(invalid(format!("bad case at {}", index)));
+          }
+          cases.push((case - 8) as u8);
+        }
+      }
+      page.set_cases(&cases);
+      if unchanged {
+        repeat = digits.poll(1)?;
+      }
+    }
+
+    let mut action = digits.poll(3)?;
+    let piece = (action % 8) as u8;
+    action /= 8;
+    let rotation = Rotation::decode(action % 4);
+    action /= 4;
+    let position = (action % FIELD_CASES as u32) as isize;
+    action /= FIELD_CASES as u32;
+    page.rise = 1 == action % 2;
+    page.mirror = 1 == (action / 2) % 2;
+    let comment = 1 == (action / 8) % 2;
+    page.lock = 0 == (action / 16) % 2;
+    page.operation = match piece {
+      0 | FUMEN_GARBAGE => None,
+      piece => Some(Operation {
+        color: from_fumen(piece),
+        rotation,
+        x: position % WIDTH as isize,
+        y: FIELD_HEIGHT as isize - 1 - position / WIDTH as isize,
+      }),
+    };
+    if comment {
+      let length = digits.poll(2)? as usize;
+      let mut escaped = String::new();
+      for _ in 0..length.div_ceil(4) {
+        let mut value = digits.poll(5)?;
+        for _ in 0..4 {
+          let letter = COMMENT_CHARS.as_bytes()[(value % COMMENT_BASE) as usize
+            % COMMENT_CHARS.len()];
+          escaped.push(letter as char);
+          value /= COMMENT_BASE;
+        }
+      }
+      escaped.truncate(length);
+      page.comment = unescape(&escaped);
+    }
+    previous = page.next_field();
+    pages.push(page);
+  }
+  if pages.is_empty() {
+    return Err(invalid("no page".to_owned()));
+  }
+  Ok(pages)
+}
+
+// A page for every piece locked in a recorded game, the grid before it with
+// the piece where it went.
+pub fn game_pages(recording: &Recording) -> Vec<Page> {
+  let mut tetris = recording.initial();
+  let mut pages = Vec::new();
+  let locked = |tetris: &Tetris| tetris.last_locked.as_ref()
+    .map(|piece| (piece.color(), piece.x, piece.y, piece.current_state));
+  for inputs in &recording.frames {
+    let game_map = tetris.game_map.clone();
+    let before = locked(&tetris);
+    tetris.step(inputs);
+    if before != locked(&tetris) || game_map != tetris.game_map {
+      let mut page = Page::new(&game_map);
+      page.operation = tetris.last_locked.as_ref()
+        .and_then(Operation::of_piece);
+      pages.push(page);
+    }
+  }
+  if pages.is_empty() {
+    pages.push(Page::new(&tetris.game_map));
+  }
+  pages[0].comment = format!("{}, seed {}", recording.name, recording.seed);
+  pages
+}
+
+#[cfg(test)]
+mod tests {
+  use game::{GARBAGE, WIDTH};
+  use movegen;
+  use tetrimino::Tetrimino;
+  use super::{decode, encode, Operation, Page, Rotation, FIELD_HEIGHT};
+
+  const ROTATIONS: [Rotation; 4] =
+    [Rotation::Spawn, Rotation::Right, Rotation::Reverse, Rotation::Left];
+
+  #[test]
+  fn pages_round_trip() {
+    let mut game_map = vec![[0; WIDTH]; 4];
+    game_map[3] = [GARBAGE, 0, 3, 3, 0, 0, 5, 5, 5, 0];
+    game_map[2][0] = 7;
+    let mut pages = Vec::new();
+    let comments = [
+      "", "#Q=[I](T)SZ", "100% é", "テトリス 🧱", "#Q=[I]",
+    ];
+    for color in 1..8 {
+      for (nb, rotation) in ROTATIONS.iter().enumerate() {
+        let mut page = Page::new(&game_map);
+        page.garbage = [GARBAGE, GARBAGE, 0, GARBAGE, 0, 0, 0, 0, 0, GARBAGE];
+        page.operation = Some(Operation {
+          color,
+          rotation: *rotation,
+          x: 4,
+          y: 8,
+        });
+        page.comment = comments[(color as usize + nb) % comments.len()]
+          .to_owned();
+        page.lock = 0 != nb % 2;
+        page.rise = 1 == nb;
+        page.mirror = 3 == nb;
+        pages.push(page);
+      }
+    }
+    // Each grid is written as changes from the one the page before left,
+    // locked, raised and mirrored.
+    assert_eq!(pages, decode(&encode(&pages)).unwrap());
+  }
+
+  #[test]
+  fn pieces_of_the_game_round_trip() {
+    for color in 1..8 {
+      let mut piece = Tetrimino::from_color(color).unwrap();
+      for state in 0..piece.states.len() {
+        piece.current_state = state as u8;
+        piece.y = 5;
+        let operation = Operation::of_piece(&piece).unwrap();
+        let back = operation.piece().unwrap();
+        let mut cells = movegen::cells(&piece);
+        let mut back_cells = movegen::cells(&back);
+        cells.sort();
+        back_cells.sort();
+        assert_eq!(cells, back_cells, "piece {} state {}", color, state);
+      }
+    }
+  }
+
+  #[test]
+  fn decodes_fumen_strings() {
+    // Four rows of six blocks, from the tetris-fumen documentation.
+    let fumen = "v115@9gF8DeF8DeF8DeF8NeAgH";
+    let pages = decode(fumen).unwrap();
+    assert_eq!(1, pages.len());
+    let page = &pages[0];
+    for (row, line) in page.field.iter().enumerate() {
+      let expected = if FIELD_HEIGHT - 4 <= row {
+        [GARBAGE, GARBAGE, GARBAGE, GARBAGE, GARBAGE, GARBAGE, 0, 0, 0, 0]
+      } else {
+        [0; WIDTH]
+      };
+      assert_eq!(expected, *line, "row {}", row);
+    }
+    assert_eq!([0; WIDTH], page.garbage);
+    assert_eq!(None, page.operation);
+    assert!(page.lock && !page.rise && !page.mirror);
+    assert_eq!(fumen, encode(&pages));
+
+    // An empty grid, and the address of a fumen page.
+    let pages = decode("https://fumen.zui.jp/?v115@vhAAgH").unwrap();
+    assert_eq!(vec![Page::new(&[])], pages);
+    assert!(decode("v115@vhAAg").is_err());
+    assert!(decode("v114@vhAAgH").is_err());
+  }
+
+  #[test]
+  fn repeated_grids_are_counted() {
+    let pages = vec![Page::new(&[]); 3];
+    assert_eq!("v115@vhCAgHAgHAgH", encode(&pages));
+    assert_eq!(pages, decode("v115@vhCAgHAgHAgH").unwrap());
+    // A count goes up to 63 pages, then a new one starts.
+    let mut pages = vec![Page::new(&[]); 70];
+    pages[66].comment = "after the repeats".to_owned();
+    assert_eq!(pages, decode(&encode(&pages)).unwrap());
+  }
+}
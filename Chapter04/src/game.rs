@@ -0,0 +1,639 @@
+use std::collections::VecDeque;
+
+use rand;
+
+use rng::Rng;
+use tetrimino::{
+  Tetrimino, TetriminoGenerator,
+  TetriminoI, TetriminoJ, TetriminoL, TetriminoO,
+  TetriminoS, TetriminoT, TetriminoZ,
+};
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const LEVEL_TIMES: [u32; 10] =
+  [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
+pub const LEVEL_LINES: [u32; 10] =
+  [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
+pub const GARBAGE: u8 = 8;
+pub const WIDTH: usize = 10;
+pub const HEIGHT: usize = 16;
+
+pub type Line = [u8; WIDTH];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+  Left,
+  Right,
+  SoftDrop,
+  Rotate,
+  HardDrop,
+  Hold,
+  // Lines of garbage sent by an opponent, queued until the next lock.
+  Garbage(u32),
+}
+
+impl Input {
+  pub fn encode(&self) -> String {
+    match *self {
+      Input::Left => "L".to_owned(),
+      Input::Right => "R".to_owned(),
+      Input::SoftDrop => "D".to_owned(),
+      Input::Rotate => "U".to_owned(),
+      Input::HardDrop => "H".to_owned(),
+      Input::Hold => "C".to_owned(),
+      Input::Garbage(lines) => format!("G{}", lines),
+    }
+  }
+
+  pub fn decode(token: &str) -> Option<Input> {
+    match token {
+      "L" => Some(Input::Left),
+      "R" => Some(Input::Right),
+      "D" => Some(Input::SoftDrop),
+      "U" => Some(Input::Rotate),
+      "H" => Some(Input::HardDrop),
+      "C" => Some(Input::Hold),
+      _ if token.starts_with('G') => {
+        token[1..].parse::<u32>().ok().map(Input::Garbage)
+      }
+      _ => None,
+    }
+  }
+}
+
+// What the engine keeps to itself, for saving a game in the middle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Internals {
+  pub rotated: bool,
+  pub gravity_frames: u32,
+  pub piece_rng: u64,
+  pub garbage_rng: u64,
+  pub previous_piece: u32,
+}
+
+#[derive(Clone)]
+pub struct Tetris {
+  pub seed: u64,
+  pub game_map: Vec<Line>,
+  pub current_level: u32,
+  pub score: u32,
+  pub nb_lines: u32,
+  pub current_piece: Option<Tetrimino>,
+  pub next_piece: Option<Tetrimino>,
+  pub held_piece: Option<Tetrimino>,
+  // Only one hold per piece.
+  pub can_hold: bool,
+  pub pending_garbage: u32,
+  pub outgoing_garbage: u32,
+  pub game_over: bool,
+  // Number of frames simulated so far. Frozen once the game is over.
+  pub frame: u32,
+  // Number of pieces that entered the grid.
+  pub nb_pieces: u32,
+  // Dealt before the random ones, for practice.
+  pub scripted_pieces: VecDeque<Tetrimino>,
+  // The piece locked last, where it locked.
+  pub last_locked: Option<Tetrimino>,
+  // Whether pieces are drawn at random once the scripted ones are dealt.
+  // Otherwise the game ends when they run out.
+  pub random_pieces: bool,
+  // Whether the piece locked last was a T-spin: a T whose last move was a
+  // rotation, with three of the corners around its center taken.
+  pub last_t_spin: bool,
+  // The last move of the current piece was a rotation.
+  rotated: bool,
+  gravity_frames: u32,
+  piece_rng: Rng,
+  garbage_rng: Rng,
+  previous_piece: u32,
+}
+
+impl Default for Tetris {
+  fn default() -> Tetris {
+    Tetris::new()
+  }
+}
+
+impl Tetris {
+  pub fn new() -> Tetris {
+    Tetris::with_seed(rand::random::<u64>())
+  }
+
+  // Games started with the same seed get the same pieces and garbage holes.
+  pub fn with_seed(seed: u64) -> Tetris {
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    Tetris {
+      seed,
+      game_map,
+      current_level: 1,
+      score: 0,
+      nb_lines: 0,
+      current_piece: None,
+      next_piece: None,
+      held_piece: None,
+      can_hold: true,
+      pending_garbage: 0,
+      outgoing_garbage: 0,
+      game_over: false,
+      frame: 0,
+      nb_pieces: 0,
+      scripted_pieces: VecDeque::new(),
+      last_locked: None,
+      random_pieces: true,
+      last_t_spin: false,
+      rotated: false,
+      gravity_frames: 0,
+      piece_rng: Rng::new(seed),
+      garbage_rng: Rng::new(!seed),
+      previous_piece: 7,
+    }
+  }
+
+  // A game starting from `game_map`, dealing `pieces` first.
+  pub fn with_board(seed: u64, game_map: Vec<Line>, pieces: &[Tetrimino])
+    -> Tetris
+  {
+    let mut tetris = Tetris::with_seed(seed);
+    tetris.game_map = game_map;
+    tetris.scripted_pieces = pieces.iter().cloned().collect();
+    tetris
+  }
+
+  pub fn internals(&self) -> Internals {
+    Internals {
+      rotated: self.rotated,
+      gravity_frames: self.gravity_frames,
+      piece_rng: self.piece_rng.state(),
+      garbage_rng: self.garbage_rng.state(),
+      previous_piece: self.previous_piece,
+    }
+  }
+
+  // Returns false, leaving the game as it is, if the generators can't be in
+  // those states.
+  pub fn set_internals(&mut self, internals: Internals) -> bool {
+    let rngs = (
+      Rng::from_state(internals.piece_rng),
+      Rng::from_state(internals.garbage_rng),
+    );
+    match rngs {
+      (Some(piece_rng), Some(garbage_rng)) => {
+        self.rotated = internals.rotated;
+        self.gravity_frames = internals.gravity_frames;
+        self.piece_rng = piece_rng;
+        self.garbage_rng = garbage_rng;
+        self.previous_piece = internals.previous_piece;
+        true
+      }
+      _ => false,
+    }
+  }
+
+  fn update_score(&mut self, to_add: u32) {
+    self.score += to_add;
+  }
+
+  fn increase_level(&mut self) {
+    self.current_level += 1;
+  }
+
+  fn increase_line(&mut self) {
+    self.nb_lines += 1;
+    if (self.current_level as usize) < LEVEL_LINES.len() &&
+      LEVEL_LINES[self.current_level as usize - 1] < self.nb_lines
+    {
+      self.increase_level();
+    }
+  }
+
+  fn check_lines(&mut self) -> u32 {
+    let mut lines = 0;
+    let mut y = 0;
+    let mut score_add = 0;
+
+    while y < self.game_map.len() {
+      let mut complete = true;
+
+      for x in &self.game_map[y] {
+        if 0 == *x {
+          complete = false;
+          break
+        }
+      }
+      if complete {
+        score_add += self.current_level;
+        // The row under takes its place, it is checked next.
+        self.game_map.remove(y);
+        lines += 1;
+      } else {
+        y += 1;
+      }
+    }
+    if 4 <= lines {
+      // A "tetris"!
+      score_add += 1000;
+    }
+    self.update_score(score_add);
+    while self.game_map.len() < HEIGHT {
+      self.increase_line();
+      self.game_map.insert(0, [0; WIDTH]);
+    }
+    lines
+  }
+
+  pub fn create_new_tetrimino(&mut self) -> Tetrimino
+  {
+    if let Some(piece) = self.scripted_pieces.pop_front() {
+      return piece;
+    }
+    let mut rand_nb = self.piece_rng.gen_range(7);
+    if self.previous_piece == rand_nb {
+      rand_nb = self.piece_rng.gen_range(7);
+    }
+    self.previous_piece = rand_nb;
+    match rand_nb {
+      0 => TetriminoI::new(),
+      1 => TetriminoJ::new(),
+      2 => TetriminoL::new(),
+      3 => TetriminoO::new(),
+      4 => TetriminoS::new(),
+      5 => TetriminoZ::new(),
+      6 => TetriminoT::new(),
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn make_permanent(&mut self) {
+    self.last_t_spin = self.rotated && self.current_piece.as_ref()
+      .map(|piece| self.is_t_spin(piece))
+      .unwrap_or(false);
+    self.rotated = false;
+    let mut to_add = 0;
+    if let Some(ref mut piece) = self.current_piece {
+      let mut shift_y = 0;
+
+      while shift_y < piece.states[piece.current_state as usize].len() &&
+        piece.y + shift_y < self.game_map.len()
+      {
+        let mut shift_x = 0;
+
+        while shift_x <
+          piece.states[piece.current_state as usize][shift_y].len() &&
+          (piece.x + shift_x as isize) <
+          self.game_map[piece.y + shift_y].len() as isize
+        {
+          if 0 != piece.states[piece.current_state as usize][shift_y][shift_x]
+          {
+            let x = piece.x + shift_x as isize;
+            self.game_map[piece.y + shift_y][x as usize] =
+              piece.states[piece.current_state as usize][shift_y][shift_x];
+          }
+          shift_x += 1;
+        }
+        shift_y += 1;
+      }
+      to_add += self.current_level;
+    }
+    self.update_score(to_add);
+    let lines = self.check_lines();
+    self.send_garbage(lines);
+    self.last_locked = self.current_piece.take();
+    self.can_hold = true;
+    self.gravity_frames = 0;
+  }
+
+  // Whether `piece` is a T with three of the corners around its center
+  // taken, by blocks or by the walls and the floor.
+  fn is_t_spin(&self, piece: &Tetrimino) -> bool {
+    let state = &piece.states[piece.current_state as usize];
+    let cells = (0..4).flat_map(|y| (0..4).map(move |x| (x, y)))
+      .filter(|&(x, y)| 7 == state[y][x])
+      .collect::<Vec<_>>();
+    // The center touches the three other cases of the T.
+    let center = cells.iter().find(|&&(x, y)| {
+      3 == cells.iter().filter(|&&(other_x, other_y)| {
+        1 == (x as isize - other_x as isize).abs() +
+          (y as isize - other_y as isize).abs()
+      }).count()
+    });
+    let (x, y) = match center {
+      Some(&(x, y)) if 4 == cells.len() => {
+        (piece.x + x as isize, piece.y as isize + y as isize)
+      }
+      _ => return false,
+    };
+    let corners = [
+      (x - 1, y - 1), (x + 1, y - 1), (x - 1, y + 1), (x + 1, y + 1),
+    ];
+    let taken = corners.iter().filter(|&&(x, y)| {
+      x < 0 || WIDTH as isize <= x || y < 0 || HEIGHT as isize <= y ||
+        0 != self.game_map[y as usize][x as usize]
+    }).count();
+    3 <= taken
+  }
+
+  // Cleared lines first cancel incoming garbage, the rest goes to the
+  // opponent. Garbage that is still pending rises when nothing was cleared.
+  fn send_garbage(&mut self, lines: u32) {
+    let mut attack = match lines {
+      0 | 1 => 0,
+      2 => 1,
+      3 => 2,
+      _ => 4,
+    };
+    if 0 == lines {
+      let pending = self.pending_garbage;
+      self.pending_garbage = 0;
+      self.add_garbage_lines(pending);
+    } else if attack <= self.pending_garbage {
+      self.pending_garbage -= attack;
+      attack = 0;
+    } else {
+      attack -= self.pending_garbage;
+      self.pending_garbage = 0;
+    }
+    self.outgoing_garbage += attack;
+  }
+
+  fn add_garbage_lines(&mut self, lines: u32) {
+    if 0 == lines {
+      return;
+    }
+    let hole = self.garbage_rng.gen_range(WIDTH as u32) as usize;
+    for _ in 0..lines {
+      if self.game_map[0].iter().any(|case| 0 != *case) {
+        // Blocks pushed out of the top of the grid.
+        self.game_over = true;
+      }
+      self.game_map.remove(0);
+      let mut line = [GARBAGE; WIDTH];
+      line[hole] = 0;
+      self.game_map.push(line);
+    }
+  }
+
+  pub fn take_garbage(&mut self) -> u32 {
+    let attack = self.outgoing_garbage;
+    self.outgoing_garbage = 0;
+    attack
+  }
+
+  fn gravity_delay(&self) -> u32 {
+    LEVEL_TIMES[self.current_level as usize - 1] * FRAMES_PER_SECOND / 1000
+  }
+
+  // The next piece of the scripted ones, or a random one if allowed.
+  fn deal(&mut self) -> Option<Tetrimino> {
+    if self.scripted_pieces.is_empty() && !self.random_pieces {
+      return None;
+    }
+    Some(self.create_new_tetrimino())
+  }
+
+  fn spawn_piece(&mut self) {
+    let current_piece = match self.next_piece.take().or_else(|| self.deal()) {
+      Some(piece) => piece,
+      None => {
+        // No more pieces.
+        self.game_over = true;
+        return;
+      }
+    };
+    self.next_piece = self.deal();
+    self.rotated = false;
+    if current_piece.test_current_position(&self.game_map) {
+      self.current_piece = Some(current_piece);
+      self.nb_pieces += 1;
+    } else {
+      self.game_over = true;
+    }
+  }
+
+  // Puts the current piece aside, and takes the one that was there or the
+  // next one. Returns false if it isn't allowed yet.
+  pub fn hold(&mut self) -> bool {
+    if !self.can_hold || self.current_piece.is_none() {
+      return false;
+    }
+    // The last piece can't be held when nothing would replace it.
+    if self.held_piece.is_none() && self.next_piece.is_none() {
+      return false;
+    }
+    self.rotated = false;
+    let current_piece = self.current_piece.take().map(|piece| piece.spawn());
+    self.can_hold = false;
+    self.gravity_frames = 0;
+    match self.held_piece.take() {
+      Some(held_piece) => {
+        if held_piece.test_current_position(&self.game_map) {
+          self.current_piece = Some(held_piece);
+          self.nb_pieces += 1;
+        } else {
+          self.game_over = true;
+        }
+      }
+      None => self.spawn_piece(),
+    }
+    self.held_piece = current_piece;
+    true
+  }
+
+  // The next `nb` pieces, the next piece first. The ones after it aren't
+  // drawn yet, they only follow from the seed.
+  pub fn preview(&self, nb: usize) -> Vec<Tetrimino> {
+    let mut future = self.clone();
+    let mut pieces = future.next_piece.into_iter().collect::<Vec<_>>();
+    while pieces.len() < nb {
+      match future.deal() {
+        Some(piece) => pieces.push(piece),
+        None => break,
+      }
+    }
+    pieces.truncate(nb);
+    pieces
+  }
+
+  // Returns true if the input locked the current piece.
+  fn apply_input(&mut self, input: Input) -> bool {
+    if let Input::Hold = input {
+      self.hold();
+      return false;
+    }
+    let mut make_permanent = false;
+    if let Some(ref mut piece) = self.current_piece {
+      let x = piece.x;
+      let y = piece.y;
+      match input {
+        Input::Left => {
+          if piece.change_position(&self.game_map, x - 1, y) {
+            self.rotated = false;
+          }
+        }
+        Input::Right => {
+          if piece.change_position(&self.game_map, x + 1, y) {
+            self.rotated = false;
+          }
+        }
+        Input::SoftDrop => {
+          self.gravity_frames = 0;
+          make_permanent = !piece.change_position(&self.game_map, x, y + 1);
+          if !make_permanent {
+            self.rotated = false;
+          }
+        }
+        Input::Rotate => {
+          let state = piece.current_state;
+          piece.rotate(&self.game_map);
+          if state != piece.current_state {
+            self.rotated = true;
+          }
+        }
+        Input::HardDrop => {
+          let mut y = piece.y;
+          while piece.change_position(&self.game_map, x, y + 1) {
+            y += 1;
+            self.rotated = false;
+          }
+          make_permanent = true;
+        }
+        Input::Garbage(lines) => {
+          self.pending_garbage += lines;
+        }
+        Input::Hold => {}
+      }
+    }
+    if make_permanent {
+      self.make_permanent();
+    }
+    make_permanent
+  }
+
+  // Advances the game by one frame.
+  pub fn step(&mut self, inputs: &[Input]) {
+    if self.game_over {
+      return;
+    }
+    self.frame += 1;
+    self.gravity_frames += 1;
+    if self.gravity_frames > self.gravity_delay() {
+      let mut make_permanent = false;
+      if let Some(ref mut piece) = self.current_piece {
+        let x = piece.x;
+        let y = piece.y + 1;
+        make_permanent = !piece.change_position(&self.game_map, x, y);
+        if !make_permanent {
+          self.rotated = false;
+        }
+      }
+      if make_permanent {
+        self.make_permanent();
+      }
+      self.gravity_frames = 0;
+    }
+    if self.current_piece.is_none() && !self.game_over {
+      self.spawn_piece();
+    }
+    if self.game_over {
+      return;
+    }
+    let mut locked = false;
+    for input in inputs {
+      match *input {
+        Input::Garbage(lines) => self.pending_garbage += lines,
+        _ if locked => {}
+        _ => locked = self.apply_input(*input),
+      }
+    }
+  }
+
+  pub fn ghost_y(&self) -> Option<usize> {
+    self.current_piece.as_ref().map(|piece| {
+      let mut ghost_y = piece.y;
+      while piece.test_position(
+        &self.game_map,
+        piece.current_state as usize,
+        piece.x,
+        ghost_y + 1
+      ) {
+        ghost_y += 1;
+      }
+      ghost_y
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Input, Tetris, Tetrimino, GARBAGE, HEIGHT, WIDTH};
+
+  #[test]
+  fn cleared_lines_cancel_garbage_before_attacking() {
+    let mut tetris = Tetris::new();
+    tetris.send_garbage(1);
+    tetris.send_garbage(2);
+    assert_eq!(1, tetris.take_garbage());
+    tetris.pending_garbage = 3;
+    tetris.send_garbage(3);
+    assert_eq!((1, 0), (tetris.pending_garbage, tetris.take_garbage()));
+    tetris.send_garbage(4);
+    assert_eq!((0, 3), (tetris.pending_garbage, tetris.take_garbage()));
+  }
+
+  #[test]
+  fn pending_garbage_rises_when_nothing_is_cleared() {
+    let mut tetris = Tetris::new();
+    tetris.pending_garbage = 2;
+    tetris.send_garbage(0);
+    assert_eq!(0, tetris.pending_garbage);
+    let height = tetris.game_map.len();
+    let rows = &tetris.game_map[height - 2..];
+    assert_eq!(rows[0], rows[1]);
+    assert_eq!(1, rows[0].iter().filter(|case| 0 == **case).count());
+    assert!(rows[0].iter().all(|case| 0 == *case || GARBAGE == *case));
+    assert!(!tetris.game_over);
+
+    // Blocks pushed out of the top end the game.
+    tetris.game_map[0][0] = 1;
+    tetris.pending_garbage = 1;
+    tetris.send_garbage(0);
+    assert!(tetris.game_over);
+  }
+
+  fn color(piece: &Option<Tetrimino>) -> Option<u8> {
+    piece.as_ref().map(|piece| piece.color())
+  }
+
+  #[test]
+  fn hold_swaps_once_per_piece() {
+    let mut tetris = Tetris::with_seed(3);
+    tetris.step(&[]);
+    let first = color(&tetris.current_piece);
+    let next = color(&tetris.next_piece);
+    tetris.step(&[Input::Hold]);
+    assert_eq!(first, color(&tetris.held_piece));
+    assert_eq!(next, color(&tetris.current_piece));
+    assert_eq!(2, tetris.nb_pieces);
+    // Not again before a piece locks.
+    tetris.step(&[Input::Hold]);
+    assert_eq!(next, color(&tetris.current_piece));
+
+    tetris.step(&[Input::HardDrop]);
+    tetris.step(&[]);
+    tetris.step(&[Input::Hold]);
+    assert_eq!(first, color(&tetris.current_piece));
+  }
+
+  #[test]
+  fn clears_the_top_row() {
+    let mut tetris = Tetris::with_seed(0);
+    tetris.game_map[0] = [1; WIDTH];
+    tetris.game_map[1] = [1; WIDTH];
+    tetris.game_map[HEIGHT - 1] = [1; WIDTH];
+    tetris.game_map[HEIGHT - 2][0] = 1;
+    assert_eq!(3, tetris.check_lines());
+    assert_eq!(HEIGHT, tetris.game_map.len());
+    assert!(tetris.game_map[..HEIGHT - 1].iter()
+      .all(|line| line.iter().all(|case| 0 == *case)));
+    assert_eq!(1, tetris.game_map[HEIGHT - 1][0]);
+  }
+}
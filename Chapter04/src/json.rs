@@ -0,0 +1,293 @@
+use std::fmt;
+
+// Just enough JSON for the bot protocol and the exports.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  // Keys keep their order.
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+      fields.into_iter().map(|(key, value)| (key.to_owned(), value)).collect()
+    )
+  }
+
+  pub fn string(value: &str) -> Json {
+    Json::String(value.to_owned())
+  }
+
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match *self {
+      Json::Object(ref fields) => fields.iter()
+        .find(|&(name, _)| name == key)
+        .map(|(_, value)| value),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match *self {
+      Json::String(ref value) => Some(value),
+      _ => None,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match *self {
+      Json::Number(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match *self {
+      Json::Array(ref values) => Some(values),
+      _ => None,
+    }
+  }
+
+  pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser {
+      chars: text.chars().collect(),
+      position: 0,
+      depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_spaces();
+    if parser.position < parser.chars.len() {
+      return Err(format!("unexpected data at {}", parser.position));
+    }
+    Ok(value)
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in value.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+// Compact, on a single line.
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(value) => write!(f, "{}", value),
+      Json::Number(value) => {
+        if value.fract() == 0. && value.abs() < 1e15 {
+          write!(f, "{}", value as i64)
+        } else {
+          write!(f, "{}", value)
+        }
+      }
+      Json::String(ref value) => write_string(f, value),
+      Json::Array(ref values) => {
+        write!(f, "[")?;
+        for (nb, value) in values.iter().enumerate() {
+          if 0 < nb {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", value)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(ref fields) => {
+        write!(f, "{{")?;
+        for (nb, (key, value)) in fields.iter().enumerate() {
+          if 0 < nb {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+// Arrays and objects nested deeper are refused, before they overflow the
+// stack.
+pub const MAX_DEPTH: usize = 64;
+
+struct Parser {
+  chars: Vec<char>,
+  position: usize,
+  // Arrays and objects around the value being parsed.
+  depth: usize,
+}
+
+impl Parser {
+  fn skip_spaces(&mut self) {
+    while self.position < self.chars.len() &&
+      self.chars[self.position].is_whitespace()
+    {
+      self.position += 1;
+    }
+  }
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.chars.get(self.position).cloned();
+    self.position += 1;
+    c
+  }
+
+  fn expect(&mut self, word: &str) -> Result<(), String> {
+    for expected in word.chars() {
+      if Some(expected) != self.next() {
+        return Err(format!("expected {} at {}", word, self.position));
+      }
+    }
+    Ok(())
+  }
+
+  fn value(&mut self) -> Result<Json, String> {
+    if MAX_DEPTH < self.depth {
+      return Err(format!("nested too deep at {}", self.position));
+    }
+    self.depth += 1;
+    let value = self.nested_value();
+    self.depth -= 1;
+    value
+  }
+
+  fn nested_value(&mut self) -> Result<Json, String> {
+    self.skip_spaces();
+    match self.chars.get(self.position).cloned() {
+      Some('n') => self.expect("null").map(|_| Json::Null),
+      Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+      Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+      Some('"') => self.string().map(Json::String),
+      Some('[') => {
+        self.position += 1;
+        let mut values = Vec::new();
+        self.skip_spaces();
+        if Some(&']') == self.chars.get(self.position) {
+          self.position += 1;
+          return Ok(Json::Array(values));
+        }
+        loop {
+          values.push(self.value()?);
+          self.skip_spaces();
+          match self.next() {
+            Some(',') => {}
+            Some(']') => return Ok(Json::Array(values)),
+            _ => return Err(format!("expected , or ] at {}", self.position)),
+          }
+        }
+      }
+      Some('{') => {
+        self.position += 1;
+        let mut fields = Vec::new();
+        self.skip_spaces();
+        if Some(&'}') == self.chars.get(self.position) {
+          self.position += 1;
+          return Ok(Json::Object(fields));
+        }
+        loop {
+          self.skip_spaces();
+          let key = self.string()?;
+          self.skip_spaces();
+          self.expect(":")?;
+          fields.push((key, self.value()?));
+          self.skip_spaces();
+          match self.next() {
+            Some(',') => {}
+            Some('}') => return Ok(Json::Object(fields)),
+            _ => return Err(format!("expected , or }} at {}", self.position)),
+          }
+        }
+      }
+      Some(c) if c == '-' || c.is_ascii_digit() => {
+        let start = self.position;
+        while self.position < self.chars.len() && {
+          let c = self.chars[self.position];
+          c.is_ascii_digit() || "+-.eE".contains(c)
+        } {
+          self.position += 1;
+        }
+        let number = self.chars[start..self.position].iter()
+          .collect::<String>();
+        number.parse::<f64>().map(Json::Number)
+          .map_err(|_| format!("bad number {}", number))
+      }
+      _ => Err(format!("unexpected data at {}", self.position)),
+    }
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.expect("\"")?;
+    let mut value = String::new();
+    loop {
+      match self.next() {
+        Some('"') => return Ok(value),
+        Some('\\') => match self.next() {
+          Some('n') => value.push('\n'),
+          Some('r') => value.push('\r'),
+          Some('t') => value.push('\t'),
+          Some('b') => value.push('\u{8}'),
+          Some('f') => value.push('\u{c}'),
+          Some('u') => {
+            let code = (0..4).filter_map(|_| self.next())
+              .collect::<String>();
+            let c = u32::from_str_radix(&code, 16).ok()
+              .and_then(::std::char::from_u32)
+              .unwrap_or('\u{fffd}');
+            value.push(c);
+          }
+          Some(c) => value.push(c),
+          None => return Err("unfinished string".to_owned()),
+        },
+        Some(c) => value.push(c),
+        None => return Err("unfinished string".to_owned()),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Json, MAX_DEPTH};
+
+  #[test]
+  fn parses_what_it_prints() {
+    let text = r#"{"type": "suggestion", "moves": [{"x": 4, "y": -1.5},
+      null, true], "name": "a \"bot\"\n"}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(Some("suggestion"), json.get("type").and_then(Json::as_str));
+    assert_eq!(Some("a \"bot\"\n"), json.get("name").and_then(Json::as_str));
+    let moves = json.get("moves").and_then(Json::as_array).unwrap();
+    assert_eq!(Some(-1.5), moves[0].get("y").and_then(Json::as_f64));
+    assert_eq!(vec![Json::Null, Json::Bool(true)], moves[1..].to_vec());
+    assert_eq!(json, Json::parse(&json.to_string()).unwrap());
+    assert!(Json::parse("[1, 2").is_err());
+    assert!(Json::parse("{} {}").is_err());
+  }
+
+  #[test]
+  fn refuses_values_nested_too_deep() {
+    let nested = |depth: usize| {
+      format!("{}1{}", "[".repeat(depth), "]".repeat(depth))
+    };
+    assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+    assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+    assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_err());
+  }
+}
@@ -0,0 +1,690 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use game::{Tetris, FRAMES_PER_SECOND, LEVEL_LINES};
+use json::Json;
+use storage::{self, Loaded};
+
+pub const LEADERBOARD_VERSION: u32 = 1;
+pub const LEADERBOARD_FILE: &str = "leaderboard.txt";
+// Every game played, ranked or not.
+pub const HISTORY_FILE: &str = "history.txt";
+// The columns of the CSV exports. The day and the duration are there to be
+// read, imports go by the date and the frames.
+pub const CSV_HEADER: &str =
+  "mode,name,score,lines,level,date,day,frames,duration,replay";
+// Where older versions kept the best scores, in the working directory.
+pub const LEGACY_FILE: &str = "scores.txt";
+// Entries kept for every mode.
+pub const NB_ENTRIES: usize = 10;
+pub const MAX_NAME: usize = 16;
+
+// The leaderboard is a text file:
+//
+//   TETRIS-LEADERBOARD <version>
+//   <mode> <score> <lines> <level> <date> <frames> <replay> <name>
+//
+// with a line per entry. The date is in seconds since the epoch, the
+// duration in frames and the replay is `-` when there's none. Every mode
+// has its own table, best score first. The history is the same with a
+// `TETRIS-HISTORY <version>` header, the oldest game first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+  pub name: String,
+  pub mode: String,
+  pub score: u32,
+  pub lines: u32,
+  pub level: u32,
+  pub date: u64,
+  pub frames: u32,
+  pub replay: Option<String>,
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// The counts of an entry are kept in 32 bits.
+fn count(key: &str, value: u64) -> io::Result<u32> {
+  if u64::from(u32::MAX) < value {
+    return Err(invalid(format!("{} too big: {}", key, value)));
+  }
+  Ok(value as u32)
+}
+
+// Replays are in the replay directory, only the name of their file is kept.
+fn replay_name(replay: &str) -> String {
+  replay.rsplit(['/', '\\']).next().unwrap_or(replay).to_owned()
+}
+
+// A CSV field, quoted when it has to be.
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_owned()
+  }
+}
+
+// The fields of a CSV line, which has no line break in its fields.
+fn csv_fields(line: &str) -> Option<Vec<String>> {
+  let mut fields = Vec::new();
+  let mut chars = line.trim_end_matches('\r').chars().peekable();
+  loop {
+    let mut field = String::new();
+    if Some(&'"') == chars.peek() {
+      chars.next();
+      loop {
+        match chars.next()? {
+          '"' if Some(&'"') == chars.peek() => {
+            chars.next();
+            field.push('"');
+          }
+          '"' => break,
+          c => field.push(c),
+        }
+      }
+    }
+    while let Some(&c) = chars.peek() {
+      if ',' == c {
+        break
+      }
+      field.push(c);
+      chars.next();
+    }
+    fields.push(field);
+    if chars.next().is_none() {
+      return Some(fields);
+    }
+  }
+}
+
+// The entries of a leaderboard or history file.
+fn decode_entries(content: &str, header: &str, version: u32)
+  -> io::Result<Vec<Entry>>
+{
+  let mut lines = content.lines();
+  match lines.next() {
+    Some(line) if line.starts_with(header) => {
+      let found = line[header.len()..].trim();
+      if found != version.to_string() {
+        return Err(invalid(format!("unsupported version {}", found)));
+      }
+    }
+    _ => return Err(invalid(format!("no {} header", header.trim()))),
+  }
+  lines.filter(|line| !line.trim().is_empty())
+    .map(|line| {
+      Entry::decode(line).ok_or_else(|| invalid(format!("bad entry {}", line)))
+    })
+    .collect()
+}
+
+fn encode_entries(entries: &[Entry], header: &str, version: u32) -> String {
+  let mut content = format!("{}{}\n", header, version);
+  for entry in entries {
+    content.push_str(&format!("{}\n", entry.encode()));
+  }
+  content
+}
+
+// The replay and the name at the end of an entry. Older versions wrote the
+// whole path of the replay, which has spaces in some data directories.
+fn split_replay(rest: &str) -> Option<(&str, &str)> {
+  const EXTENSION: &str = ".replay ";
+  if let Some(end) = rest.find(EXTENSION) {
+    let replay = &rest[..end + EXTENSION.len() - 1];
+    if !replay.starts_with("- ") && replay.contains(['/', '\\']) {
+      return Some((replay, &rest[end + EXTENSION.len()..]));
+    }
+  }
+  rest.split_once(' ')
+}
+
+// The day of `date` as year, month and day.
+fn civil_date(date: u64) -> (i64, u32, u32) {
+  // From the days since 1970-01-01, in eras of 400 years from 0000-03-01.
+  let days = (date / 86400) as i64 + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days - era * 146_097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 -
+    day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era -
+    (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month = (5 * day_of_year + 2) / 153;
+  let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+  let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+impl Entry {
+  // The entry of a finished game, played now.
+  pub fn new(name: &str, mode: &str, tetris: &Tetris, replay: Option<String>)
+    -> Entry
+  {
+    Entry {
+      name: name.to_owned(),
+      mode: mode.to_owned(),
+      score: tetris.score,
+      lines: tetris.nb_lines,
+      level: tetris.current_level,
+      date: SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0),
+      frames: tetris.frame,
+      replay,
+    }
+  }
+
+  // Whether the entry ranks above `other`: the best score, then the most
+  // lines, then the oldest.
+  fn is_above(&self, other: &Entry) -> bool {
+    (self.score, self.lines, other.date) > (other.score, other.lines, self.date)
+  }
+
+  pub fn describe_date(&self) -> String {
+    let (year, month, day) = civil_date(self.date);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+  }
+
+  pub fn describe_duration(&self) -> String {
+    let seconds = self.frames / FRAMES_PER_SECOND;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+  }
+
+  fn encode(&self) -> String {
+    format!(
+      "{} {} {} {} {} {} {} {}",
+      self.mode, self.score, self.lines, self.level, self.date, self.frames,
+      self.replay.as_deref().unwrap_or("-"),
+      self.name
+    )
+  }
+
+  fn decode(line: &str) -> Option<Entry> {
+    let words = line.splitn(7, ' ').collect::<Vec<_>>();
+    if 7 != words.len() {
+      return None;
+    }
+    let (replay, name) = split_replay(words[6])?;
+    Some(Entry {
+      mode: words[0].to_owned(),
+      score: words[1].parse::<u32>().ok()?,
+      lines: words[2].parse::<u32>().ok()?,
+      level: words[3].parse::<u32>().ok()?,
+      date: words[4].parse::<u64>().ok()?,
+      frames: words[5].parse::<u32>().ok()?,
+      replay: match replay {
+        "-" => None,
+        replay => Some(replay_name(replay)),
+      },
+      name: name.to_owned(),
+    })
+  }
+
+  // Whether the entry fits in a line of the files: a mode and a replay
+  // without spaces, a name without line breaks. The replay is kept by name.
+  fn check(mut self) -> io::Result<Entry> {
+    self.replay = self.replay.map(|replay| replay_name(&replay));
+    let spaced = |value: &str| {
+      value.is_empty() || value.contains(char::is_whitespace)
+    };
+    if spaced(&self.mode) || self.replay.as_ref().is_some_and(|replay| {
+      spaced(replay) || "-" == replay
+    }) || self.name.contains(char::is_control)
+    {
+      return Err(invalid(format!("bad entry {:?}", self)));
+    }
+    Ok(self)
+  }
+
+  pub fn to_json(&self) -> Json {
+    Json::object(vec![
+      ("mode", Json::string(&self.mode)),
+      ("name", Json::string(&self.name)),
+      ("score", Json::Number(self.score as f64)),
+      ("lines", Json::Number(self.lines as f64)),
+      ("level", Json::Number(self.level as f64)),
+      ("date", Json::Number(self.date as f64)),
+      ("day", Json::string(&self.describe_date())),
+      ("frames", Json::Number(self.frames as f64)),
+      ("duration", Json::string(&self.describe_duration())),
+      ("replay", match self.replay {
+        Some(ref replay) => Json::string(replay),
+        None => Json::Null,
+      }),
+    ])
+  }
+
+  pub fn from_json(json: &Json) -> io::Result<Entry> {
+    let text = |key: &str| {
+      json.get(key).and_then(|value| value.as_str())
+        .map(|value| value.to_owned())
+        .ok_or_else(|| invalid(format!("bad {} in {}", key, json)))
+    };
+    let number = |key: &str| {
+      json.get(key).and_then(|value| value.as_f64())
+        .filter(|value| 0. <= *value && 0. == value.fract())
+        .map(|value| value as u64)
+        .ok_or_else(|| invalid(format!("bad {} in {}", key, json)))
+    };
+    Entry {
+      mode: text("mode")?,
+      name: text("name")?,
+      score: count("score", number("score")?)?,
+      lines: count("lines", number("lines")?)?,
+      level: count("level", number("level")?)?,
+      date: number("date")?,
+      frames: count("frames", number("frames")?)?,
+      replay: match json.get("replay") {
+        None | Some(&Json::Null) => None,
+        Some(_) => Some(text("replay")?),
+      },
+    }.check()
+  }
+
+  // A line of CSV, in the columns of `CSV_HEADER`.
+  pub fn to_csv(&self) -> String {
+    [
+      csv_field(&self.mode),
+      csv_field(&self.name),
+      self.score.to_string(),
+      self.lines.to_string(),
+      self.level.to_string(),
+      self.date.to_string(),
+      self.describe_date(),
+      self.frames.to_string(),
+      self.describe_duration(),
+      csv_field(self.replay.as_ref().map_or("", |replay| replay.as_str())),
+    ].join(",")
+  }
+
+  // The columns are found by name in `header`.
+  pub fn from_csv(header: &[String], line: &str) -> io::Result<Entry> {
+    let bad = || invalid(format!("bad CSV line {}", line));
+    let fields = csv_fields(line).ok_or_else(bad)?;
+    let field = |name: &str| {
+      header.iter().position(|column| name == column.trim())
+        .and_then(|index| fields.get(index))
+        .map(|value| value.as_str())
+        .ok_or_else(|| invalid(format!("no {} column", name)))
+    };
+    let number = |name: &str| {
+      field(name)?.trim().parse::<u64>().map_err(|_| bad())
+    };
+    Entry {
+      mode: field("mode")?.to_owned(),
+      name: field("name")?.to_owned(),
+      score: count("score", number("score")?)?,
+      lines: count("lines", number("lines")?)?,
+      level: count("level", number("level")?)?,
+      date: number("date")?,
+      frames: count("frames", number("frames")?)?,
+      replay: match field("replay").unwrap_or("") {
+        "" => None,
+        replay => Some(replay.to_owned()),
+      },
+    }.check()
+  }
+}
+
+pub fn to_json(entries: &[&Entry]) -> Json {
+  Json::Array(entries.iter().map(|entry| entry.to_json()).collect())
+}
+
+pub fn to_csv(entries: &[&Entry]) -> String {
+  let mut content = format!("{}\n", CSV_HEADER);
+  for entry in entries {
+    content.push_str(&format!("{}\n", entry.to_csv()));
+  }
+  content
+}
+
+// The entries of a leaderboard or history file, a JSON array of entries or
+// a CSV file with a header.
+pub fn import(content: &str) -> io::Result<Vec<Entry>> {
+  let start = content.trim_start();
+  if start.starts_with("TETRIS-LEADERBOARD") {
+    return Leaderboard::decode(content).map(|leaderboard| leaderboard.entries);
+  }
+  if start.starts_with("TETRIS-HISTORY") {
+    return History::decode(content).map(|history| history.entries);
+  }
+  if start.starts_with('[') {
+    let json = Json::parse(content).map_err(invalid)?;
+    return json.as_array()
+      .ok_or_else(|| invalid("not an array of entries".to_owned()))?
+      .iter()
+      .map(Entry::from_json)
+      .collect();
+  }
+  let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+  let header = lines.next().and_then(csv_fields)
+    .ok_or_else(|| invalid("no CSV header".to_owned()))?;
+  lines.map(|line| Entry::from_csv(&header, line)).collect()
+}
+
+// The single player entries of a legacy file: the best scores on a line and
+// the most lines on the next. Both lists were kept apart, the best of each
+// go together. `date` is when the file was written last.
+pub fn legacy_entries(content: &str, date: u64) -> io::Result<Vec<Entry>> {
+  let list = |line: Option<&str>| -> io::Result<Vec<u32>> {
+    let mut list = line.unwrap_or("").split_whitespace()
+      .map(|number| number.parse::<u32>())
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|_| invalid(format!("bad {} line", LEGACY_FILE)))?;
+    list.sort_by(|a, b| b.cmp(a));
+    Ok(list)
+  };
+  let mut lines = content.lines();
+  let scores = list(lines.next())?;
+  let nb_lines = list(lines.next())?;
+  Ok(scores.iter().enumerate().map(|(index, score)| {
+    let lines = nb_lines.get(index).cloned().unwrap_or(0);
+    Entry {
+      name: String::new(),
+      mode: "single".to_owned(),
+      score: *score,
+      lines,
+      // The level the game reached with those lines.
+      level: 1 + LEVEL_LINES[..LEVEL_LINES.len() - 1].iter()
+        .filter(|level_lines| **level_lines < lines)
+        .count() as u32,
+      date,
+      frames: 0,
+      replay: None,
+    }
+  }).collect())
+}
+
+pub fn load_legacy(path: &Path) -> io::Result<Vec<Entry>> {
+  let mut content = String::new();
+  File::open(path)?.read_to_string(&mut content)?;
+  let date = fs::metadata(path)?.modified()?.duration_since(UNIX_EPOCH)
+    .map(|date| date.as_secs())
+    .unwrap_or(0);
+  legacy_entries(&content, date)
+}
+
+// What a merge did with the entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Merged {
+  pub added: usize,
+  // Already there.
+  pub duplicates: usize,
+  // Not good enough to rank.
+  pub dropped: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Leaderboard {
+  // The tables of every mode one after the other, each one in order.
+  entries: Vec<Entry>,
+}
+
+impl Leaderboard {
+  pub fn encode(&self) -> String {
+    encode_entries(&self.entries, "TETRIS-LEADERBOARD ", LEADERBOARD_VERSION)
+  }
+
+  pub fn decode(content: &str) -> io::Result<Leaderboard> {
+    let entries =
+      decode_entries(content, "TETRIS-LEADERBOARD ", LEADERBOARD_VERSION)?;
+    let mut leaderboard = Leaderboard::default();
+    for entry in entries {
+      leaderboard.insert(entry);
+    }
+    Ok(leaderboard)
+  }
+
+  pub fn path() -> PathBuf {
+    storage::data_path(LEADERBOARD_FILE)
+  }
+
+  pub fn load() -> io::Result<Loaded<Leaderboard>> {
+    storage::load(&Leaderboard::path(), Leaderboard::decode)
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    storage::write_with_backup(&Leaderboard::path(), self.encode().as_bytes())
+  }
+
+  // The modes with a table, in the order they first appear.
+  pub fn modes(&self) -> Vec<&str> {
+    let mut modes = Vec::new();
+    for entry in &self.entries {
+      if !modes.contains(&entry.mode.as_str()) {
+        modes.push(entry.mode.as_str());
+      }
+    }
+    modes
+  }
+
+  // The table of `mode`, best first.
+  pub fn table(&self, mode: &str) -> Vec<&Entry> {
+    self.entries.iter().filter(|entry| mode == entry.mode).collect()
+  }
+
+  // Where `entry` would rank in the table of its mode, from 1, if it makes
+  // it.
+  pub fn rank(&self, entry: &Entry) -> Option<usize> {
+    let table = self.table(&entry.mode);
+    let rank = table.iter().position(|other| entry.is_above(other))
+      .unwrap_or(table.len());
+    if rank < NB_ENTRIES { Some(rank + 1) } else { None }
+  }
+
+  // Puts `entry` in its place, the last one of the table goes when it's
+  // full. Returns its rank if it made it.
+  pub fn insert(&mut self, entry: Entry) -> Option<usize> {
+    let rank = self.rank(&entry)?;
+    let index = self.entries.iter().position(|other| {
+      entry.mode == other.mode && entry.is_above(other)
+    }).unwrap_or(self.entries.len());
+    let mode = entry.mode.clone();
+    self.entries.insert(index, entry);
+    let mut nb = 0;
+    self.entries.retain(|other| {
+      if mode != other.mode {
+        return true;
+      }
+      nb += 1;
+      nb <= NB_ENTRIES
+    });
+    Some(rank)
+  }
+
+  // Adds the entries that aren't there yet, where they rank.
+  pub fn merge(&mut self, entries: Vec<Entry>) -> Merged {
+    let mut merged = Merged::default();
+    for entry in entries {
+      if self.entries.contains(&entry) {
+        merged.duplicates += 1;
+      } else if self.insert(entry).is_some() {
+        merged.added += 1;
+      } else {
+        merged.dropped += 1;
+      }
+    }
+    merged
+  }
+
+  // Every table, one mode after the other.
+  pub fn entries(&self) -> Vec<&Entry> {
+    self.entries.iter().collect()
+  }
+
+  // The name of the latest entry, to suggest next time.
+  pub fn last_name(&self) -> Option<&str> {
+    self.entries.iter().max_by_key(|entry| entry.date)
+      .map(|entry| entry.name.as_str())
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+  entries: Vec<Entry>,
+}
+
+impl History {
+  pub fn encode(&self) -> String {
+    encode_entries(&self.entries, "TETRIS-HISTORY ", LEADERBOARD_VERSION)
+  }
+
+  pub fn decode(content: &str) -> io::Result<History> {
+    let mut entries =
+      decode_entries(content, "TETRIS-HISTORY ", LEADERBOARD_VERSION)?;
+    entries.sort_by_key(|entry| entry.date);
+    Ok(History { entries })
+  }
+
+  pub fn path() -> PathBuf {
+    storage::data_path(HISTORY_FILE)
+  }
+
+  pub fn load() -> io::Result<Loaded<History>> {
+    storage::load(&History::path(), History::decode)
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    storage::write_with_backup(&History::path(), self.encode().as_bytes())
+  }
+
+  pub fn push(&mut self, entry: Entry) {
+    let index = self.entries.iter().position(|other| entry.date < other.date)
+      .unwrap_or(self.entries.len());
+    self.entries.insert(index, entry);
+  }
+
+  // Adds the games that aren't there yet.
+  pub fn merge(&mut self, entries: Vec<Entry>) -> Merged {
+    let mut merged = Merged::default();
+    for entry in entries {
+      if self.entries.contains(&entry) {
+        merged.duplicates += 1;
+      } else {
+        self.push(entry);
+        merged.added += 1;
+      }
+    }
+    merged
+  }
+
+  // The games of `mode`, or all of them, the oldest first.
+  pub fn games(&self, mode: Option<&str>) -> Vec<&Entry> {
+    self.entries.iter()
+      .filter(|entry| mode.map_or(true, |mode| mode == entry.mode))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{import, legacy_entries, Entry, Leaderboard, NB_ENTRIES};
+
+  fn entry(mode: &str, score: u32, date: u64) -> Entry {
+    Entry {
+      name: "Some One".to_owned(),
+      mode: mode.to_owned(),
+      score,
+      lines: score / 100,
+      level: 1,
+      date,
+      frames: 600,
+      replay: None,
+    }
+  }
+
+  #[test]
+  fn tables_keep_the_best_of_each_mode() {
+    let mut leaderboard = Leaderboard::default();
+    for score in 0..12 {
+      leaderboard.insert(entry("single", score * 100, score as u64));
+    }
+    assert_eq!(Some(1), leaderboard.insert(entry("royale", 50, 20)));
+    // Older first when the scores are the same.
+    assert_eq!(Some(2), leaderboard.insert(entry("single", 1100, 30)));
+    assert_eq!(None, leaderboard.insert(entry("single", 100, 40)));
+    assert_eq!(vec!["single", "royale"], leaderboard.modes());
+    let table = leaderboard.table("single");
+    assert_eq!(NB_ENTRIES, table.len());
+    assert_eq!((1100, 11), (table[0].score, table[0].date));
+    assert_eq!(300, table[NB_ENTRIES - 1].score);
+    assert_eq!(Some("Some One"), leaderboard.last_name());
+    assert_eq!(
+      leaderboard,
+      Leaderboard::decode(&leaderboard.encode()).unwrap()
+    );
+  }
+
+  #[test]
+  fn replays_are_kept_by_name() {
+    let content = "TETRIS-LEADERBOARD 1\n\
+      single 900 12 1 1700000000 3600 \
+      /Users/bob/Library/Application Support/tetris/replays/1-single.replay \
+      Bob B\n\
+      single 800 10 1 1700000001 3000 /home/al/replays/2-single.replay Al\n\
+      single 700 9 1 1700000002 2800 3-single.replay A name.replay x\n\
+      single 600 8 1 1700000003 2500 - \n";
+    let leaderboard = Leaderboard::decode(content).unwrap();
+    let entries = leaderboard.table("single").iter()
+      .map(|entry| (entry.replay.as_deref(), entry.name.as_str()))
+      .collect::<Vec<_>>();
+    assert_eq!(vec![
+      (Some("1-single.replay"), "Bob B"),
+      (Some("2-single.replay"), "Al"),
+      (Some("3-single.replay"), "A name.replay x"),
+      (None, ""),
+    ], entries);
+    let decoded = Leaderboard::decode(&leaderboard.encode()).unwrap();
+    assert_eq!(leaderboard, decoded);
+  }
+
+  #[test]
+  fn legacy_scores_join_the_single_player_table() {
+    let entries = legacy_entries("120 3400 80\n4 31 12\n", 1_700_000_000)
+      .unwrap();
+    let mut leaderboard = Leaderboard::default();
+    assert_eq!(3, leaderboard.merge(entries.clone()).added);
+    assert_eq!(3, leaderboard.merge(entries).duplicates);
+    let table = leaderboard.table("single");
+    let best = table.iter()
+      .map(|entry| (entry.score, entry.lines, entry.level))
+      .collect::<Vec<_>>();
+    assert_eq!(vec![(3400, 31, 2), (120, 12, 1), (80, 4, 1)], best);
+    assert!(legacy_entries("12 x\n", 0).is_err());
+  }
+
+  #[test]
+  fn imports_refuse_counts_past_32_bits() {
+    let csv = |score: &str| format!("{}\nsingle,Al,{},10,1,1700000000,,3000\n",
+      "mode,name,score,lines,level,date,day,frames", score);
+    let json = |score: &str| format!("[{{\"mode\": \"single\", \
+      \"name\": \"Al\", \"score\": {}, \"lines\": 10, \"level\": 1, \
+      \"date\": 1700000000, \"frames\": 3000}}]", score);
+    for content in &[csv("4294967295"), json("4294967295")] {
+      assert_eq!(u32::MAX, import(content).unwrap()[0].score);
+    }
+    for content in &[csv("4294967296"), json("4294967296")] {
+      assert!(import(content).is_err());
+    }
+  }
+
+  #[test]
+  fn imported_replays_are_kept_by_name() {
+    let content = "mode,name,score,lines,level,date,frames,replay\n\
+      single,Al,800,10,1,1700000000,3000,/home/al/replays/1-single.replay\n\
+      single,Al,800,10,1,1700000000,3000,C:\\replays\\1-single.replay\n\
+      single,Al,800,10,1,1700000000,3000,1-single.replay\n";
+    let entries = import(content).unwrap();
+    for entry in &entries {
+      assert_eq!(Some("1-single.replay"), entry.replay.as_deref());
+    }
+    let mut leaderboard = Leaderboard::default();
+    let merged = leaderboard.merge(entries);
+    assert_eq!((1, 2), (merged.added, merged.duplicates));
+  }
+}
@@ -0,0 +1,28 @@
+extern crate rand;
+
+pub mod bot;
+pub mod cli;
+pub mod editor;
+pub mod env;
+pub mod finesse;
+pub mod fumen;
+pub mod game;
+pub mod json;
+pub mod leaderboard;
+pub mod lobby;
+pub mod movegen;
+pub mod net;
+pub mod opener;
+pub mod perfect_clear;
+pub mod practice;
+pub mod puzzle;
+pub mod replay;
+pub mod rng;
+pub mod rollback;
+pub mod royale;
+pub mod save;
+pub mod session;
+pub mod spectator;
+pub mod storage;
+pub mod tbp;
+pub mod tetrimino;
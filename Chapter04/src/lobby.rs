@@ -0,0 +1,649 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::TcpListener;
+use std::thread::sleep;
+use std::time::Duration;
+
+use rand;
+
+use game::{Input, Tetris};
+use net::{Connection, Message, PROTOCOL_VERSION};
+use save;
+
+pub const MAX_PLAYERS: u32 = 8;
+
+enum State {
+  Connected,
+  Waiting(u32),
+  Playing { game: u64, index: u32 },
+  Watching,
+}
+
+struct Client {
+  id: u64,
+  name: String,
+  connection: Connection,
+  state: State,
+}
+
+// The frames of a player, for the spectators. The frames every other player
+// acknowledged are played on `base` and dropped: the spectators got them
+// when they came in, and the ones who come later get `base` instead.
+struct Record {
+  base: Tetris,
+  // The number of the first frame of `frames`.
+  first: u32,
+  frames: VecDeque<Vec<Input>>,
+  // The last frame of this player each player has, by player index.
+  acked: Vec<u32>,
+}
+
+impl Record {
+  fn new(seed: u64, nb_players: usize) -> Record {
+    Record {
+      base: Tetris::with_seed(seed),
+      first: 1,
+      frames: VecDeque::new(),
+      acked: vec![0; nb_players],
+    }
+  }
+
+  // The frame after the last one we have.
+  fn next(&self) -> u32 {
+    self.first + self.frames.len() as u32
+  }
+}
+
+struct Game {
+  id: u64,
+  seed: u64,
+  names: Vec<String>,
+  // Client ids, by player index. `None` once the player left.
+  members: Vec<Option<u64>>,
+  // By player index.
+  records: Vec<Record>,
+  spectators: Vec<u64>,
+}
+
+impl Game {
+  // Drops the frames every player still in the game has.
+  fn trim(&mut self) {
+    for (index, record) in self.records.iter_mut().enumerate() {
+      let acked = self.members.iter().zip(&record.acked).enumerate()
+        .filter(|&(other, (member, _))| index != other && member.is_some())
+        .map(|(_, (_, acked))| *acked)
+        .min()
+        .unwrap_or(u32::MAX);
+      while record.first <= acked {
+        match record.frames.pop_front() {
+          Some(inputs) => record.base.step(&inputs),
+          None => break,
+        }
+        record.first += 1;
+      }
+    }
+  }
+}
+
+// Pairs the players waiting for a game of the same size, then relays the
+// game traffic between them.
+pub struct Lobby {
+  listener: TcpListener,
+  clients: Vec<Client>,
+  games: Vec<Game>,
+  next_id: u64,
+}
+
+impl Lobby {
+  pub fn bind(port: u16) -> io::Result<Lobby> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    listener.set_nonblocking(true)?;
+    Ok(Lobby {
+      listener,
+      clients: Vec::new(),
+      games: Vec::new(),
+      next_id: 1,
+    })
+  }
+
+  // The port listened on, to find it when binding on port 0.
+  pub fn port(&self) -> io::Result<u16> {
+    self.listener.local_addr().map(|address| address.port())
+  }
+
+  pub fn run(&mut self) {
+    loop {
+      self.poll();
+      sleep(Duration::from_millis(1));
+    }
+  }
+
+  pub fn poll(&mut self) {
+    self.accept();
+    let mut outgoing = Vec::new();
+    for nb in 0..self.clients.len() {
+      for message in self.clients[nb].connection.receive() {
+        self.handle(nb, message, &mut outgoing);
+      }
+    }
+    self.remove_closed(&mut outgoing);
+    self.match_players(&mut outgoing);
+    for (id, message) in outgoing {
+      let client = self.clients.iter_mut().find(|client| id == client.id);
+      if let Some(client) = client {
+        client.connection.send(&message).ok();
+      }
+    }
+  }
+
+  fn accept(&mut self) {
+    loop {
+      match self.listener.accept() {
+        Ok((stream, address)) => {
+          if let Ok(mut connection) = Connection::from_stream(stream) {
+            println!("Connection from {}", address);
+            connection.buffer_writes();
+            self.clients.push(Client {
+              id: self.next_id,
+              name: String::new(),
+              connection,
+              state: State::Connected,
+            });
+            self.next_id += 1;
+          }
+        }
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(e) => {
+          println!("Couldn't accept a connection: {}", e);
+          break
+        }
+      }
+    }
+  }
+
+  fn handle(
+    &mut self,
+    nb: usize,
+    message: Message,
+    outgoing: &mut Vec<(u64, Message)>
+  ) {
+    let id = self.clients[nb].id;
+    match message {
+      Message::Hello { .. } => {
+        outgoing.push((id, Message::Hello {
+          version: PROTOCOL_VERSION,
+          seed: None,
+        }));
+      }
+      Message::Join { name, players } => {
+        let client = &mut self.clients[nb];
+        if let State::Connected = client.state {
+          let players = players.clamp(2, MAX_PLAYERS);
+          println!("{} is waiting for a {} players game", name, players);
+          client.name = name;
+          client.state = State::Waiting(players);
+        }
+      }
+      Message::List => {
+        let waiting = self.clients.iter().filter_map(|client| {
+          match client.state {
+            State::Waiting(players) => Some((client.name.clone(), players)),
+            _ => None,
+          }
+        }).collect();
+        outgoing.push((id, Message::Waiting(waiting)));
+        let playing = self.games.iter()
+          .map(|game| (game.id, game.names.clone()))
+          .collect();
+        outgoing.push((id, Message::Playing(playing)));
+      }
+      Message::Watch { game } => self.watch(nb, game, outgoing),
+      Message::To { index, message } => {
+        if let State::Playing { game, index: from } = self.clients[nb].state {
+          let target = self.games.iter()
+            .find(|candidate| game == candidate.id)
+            .and_then(|game| game.members.get(index as usize).cloned())
+            .and_then(|member| member);
+          if let Some(target) = target {
+            self.record_frames(game, from, index, &message, outgoing);
+            outgoing.push((target, Message::From {
+              index: from,
+              message,
+            }));
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+
+  // Keeps the frames of a player we didn't have yet, and sends them to the
+  // spectators. The acknowledgement tells which frames of the receiver the
+  // sender has.
+  fn record_frames(
+    &mut self,
+    game: u64,
+    index: u32,
+    to: u32,
+    message: &Message,
+    outgoing: &mut Vec<(u64, Message)>
+  ) {
+    let game = match self.games.iter_mut().find(|candidate| {
+      game == candidate.id
+    }) {
+      Some(game) => game,
+      None => return,
+    };
+    if let Message::Inputs { ack, first, ref frames } = *message {
+      if let Some(acked) = game.records.get_mut(to as usize)
+        .and_then(|record| record.acked.get_mut(index as usize))
+      {
+        *acked = ack.max(*acked);
+      }
+      let record = &mut game.records[index as usize];
+      let new_first = record.next();
+      for (nb, inputs) in frames.iter().enumerate() {
+        if first + nb as u32 == record.next() {
+          record.frames.push_back(inputs.clone());
+        }
+      }
+      if new_first < record.next() {
+        let skipped = (new_first - record.first) as usize;
+        let new_frames = record.frames.iter().skip(skipped)
+          .cloned()
+          .collect::<Vec<_>>();
+        for spectator in &game.spectators {
+          outgoing.push((*spectator, Message::From {
+            index,
+            message: Box::new(Message::Inputs {
+              ack: 0,
+              first: new_first,
+              frames: new_frames.clone(),
+            }),
+          }));
+        }
+      }
+      game.trim();
+    }
+  }
+
+  fn watch(
+    &mut self,
+    nb: usize,
+    game: u64,
+    outgoing: &mut Vec<(u64, Message)>
+  ) {
+    let id = self.clients[nb].id;
+    let found = if 0 == game {
+      self.games.last_mut()
+    } else {
+      self.games.iter_mut().find(|candidate| game == candidate.id)
+    };
+    let game = match found {
+      Some(game) => game,
+      None => {
+        self.clients[nb].connection.send(&Message::Bye).ok();
+        self.clients[nb].connection.close();
+        return;
+      }
+    };
+    println!("Someone is watching {}", game.names.join(", "));
+    self.clients[nb].state = State::Watching;
+    game.spectators.push(id);
+    outgoing.push((id, Message::Start {
+      seed: game.seed,
+      index: game.names.len() as u32,
+      names: game.names.clone(),
+    }));
+    for (index, record) in game.records.iter().enumerate() {
+      outgoing.push((id, Message::State {
+        index: index as u32,
+        state: save::encode_state(&record.base),
+      }));
+      outgoing.push((id, Message::From {
+        index: index as u32,
+        message: Box::new(Message::Inputs {
+          ack: 0,
+          first: record.first,
+          frames: record.frames.iter().cloned().collect(),
+        }),
+      }));
+      if game.members[index].is_none() {
+        outgoing.push((id, Message::Left { index: index as u32 }));
+      }
+    }
+  }
+
+  fn remove_closed(&mut self, outgoing: &mut Vec<(u64, Message)>) {
+    for client in self.clients.iter().filter(|client| {
+      client.connection.is_closed()
+    }) {
+      println!("{} left", if client.name.is_empty() {
+        "An unnamed client"
+      } else {
+        &client.name
+      });
+      if let State::Playing { game, index } = client.state {
+        if let Some(game) = self.games.iter_mut().find(|candidate| {
+          game == candidate.id
+        }) {
+          game.members[index as usize] = None;
+          game.trim();
+          let others = game.members.iter().filter_map(|member| *member)
+            .chain(game.spectators.iter().cloned());
+          for other in others {
+            outgoing.push((other, Message::Left { index }));
+          }
+        }
+      }
+      if let State::Watching = client.state {
+        for game in self.games.iter_mut() {
+          game.spectators.retain(|spectator| client.id != *spectator);
+        }
+      }
+    }
+    self.clients.retain(|client| !client.connection.is_closed());
+    self.games.retain(|game| {
+      game.members.iter().any(|member| member.is_some())
+    });
+  }
+
+  fn match_players(&mut self, outgoing: &mut Vec<(u64, Message)>) {
+    for players in 2..MAX_PLAYERS + 1 {
+      loop {
+        let waiting = self.clients.iter().filter(|client| {
+          match client.state {
+            State::Waiting(wanted) => players == wanted,
+            _ => false,
+          }
+        }).map(|client| client.id).take(players as usize).collect::<Vec<_>>();
+        if waiting.len() < players as usize {
+          break
+        }
+        self.start_game(waiting, outgoing);
+      }
+    }
+  }
+
+  fn start_game(
+    &mut self,
+    members: Vec<u64>,
+    outgoing: &mut Vec<(u64, Message)>
+  ) {
+    let game = self.next_id;
+    self.next_id += 1;
+    let seed = rand::random::<u64>();
+    let mut names = Vec::new();
+    for (index, id) in members.iter().enumerate() {
+      let client = self.clients.iter_mut().find(|client| *id == client.id);
+      if let Some(client) = client {
+        client.state = State::Playing { game, index: index as u32 };
+        names.push(client.name.clone());
+      }
+    }
+    println!("Starting a game for {}", names.join(", "));
+    for (index, id) in members.iter().enumerate() {
+      outgoing.push((*id, Message::Start {
+        seed,
+        index: index as u32,
+        names: names.clone(),
+      }));
+    }
+    self.games.push(Game {
+      id: game,
+      seed,
+      records: (0..names.len())
+        .map(|_| Record::new(seed, names.len()))
+        .collect(),
+      names,
+      members: members.into_iter().map(Some).collect(),
+      spectators: Vec::new(),
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc::{self, Receiver};
+  use std::thread;
+  use std::time::{Duration, SystemTime};
+
+  use game::{Input, Tetris};
+  use net::{self, Connection, Message, PROTOCOL_VERSION};
+  use session::{Outcome, Session};
+  use spectator::Spectator;
+  use super::{Lobby, State};
+
+  type Joined = (Connection, u64, usize, Vec<String>);
+
+  fn lobby() -> Lobby {
+    Lobby::bind(0).unwrap()
+  }
+
+  fn connect(lobby: &Lobby) -> Connection {
+    Connection::connect(("127.0.0.1", lobby.port().unwrap())).unwrap()
+  }
+
+  // Joins on another thread, the lobby has to be polled meanwhile.
+  fn join(lobby: &Lobby, name: &str, players: u32) -> Receiver<Joined> {
+    let mut connection = connect(lobby);
+    let name = name.to_owned();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+      let (seed, index, names) =
+        net::join_lobby(&mut connection, &name, players).unwrap();
+      sender.send((connection, seed, index, names)).ok();
+    });
+    receiver
+  }
+
+  // Polls the lobby until `done`, failing after a few seconds.
+  fn poll_until<F: FnMut(&mut Lobby) -> bool>(lobby: &mut Lobby, mut done: F) {
+    let start = SystemTime::now();
+    while !done(lobby) {
+      assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+      lobby.poll();
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+
+  fn wait<T>(lobby: &mut Lobby, receiver: &Receiver<T>) -> T {
+    let mut received = None;
+    poll_until(lobby, |_| {
+      received = received.take().or_else(|| receiver.try_recv().ok());
+      received.is_some()
+    });
+    received.unwrap()
+  }
+
+  fn nb_waiting(lobby: &Lobby) -> usize {
+    lobby.clients.iter()
+      .filter(|client| matches!(client.state, State::Waiting(_)))
+      .count()
+  }
+
+  fn sessions(lobby: &mut Lobby, joining: &[Receiver<Joined>]) -> Vec<Session> {
+    let mut sessions = joining.iter().map(|receiver| {
+      let (connection, seed, index, names) = wait(lobby, receiver);
+      Session::relayed(connection, seed, index, names)
+    }).collect::<Vec<_>>();
+    sessions.sort_by_key(|session| session.local_index);
+    sessions
+  }
+
+  #[test]
+  fn pairs_two_players() {
+    let mut lobby = lobby();
+    let alice = join(&lobby, "alice", 2);
+    poll_until(&mut lobby, |lobby| 1 == nb_waiting(lobby));
+    let bob = join(&lobby, "bob", 2);
+    let alice = wait(&mut lobby, &alice);
+    let bob = wait(&mut lobby, &bob);
+    assert_eq!(alice.1, bob.1);
+    assert_eq!((0, 1), (alice.2, bob.2));
+    assert_eq!(vec!["alice".to_owned(), "bob".to_owned()], alice.3);
+    assert_eq!(alice.3, bob.3);
+    assert_eq!(1, lobby.games.len());
+  }
+
+  #[test]
+  fn matches_players_by_game_size() {
+    let mut lobby = lobby();
+    let joining = [("a", 3), ("b", 2), ("c", 3), ("d", 2), ("e", 3)].iter()
+      .map(|&(name, players)| join(&lobby, name, players))
+      .collect::<Vec<_>>();
+    let joined = joining.iter()
+      .map(|receiver| wait(&mut lobby, receiver))
+      .collect::<Vec<_>>();
+    for &(ref connection, seed, index, ref names) in &joined {
+      assert!(!connection.is_closed());
+      let same_game = joined.iter()
+        .filter(|other| seed == other.1)
+        .collect::<Vec<_>>();
+      assert_eq!(names.len(), same_game.len());
+      assert!(same_game.iter().all(|other| *names == other.3));
+      assert!(index < names.len());
+    }
+    let mut sizes = joined.iter().map(|joined| joined.3.len())
+      .collect::<Vec<_>>();
+    sizes.sort();
+    assert_eq!(vec![2, 2, 3, 3, 3], sizes);
+    assert_eq!(2, lobby.games.len());
+  }
+
+  #[test]
+  fn forgets_players_who_left_before_their_game() {
+    let mut lobby = lobby();
+    let mut gone = connect(&lobby);
+    gone.send(&Message::Hello { version: PROTOCOL_VERSION, seed: None })
+      .unwrap();
+    gone.send(&Message::Join { name: "gone".to_owned(), players: 2 })
+      .unwrap();
+    poll_until(&mut lobby, |lobby| 1 == nb_waiting(lobby));
+    gone.close();
+    poll_until(&mut lobby, |lobby| lobby.clients.is_empty());
+
+    let alice = join(&lobby, "alice", 2);
+    let bob = join(&lobby, "bob", 2);
+    let alice = wait(&mut lobby, &alice);
+    let bob = wait(&mut lobby, &bob);
+    assert_eq!(alice.1, bob.1);
+    assert!(!alice.3.contains(&"gone".to_owned()));
+  }
+
+  fn inputs(player: usize, frame: u32) -> Vec<Input> {
+    match (frame + 11 * player as u32) % 30 {
+      4 => vec![Input::Rotate],
+      8 => vec![Input::Left],
+      12 => vec![Input::HardDrop],
+      _ => Vec::new(),
+    }
+  }
+
+  // Plays `frames` frames on every session and waits until every player
+  // confirmed the frames of the others through the lobby.
+  fn play(lobby: &mut Lobby, sessions: &mut [Session], frames: u32) {
+    for _ in 0..frames {
+      for (player, session) in sessions.iter_mut().enumerate() {
+        let frame = session.local.frame + 1;
+        session.step(inputs(player, frame));
+        session.sync();
+      }
+      lobby.poll();
+    }
+    poll_until(lobby, |_| {
+      for session in sessions.iter_mut() {
+        session.sync();
+      }
+      let frames = sessions.iter()
+        .map(|session| session.local.frame)
+        .collect::<Vec<_>>();
+      sessions.iter().all(|session| {
+        session.peers.iter().enumerate().all(|(index, peer)| match *peer {
+          Some(ref peer) => frames[index] == peer.board.confirmed().frame,
+          None => true,
+        })
+      })
+    });
+  }
+
+  fn summary(tetris: &Tetris) -> (u32, u32, u32, Vec<[u8; 10]>) {
+    let game_map = tetris.game_map.clone();
+    (tetris.frame, tetris.nb_pieces, tetris.score, game_map)
+  }
+
+  #[test]
+  fn relays_games_and_drops_acknowledged_frames() {
+    let mut lobby = lobby();
+    let joining = (0..3)
+      .map(|nb| join(&lobby, &format!("p{}", nb), 3))
+      .collect::<Vec<_>>();
+    let mut sessions = sessions(&mut lobby, &joining);
+    play(&mut lobby, &mut sessions, 90);
+    // Once every player has them, the frames are played on the boards.
+    poll_until(&mut lobby, |lobby| {
+      for session in sessions.iter_mut() {
+        session.sync();
+      }
+      let records = lobby.games[0].records.iter();
+      records.zip(sessions.iter()).all(|(record, session)| {
+        record.frames.is_empty() && session.local.frame + 1 == record.first
+      })
+    });
+    for (record, session) in lobby.games[0].records.iter().zip(&sessions) {
+      assert_eq!(summary(&session.local), summary(&record.base));
+    }
+    for session in &sessions {
+      for (index, peer) in session.peers.iter().enumerate() {
+        if let Some(ref peer) = *peer {
+          let local = &sessions[index].local;
+          assert_eq!(summary(local), summary(peer.board.confirmed()));
+        }
+      }
+    }
+    assert!(0 < sessions[0].local.nb_pieces);
+  }
+
+  #[test]
+  fn spectators_see_the_whole_game() {
+    let mut lobby = lobby();
+    let joining = [join(&lobby, "alice", 2), join(&lobby, "bob", 2)];
+    let mut sessions = sessions(&mut lobby, &joining);
+    play(&mut lobby, &mut sessions, 60);
+
+    // Comes in halfway through.
+    let mut connection = connect(&lobby);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+      let (seed, names) = net::watch_game(&mut connection, 0).unwrap();
+      sender.send(Spectator::new(connection, seed, names)).ok();
+    });
+    let mut spectator = wait(&mut lobby, &receiver);
+    play(&mut lobby, &mut sessions, 60);
+    for session in &mut sessions {
+      session.quit();
+    }
+    poll_until(&mut lobby, |_| {
+      spectator.update();
+      spectator.boards.iter().all(|board| board.is_done())
+    });
+    for (board, session) in spectator.boards.iter().zip(&sessions) {
+      assert_eq!(summary(&session.local), summary(&board.tetris));
+    }
+  }
+
+  #[test]
+  fn tells_the_others_who_left() {
+    let mut lobby = lobby();
+    let joining = [join(&lobby, "alice", 2), join(&lobby, "bob", 2)];
+    let mut sessions = sessions(&mut lobby, &joining);
+    play(&mut lobby, &mut sessions, 30);
+    // Gone without a word.
+    drop(sessions.pop());
+    poll_until(&mut lobby, |_| {
+      sessions[0].sync();
+      Some(Outcome::Disconnected) == sessions[0].outcome()
+    });
+    assert_eq!(None, lobby.games[0].members[1]);
+  }
+}
@@ -1,25 +1,57 @@
 extern crate rand;
 extern crate sdl2;
+extern crate tetris;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::{Canvas, Texture, TextureCreator};
 use sdl2::video::{Window, WindowContext};
 
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::thread::sleep;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, sleep};
 use std::time::{Duration, SystemTime};
 
+use tetris::bot::{Bot, Controller, Weights};
+use tetris::cli::option_value;
+use tetris::editor::Editor;
+use tetris::finesse::{Drill, Finesse, Outcome as FinesseOutcome};
+use tetris::fumen::{self, Page};
+use tetris::game::{Input, Tetris, FRAMES_PER_SECOND};
+use tetris::leaderboard::{
+  self, Entry, History, Leaderboard, LEADERBOARD_FILE, LEGACY_FILE, MAX_NAME,
+};
+use tetris::movegen;
+use tetris::net::{self, Connection, Message, DEFAULT_LOBBY_PORT, DEFAULT_PORT};
+use tetris::opener::{Opener, Progress, Trainer};
+use tetris::perfect_clear::{self, Move};
+use tetris::practice::Practice;
+use tetris::puzzle::{
+  Attempt, Progress as PuzzleProgress, Puzzle, Status, PROGRESS_FILE,
+  PUZZLE_DIRECTORY,
+};
+use tetris::replay::{Playback, Recording, Replay, REPLAY_DIRECTORY};
+use tetris::royale::{Royale, Strategy};
+use tetris::save::{SavedGame, SAVE_FILE};
+use tetris::storage::{self, Loaded};
+use tetris::session::{Outcome, Session};
+use tetris::spectator::Spectator;
+use tetris::tbp::ExternalBot;
+use tetris::tetrimino::{piece_letter, Tetrimino};
+
 const TETRIS_HEIGHT: usize = 40;
-const HIGHSCORE_FILE: &'static str = "scores.txt";
-const LEVEL_TIMES: [u32; 10] =
-  [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
-const LEVEL_LINES: [u32; 10] =
-  [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
-const NB_HIGHSCORES: usize = 5;
+const BOARD_WIDTH: u32 = 600;
+const SMALL_BOARD_WIDTH: u32 = 240;
+const SMALL_CASE_SIZE: u32 = 20;
+// Room for the miniature boards of the battle royale.
+const ROYALE_WIDTH: u32 = 800;
+// Rows the perfect clear hints stay under.
+const HINT_LINES: usize = 4;
 
 #[derive(Clone, Copy)]
 enum TextureColor {
@@ -30,581 +62,267 @@ enum TextureColor {
   White,
 }
 
-type Piece = Vec<Vec<u8>>;
-type States = Vec<Piece>;
-
-trait TetriminoGenerator {
-  fn new() -> Tetrimino;
-}
-
-struct TetriminoI;
-impl TetriminoGenerator for TetriminoI
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![1, 1, 1, 1],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 1, 0, 0],
-          vec![0, 1, 0, 0],
-          vec![0, 1, 0, 0],
-          vec![0, 1, 0, 0],
-        ],
-      ],
-      x: 4,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct TetriminoJ;
-impl TetriminoGenerator for TetriminoJ
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![2, 2, 2, 0],
-          vec![2, 0, 0, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![2, 2, 0, 0],
-          vec![0, 2, 0, 0],
-          vec![0, 2, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 0, 2, 0],
-          vec![2, 2, 2, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![2, 0, 0, 0],
-          vec![2, 0, 0, 0],
-          vec![2, 2, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-      ],
-      x: 4,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct TetriminoL;
-impl TetriminoGenerator for TetriminoL
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![3, 3, 3, 0],
-          vec![0, 0, 3, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 3, 0, 0],
-          vec![0, 3, 0, 0],
-          vec![3, 3, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![3, 0, 0, 0],
-          vec![3, 3, 3, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![3, 3, 0, 0],
-          vec![3, 0, 0, 0],
-          vec![3, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-      ],
-      x: 4,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct TetriminoO;
-impl TetriminoGenerator for TetriminoO
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![4, 4, 0, 0],
-          vec![4, 4, 0, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-      ],
-      x: 5,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct TetriminoS;
-impl TetriminoGenerator for TetriminoS
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![0, 5, 5, 0],
-          vec![5, 5, 0, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 5, 0, 0],
-          vec![0, 5, 5, 0],
-          vec![0, 0, 5, 0],
-          vec![0, 0, 0, 0],
-        ],
-      ],
-      x: 4,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct TetriminoZ;
-impl TetriminoGenerator for TetriminoZ
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![6, 6, 0, 0],
-          vec![0, 6, 6, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 0, 6, 0],
-          vec![0, 6, 6, 0],
-          vec![0, 6, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-      ],
-      x: 4,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct TetriminoT;
-impl TetriminoGenerator for TetriminoT
-{
-  fn new() -> Tetrimino {
-    Tetrimino {
-      states: vec![
-        vec![
-          vec![7, 7, 7, 0],
-          vec![0, 7, 0, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 7, 0, 0],
-          vec![7, 7, 0, 0],
-          vec![0, 7, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 7, 0, 0],
-          vec![7, 7, 7, 0],
-          vec![0, 0, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-        vec![
-          vec![0, 7, 0, 0],
-          vec![0, 7, 7, 0],
-          vec![0, 7, 0, 0],
-          vec![0, 0, 0, 0],
-        ],
-      ],
-      x: 4,
-      y: 0,
-      current_state: 0,
-    }
-  }
-}
-
-struct Tetrimino {
-  states: States,
-  x: isize,
-  y: usize,
-  current_state: u8,
-}
-
-impl Tetrimino {
-  fn rotate(&mut self, game_map: &[Vec<u8>]) {
-    let mut tmp_state = self.current_state + 1;
-    if self.states.len() <= tmp_state as usize {
-      tmp_state = 0;
-    }
-    let x_pos = [0, -1, 1, -2, 2, -3];
-    for x in x_pos.iter() {
-      let test_result = self.test_position(
-        game_map,
-        tmp_state as usize,
-        self.x + x,
-        self.y
-      );
-      if true == test_result {
-        self.current_state = tmp_state;
-        self.x += *x;
-        break
-      }
-    }
-  }
+struct KeyLayout {
+  left: Keycode,
+  right: Keycode,
+  down: Keycode,
+  rotate: Keycode,
+  drop: Keycode,
+  hold: Keycode,
+}
 
-  fn test_position(
-    &self,
-    game_map: &[Vec<u8>],
-    tmp_state: usize,
-    x: isize,
-    y: usize
-  ) -> bool {
-    for shift_y in 0..4 {
-      for shift_x in 0..4 {
-        let x = x + shift_x;
-        if 0 != self.states[tmp_state][shift_y][shift_x as usize] && (
-          game_map.len() <= y + shift_y ||
-          x < 0 ||
-          game_map[y + shift_y].len() <= x as usize ||
-          0 != game_map[y + shift_y][x as usize]
-        ) {
-          return false;
-        }
-      }
+impl KeyLayout {
+  fn arrows() -> KeyLayout {
+    KeyLayout {
+      left: Keycode::Left,
+      right: Keycode::Right,
+      down: Keycode::Down,
+      rotate: Keycode::Up,
+      drop: Keycode::Space,
+      hold: Keycode::C,
     }
-    return true;
   }
 
-  fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
-    self.test_position(game_map, self.current_state as usize, self.x, self.y)
+  fn wasd() -> KeyLayout {
+    KeyLayout {
+      left: Keycode::A,
+      right: Keycode::D,
+      down: Keycode::S,
+      rotate: Keycode::W,
+      drop: Keycode::LShift,
+      hold: Keycode::Q,
+    }
   }
 
-  fn change_position(
-    &mut self,
-    game_map: &[Vec<u8>],
-    new_x: isize,
-    new_y: usize
-  ) -> bool {
-    if true == self.test_position(
-      game_map,
-      self.current_state as usize,
-      new_x,
-      new_y
-    ) {
-      self.x = new_x as isize;
-      self.y = new_y;
-      true
-    } else {
-      false
+  fn arrows_versus() -> KeyLayout {
+    KeyLayout {
+      drop: Keycode::RShift,
+      hold: Keycode::RCtrl,
+      ..KeyLayout::arrows()
     }
   }
 }
 
-struct Tetris {
-  game_map: Vec<Vec<u8>>,
-  current_level: u32,
-  score: u32,
-  nb_lines: u32,
-  current_piece: Option<Tetrimino>,
-  next_piece: Option<Tetrimino>,
+struct Player {
+  tetris: Tetris,
+  keys: KeyLayout,
+  // Plays instead of the keyboard.
+  bot: Option<Box<dyn Controller>>,
 }
 
-impl Tetris {
-  fn new() -> Tetris {
-    let mut game_map = Vec::new();
-    for _ in 0..16 {
-      game_map.push(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-    }
-    Tetris {
-      game_map: game_map,
-      current_level: 1,
-      score: 0,
-      nb_lines: 0,
-      current_piece: None,
-      next_piece: None,
-    }
-  }
+struct BoardTextures<'a> {
+  grid: Texture<'a>,
+  border: Texture<'a>,
+  pieces: Vec<Texture<'a>>,
+  ghosts: Vec<Texture<'a>>,
+}
 
-  fn update_score(&mut self, to_add: u32) {
-    self.score += to_add;
-  }
+struct Screen<'a> {
+  canvas: Canvas<Window>,
+  texture_creator: &'a TextureCreator<WindowContext>,
+  font: sdl2::ttf::Font<'a, 'static>,
+  textures: BoardTextures<'a>,
+  event_pump: sdl2::EventPump,
+  height: u32,
+}
 
-  fn increase_level(&mut self) {
-    self.current_level += 1;
-  }
+enum Mode {
+  Single,
+  // The second player is a bot pressing a key every that many frames.
+  Versus { bot: Option<u32> },
+  // Against that many bots.
+  Royale(usize),
+  Host(u16),
+  Connect(String),
+  Lobby { address: String, name: String, players: u32 },
+  ListLobby(String),
+  // Play a replay file.
+  Replay(String),
+  // Practice an opener, by name or file.
+  Opener(String),
+  FinesseDrill,
+  // A game whose placements can be undone.
+  Practice,
+  // Play from a page of a fumen, from 1.
+  Fumen { data: String, page: usize },
+  // A puzzle by name or file, all of them without.
+  Puzzle(Option<String>),
+  // Edit a board, from a puzzle or a fumen if given.
+  Editor(Option<String>),
+  ListOpeners,
+  // The leaderboard, of a mode or of all of them.
+  ListScores(Option<String>),
+  // Watch a game of the lobby, 0 for the latest one.
+  Watch { address: String, game: u64 },
+}
 
-  fn increase_line(&mut self) {
-    self.nb_lines += 1;
-    if LEVEL_LINES[self.current_level as usize - 1] < self.nb_lines {
-      self.increase_level();
+fn handle_events(
+  keys: &KeyLayout,
+  quit: &mut bool,
+  events: &[Event]
+) -> Vec<Input> {
+  let mut inputs = Vec::new();
+  for event in events {
+    match *event {
+      Event::Quit { .. } |
+      Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+        *quit = true;
+        break
+      }
+      Event::KeyDown { keycode: Some(keycode), .. } => {
+        if keycode == keys.down {
+          inputs.push(Input::SoftDrop);
+        } else if keycode == keys.right {
+          inputs.push(Input::Right);
+        } else if keycode == keys.left {
+          inputs.push(Input::Left);
+        } else if keycode == keys.rotate {
+          inputs.push(Input::Rotate);
+        } else if keycode == keys.drop {
+          inputs.push(Input::HardDrop);
+        } else if keycode == keys.hold {
+          inputs.push(Input::Hold);
+        }
+      }
+      _ => {}
     }
   }
+  inputs
+}
 
-  fn check_lines(&mut self) {
-    let mut lines = 0;
-    let mut y = 0;
-    let mut score_add = 0;
-
-    while y < self.game_map.len() {
-      let mut complete = true;
+fn print_game_information(tetris: &Tetris) {
+  println!("Game over...");
+  println!("Score:           {}", tetris.score);
+  println!("Number of lines: {}", tetris.nb_lines);
+  println!("Current level:   {}", tetris.current_level);
+}
 
-      for x in &self.game_map[y] {
-        if 0 == *x {
-          complete = false;
-          break
-        }
-      }
-      if true == complete {
-        score_add += self.current_level;
-        self.game_map.remove(y);
-        y -= 1;
-        lines += 1;
-      }
-      y += 1;
-    }
-    if 4 <= lines {
-      // A "tetris"!
-      score_add += 1000;
-    }
-    self.update_score(score_add);
-    while self.game_map.len() < 16 {
-      self.increase_line();
-      self.game_map.insert(0, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+// What was loaded from a file of the data directory, None when there was
+// nothing. A corrupted file is reported along with what became of it.
+fn check_loaded<T>(
+  what: &str,
+  path: &Path,
+  loaded: io::Result<Loaded<T>>
+) -> io::Result<Option<T>> {
+  let loaded = loaded.map_err(|e| {
+    println!("Couldn't read the {} {}: {}", what, path.display(), e);
+    e
+  })?;
+  if let Some((ref corrupt, ref e)) = loaded.corrupt {
+    println!(
+      "The {} {} is corrupted ({}), it was moved to {}",
+      what, path.display(), e, corrupt.display()
+    );
+    if loaded.from_backup {
+      println!("Its backup is used instead");
+    } else {
+      println!("There is no backup to use instead, it starts over");
     }
   }
+  Ok(loaded.value)
+}
 
-  fn create_new_tetrimino() -> Tetrimino
-  {
-    static mut PREV: u8 = 7;
-    let mut rand_nb = rand::random::<u8>() % 7;
-    if unsafe { PREV } == rand_nb {
-      rand_nb = rand::random::<u8>() % 7;
-    }
-    unsafe { PREV = rand_nb; }
-    match rand_nb {
-      0 => TetriminoI::new(),
-      1 => TetriminoJ::new(),
-      2 => TetriminoL::new(),
-      3 => TetriminoO::new(),
-      4 => TetriminoS::new(),
-      5 => TetriminoZ::new(),
-      6 => TetriminoT::new(),
-      _ => unreachable!(),
-    }
-  }
-
-  fn make_permanent(&mut self) {
-    let mut to_add = 0;
-    if let Some(ref mut piece) = self.current_piece {
-      let mut shift_y = 0;
-
-      while shift_y < piece.states[piece.current_state as usize].len() &&
-        piece.y + shift_y < self.game_map.len()
-      {
-        let mut shift_x = 0;
-
-        while shift_x <
-          piece.states[piece.current_state as usize][shift_y].len() &&
-          (piece.x + shift_x as isize) <
-          self.game_map[piece.y + shift_y].len() as isize
-        {
-          if 0 != piece.states[piece.current_state as usize][shift_y][shift_x]
-          {
-            let x = piece.x + shift_x as isize;
-            self.game_map[piece.y + shift_y][x as usize] =
-              piece.states[piece.current_state as usize][shift_y][shift_x];
-          }
-          shift_x += 1;
-        }
-        shift_y += 1;
-      }
-      to_add += self.current_level;
+fn load_puzzle_progress() -> PuzzleProgress {
+  let path = PuzzleProgress::path();
+  check_loaded("puzzle progress", &path, PuzzleProgress::load())
+    .ok()
+    .and_then(|progress| progress)
+    .unwrap_or_default()
+}
+
+// Adds the game to the history and puts it on the leaderboard of `mode` if
+// it makes it, asking the name of the player unless it's known.
+fn record_score(
+  screen: &mut Screen,
+  tetris: &Tetris,
+  mode: &str,
+  replay: Option<String>,
+  name: Option<&str>
+) {
+  let loaded =
+    check_loaded("leaderboard", &Leaderboard::path(), Leaderboard::load());
+  // Better not to write over a leaderboard that couldn't be read.
+  let mut leaderboard = loaded.ok().map(|loaded| loaded.unwrap_or_default());
+  let mut entry = Entry::new(name.unwrap_or(""), mode, tetris, replay);
+  let rank = leaderboard.as_ref()
+    .and_then(|leaderboard| leaderboard.rank(&entry));
+  if name.is_none() {
+    let last_name = leaderboard.as_ref()
+      .and_then(|leaderboard| leaderboard.last_name())
+      .unwrap_or("Player")
+      .to_owned();
+    entry.name = match rank {
+      Some(rank) => prompt_name(screen, &last_name, rank),
+      None => last_name,
+    };
+  }
+  let loaded = check_loaded("history", &History::path(), History::load());
+  if let Ok(history) = loaded {
+    let mut history = history.unwrap_or_default();
+    history.push(entry.clone());
+    if let Err(e) = history.save() {
+      println!("Couldn't save the history: {}", e);
+    }
+  }
+  if let (Some(leaderboard), Some(rank)) = (leaderboard.as_mut(), rank) {
+    leaderboard.insert(entry);
+    match leaderboard.save() {
+      Ok(_) => println!("Rank:            #{} [NEW HIGHSCORE]", rank),
+      Err(e) => println!("Couldn't save the leaderboard: {}", e),
     }
-    self.update_score(to_add);
-    self.check_lines();
-    self.current_piece = None;
   }
 }
 
-fn handle_events(
-  tetris: &mut Tetris,
-  quit: &mut bool,
-  timer: &mut SystemTime,
-  event_pump: &mut sdl2::EventPump
-) -> bool {
-  let mut make_permanent = false;
-  if let Some(ref mut piece) = tetris.current_piece {
-    let mut tmp_x = piece.x;
-    let mut tmp_y = piece.y;
+// Typing changes the name, Enter keeps it. Escape keeps `default`.
+fn prompt_name(screen: &mut Screen, default: &str, rank: usize) -> String {
+  let mut name = default.to_owned();
+  let mut next_frame = SystemTime::now();
 
-    for event in event_pump.poll_iter() {
+  loop {
+    for event in screen.event_pump.poll_iter().collect::<Vec<_>>() {
       match event {
         Event::Quit { .. } |
         Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-          *quit = true;
-          break
+          return default.to_owned();
         }
-        Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-          *timer = SystemTime::now();
-          tmp_y += 1;
-        }
-        Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-          tmp_x += 1;
+        Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
+          let name = name.trim();
+          return if name.is_empty() { default } else { name }.to_owned();
         }
-        Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-          tmp_x -= 1;
-        }
-        Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-          piece.rotate(&tetris.game_map);
+        Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+          name.pop();
         }
-        Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-          let x = piece.x;
-          let mut y = piece.y;
-          while true == piece.change_position(&tetris.game_map, x, y + 1) {
-            y += 1;
+        Event::TextInput { ref text, .. } => {
+          for letter in text.chars() {
+            let allowed = letter.is_alphanumeric() || ' ' == letter ||
+              '-' == letter || '_' == letter;
+            if allowed && name.chars().count() < MAX_NAME {
+              name.push(letter);
+            }
           }
-          make_permanent = true;
         }
         _ => {}
       }
     }
-    if !make_permanent {
-      if false == piece.change_position(&tetris.game_map, tmp_x, tmp_y) &&
-        tmp_y != piece.y
-      {
-        make_permanent = true;
-      }
-    }
-  }
-  if make_permanent {
-    tetris.make_permanent();
-    *timer = SystemTime::now();
-  }
-  make_permanent
-}
-
-fn write_into_file(content: &str, file_name: &str) -> io::Result<()> {
-  let mut f = File::create(file_name)?;
-  f.write_all(content.as_bytes())
-}
-
-fn read_from_file(file_name: &str) -> io::Result<String> {
-  let mut f = File::open(file_name)?;
-  let mut content = String::new();
-  f.read_to_string(&mut content)?;
-  Ok(content)
-}
-
-fn slice_to_string(slice: &[u32]) -> String {
-  slice.iter().map(|highscore| highscore.to_string())
-    .collect::<Vec<String>>().join(" ")
-}
 
-fn save_highscores_and_lines(
-  highscores: &[u32],
-  number_of_lines: &[u32]
-) -> bool {
-  let s_highscores = slice_to_string(highscores);
-  let s_number_of_lines = slice_to_string(number_of_lines);
-  write_into_file(
-    &format!("{}\n{}\n", s_highscores, s_number_of_lines),
-    HIGHSCORE_FILE
-  ).is_ok()
-}
-
-fn line_to_slice(line: &str) -> Vec<u32> {
-  line.split(" ").filter_map(|nb| nb.parse::<u32>().ok()).collect()
-}
-
-fn load_highscores_and_lines() -> Option<(Vec<u32>, Vec<u32>)> {
-  if let Ok(content) = read_from_file(HIGHSCORE_FILE) {
-    let mut lines = content.splitn(2, "\n").map(|line| line_to_slice(line))
-      .collect::<Vec<_>>();
-    if lines.len() == 2 {
-      let (lines_sent, highscores) =
-        (lines.pop().unwrap(), lines.pop().unwrap());
-      Some((highscores, lines_sent))
-    } else {
-      None
-    }
-  } else {
-    None
-  }
-}
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_label(screen, &format!("NEW HIGHSCORE, #{}", rank), 20, 300);
+    display_label(screen, &format!("Name: {}_", name), 20, 340);
+    display_label(screen, "Enter to confirm", 20, 380);
+    screen.canvas.present();
 
-fn update_vec(v: &mut Vec<u32>, value: u32) -> bool {
-  if v.len() < NB_HIGHSCORES {
-    v.push(value);
-    true
-  } else {
-    for entry in v.iter_mut() {
-      if *entry < value {
-        *entry = value;
-        return true;
-      }
-    }
-    false
+    wait_for_next_frame(&mut next_frame);
   }
 }
 
-fn print_game_information(tetris: &Tetris) {
-  let mut new_highest_highscore = true;
-  let mut new_highest_lines_sent = true;
-  if let Some((mut highscores, mut lines_sent)) = load_highscores_and_lines() {
-    new_highest_highscore = update_vec(&mut highscores, tetris.score);
-    new_highest_lines_sent = update_vec(&mut lines_sent, tetris.nb_lines);
-    if new_highest_highscore || new_highest_lines_sent {
-      save_highscores_and_lines(&highscores, &lines_sent);
-    }
+fn wait_for_next_frame(next_frame: &mut SystemTime) {
+  *next_frame += Duration::new(0, 1_000_000_000u32 / FRAMES_PER_SECOND);
+  if let Ok(remaining) = next_frame.duration_since(SystemTime::now()) {
+    sleep(remaining);
   } else {
-    save_highscores_and_lines(&[tetris.score], &[tetris.nb_lines]);
-  }
-  println!("Game over...");
-  println!("Score:           {}{}",
-    tetris.score,
-    if new_highest_highscore { " [NEW HIGHSCORE]"} else { "" }
-  );
-  println!("Number of lines: {}{}",
-    tetris.nb_lines,
-    if new_highest_lines_sent { " [NEW HIGHSCORE]"} else { "" }
-  );
-  println!("Current level:   {}", tetris.current_level);
-}
-
-fn is_time_over(tetris: &Tetris, timer: &SystemTime) -> bool {
-  match timer.elapsed() {
-    Ok(elapsed) => {
-      let millis =
-        elapsed.as_secs() as u32 * 1000 + elapsed.subsec_nanos() / 1_000_000;
-      millis > LEVEL_TIMES[tetris.current_level as usize - 1]
-    }
-    Err(_) => false,
+    // We're running late, don't try to catch up.
+    *next_frame = SystemTime::now();
   }
 }
 
@@ -672,10 +390,10 @@ fn get_rect_from_text(text: &str, x: i32, y: i32) -> Option<Rect> {
   Some(Rect::new(x, y, text.len() as u32 * 20, 30))
 }
 
-fn display_game_information<'a>(
+fn display_game_information(
   tetris: &Tetris,
   canvas: &mut Canvas<Window>,
-  texture_creator: &'a TextureCreator<WindowContext>,
+  texture_creator: &TextureCreator<WindowContext>,
   font: &sdl2::ttf::Font,
   start_x_point: i32
 ) {
@@ -685,19 +403,19 @@ fn display_game_information<'a>(
 
   let score =
     create_texture_from_text(
-      &texture_creator, &font,
+      texture_creator, font,
       &score_text,
       255, 255, 255
     ).expect("Cannot render text");
   let lines_sent =
     create_texture_from_text(
-      &texture_creator, &font,
+      texture_creator, font,
       &lines_sent_text,
       255, 255, 255
     ).expect("Cannot render text");
   let level =
     create_texture_from_text(
-      &texture_creator, &font,
+      texture_creator, font,
       &level_text,
       255, 255, 255
     ).expect("Cannot render text");
@@ -719,207 +437,1796 @@ fn display_game_information<'a>(
     ).expect("Couldn't copy text");
 }
 
-fn display_game_map(
+fn display_game_map<L: AsRef<[u8]>>(
   grid_x: i32,
   grid_y: i32,
-  game_map: &[Vec<u8>],
+  game_map: &[L],
   canvas: &mut Canvas<Window>,
   textures: &[Texture]
+) {
+  display_game_map_scaled(
+    grid_x, grid_y, game_map, canvas, textures, TETRIS_HEIGHT as u32
+  );
+}
+
+fn display_game_map_scaled<L: AsRef<[u8]>>(
+  grid_x: i32,
+  grid_y: i32,
+  game_map: &[L],
+  canvas: &mut Canvas<Window>,
+  textures: &[Texture],
+  case_size: u32
 ) {
   for (line_nb, line) in game_map.iter().enumerate() {
-    for (case_nb, case) in line.iter().enumerate() {
+    for (case_nb, case) in line.as_ref().iter().enumerate() {
       if *case == 0 {
         continue
       }
       canvas.copy(
         &textures[*case as usize - 1], None,
         Rect::new(
-          grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
-          grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
-          TETRIS_HEIGHT as u32,
-          TETRIS_HEIGHT as u32
+          grid_x + case_nb as i32 * case_size as i32,
+          grid_y + line_nb as i32 * case_size as i32,
+          case_size,
+          case_size
         )
       ).expect("Couldn't copy texture into window");
     }
   }
 }
 
-fn main() {
-  let sdl_context = sdl2::init().expect("SDL initialization failed");
-  let video_subsystem =
-    sdl_context.video().expect("Couldn't get SDL video subsystem");
-  let width = 600;
-  let height = 800;
-  let mut timer = SystemTime::now();
-  let mut event_pump =
-    sdl_context.event_pump().expect("Failed to get SDL event pump");
+fn display_board(screen: &mut Screen, tetris: &Tetris, offset_x: i32) {
+  let grid_x = offset_x + 20;
+  let grid_y = (screen.height - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
+  let canvas = &mut screen.canvas;
+  let textures = &screen.textures;
+
+  canvas.copy(
+    &textures.border, None,
+    Rect::new(
+      grid_x - 10,
+      grid_y - 10,
+      TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20
+    )
+  ).expect("Couldn't copy texture into window");
+  canvas.copy(
+    &textures.grid,
+    None,
+    Rect::new(
+      grid_x,
+      grid_y,
+      TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16
+    )
+  ).expect("Couldn't copy texture into window");
+
+  if let Some(ref piece) = tetris.current_piece {
+    let ghost_y = tetris.ghost_y().unwrap_or(piece.y);
+    display_game_map(
+      grid_x + piece.x as i32 * TETRIS_HEIGHT as i32,
+      grid_y + ghost_y as i32 * TETRIS_HEIGHT as i32,
+      &piece.states[piece.current_state as usize],
+      canvas,
+      &textures.ghosts
+    );
+    display_game_map(
+      grid_x + piece.x as i32 * TETRIS_HEIGHT as i32,
+      grid_y + piece.y as i32 * TETRIS_HEIGHT as i32,
+      &piece.states[piece.current_state as usize],
+      canvas,
+      &textures.pieces
+    );
+  }
+  if let Some(ref piece) = tetris.next_piece {
+    display_game_map(
+      offset_x + 435,
+      205,
+      &piece.states[piece.current_state as usize],
+      canvas,
+      &textures.pieces
+    );
+  }
+  if let Some(ref piece) = tetris.held_piece {
+    let textures = if tetris.can_hold {
+      &textures.pieces
+    } else {
+      &textures.ghosts
+    };
+    display_game_map(
+      offset_x + 435,
+      400,
+      &piece.states[piece.current_state as usize],
+      canvas,
+      textures
+    );
+  }
 
-  let grid_x = 20; // (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2;
-  let grid_y = (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
-  let mut tetris = Tetris::new();
+  display_game_information(
+    tetris,
+    canvas,
+    screen.texture_creator,
+    &screen.font,
+    offset_x + 430 // width as i32 - grid_x - 10
+  );
 
-  let window = video_subsystem.window("Tetris", width, height)
-    .position_centered().build().expect("Failed to create window");
+  display_game_map(grid_x, grid_y, &tetris.game_map, canvas, &textures.pieces);
+}
 
-  let mut canvas = window.into_canvas()
-    .target_texture().present_vsync().build()
-    .expect("Couldn't get window's canvas");
+// A board without the game information, for the other players of a network
+// game.
+fn display_small_board(
+  screen: &mut Screen,
+  tetris: &Tetris,
+  name: &str,
+  x: i32,
+  y: i32
+) {
+  let case_size = SMALL_CASE_SIZE;
+  let canvas = &mut screen.canvas;
+  let textures = &screen.textures;
+
+  canvas.copy(
+    &textures.border, None,
+    Rect::new(x - 5, y - 5, case_size * 10 + 10, case_size * 16 + 10)
+  ).expect("Couldn't copy texture into window");
+  canvas.copy(
+    &textures.grid, None,
+    Rect::new(x, y, case_size * 10, case_size * 16)
+  ).expect("Couldn't copy texture into window");
+  if let Some(ref piece) = tetris.current_piece {
+    display_game_map_scaled(
+      x + piece.x as i32 * case_size as i32,
+      y + piece.y as i32 * case_size as i32,
+      &piece.states[piece.current_state as usize],
+      canvas,
+      &textures.pieces,
+      case_size
+    );
+  }
+  display_game_map_scaled(
+    x, y, &tetris.game_map, canvas, &textures.pieces, case_size
+  );
 
-  let texture_creator: TextureCreator<_> = canvas.texture_creator();
+  let label = create_texture_from_text(
+    screen.texture_creator, &screen.font, name, 255, 255, 255
+  ).expect("Cannot render text");
+  canvas.copy(
+    &label, None,
+    Rect::new(x, y + case_size as i32 * 16 + 10, name.len() as u32 * 10, 15)
+  ).expect("Couldn't copy text");
+}
 
-  let grid = create_texture_square(
-    &mut canvas,
-    &texture_creator,
-    TextureColor::Black,
-    TETRIS_HEIGHT as u32 * 10
-  ).expect("Failed to create a texture");
+// Position of the small board of the `nb`th other player.
+fn small_board_position(nb: usize) -> (i32, i32) {
+  (
+    BOARD_WIDTH as i32 + (nb / 2) as i32 * SMALL_BOARD_WIDTH as i32 + 20,
+    60 + (nb % 2) as i32 * 380
+  )
+}
 
-  let border = create_texture_square(
-    &mut canvas,
-    &texture_creator,
-    TextureColor::White,
-    TETRIS_HEIGHT as u32 * 10 + 20
-  ).expect("Failed to create a texture");
+fn window_width(nb_players: usize) -> u32 {
+  if nb_players <= 2 {
+    BOARD_WIDTH * nb_players as u32
+  } else {
+    BOARD_WIDTH + (nb_players as u32 / 2) * SMALL_BOARD_WIDTH
+  }
+}
 
-  macro_rules! texture {
-    ($r:expr, $g:expr, $b:expr) => (
-      create_texture_rect(
-        &mut canvas,
-        &texture_creator,
-        $r, $g, $b,
-        TETRIS_HEIGHT as u32,
-        TETRIS_HEIGHT as u32
-      ).unwrap()
+fn display_versus_result(screen: &mut Screen, results: &[&str]) {
+  for (nb, text) in results.iter().enumerate() {
+    let (r, g, b) = match *text {
+      "YOU WIN" => (45, 216, 47),
+      "YOU LOSE" => (255, 69, 69),
+      _ => (255, 255, 255),
+    };
+    let result = create_texture_from_text(
+      screen.texture_creator, &screen.font, text, r, g, b
+    ).expect("Cannot render text");
+    screen.canvas.copy(
+      &result, None,
+      get_rect_from_text(
+        text,
+        nb as i32 * BOARD_WIDTH as i32 + 220 - text.len() as i32 * 10,
+        screen.height as i32 / 2 - 15
+      )
+    ).expect("Couldn't copy text");
+  }
+  let continue_text = "Press Enter";
+  let continue_texture = create_texture_from_text(
+    screen.texture_creator, &screen.font, continue_text, 255, 255, 255
+  ).expect("Cannot render text");
+  screen.canvas.copy(
+    &continue_texture, None,
+    get_rect_from_text(
+      continue_text,
+      (results.len() as i32 * BOARD_WIDTH as i32 -
+        continue_text.len() as i32 * 20) / 2,
+      screen.height as i32 - 60
     )
+  ).expect("Couldn't copy text");
+}
+
+fn wait_for_key(event_pump: &mut sdl2::EventPump, keycodes: &[Keycode]) {
+  loop {
+    for event in event_pump.poll_iter() {
+      match event {
+        Event::Quit { .. } => return,
+        Event::KeyDown { keycode: Some(keycode), .. }
+          if keycodes.contains(&keycode) => return,
+        _ => {}
+      }
+    }
+    sleep(Duration::new(0, 1_000_000_000u32 / FRAMES_PER_SECOND));
   }
+}
 
-  let textures = [
-    texture!(255, 69, 69),
-    texture!(255, 220, 69),
-    texture!(237, 150, 37),
-    texture!(171, 99, 237),
-    texture!(77, 149, 239),
-    texture!(39, 218, 225),
-    texture!(45, 216, 47),
-  ];
+fn print_versus_information(boards: &[(&str, &Tetris, &str)]) {
+  println!("Game over...");
+  for &(name, tetris, result) in boards {
+    println!("{}: {} (score {}, {} lines)",
+      name,
+      result,
+      tetris.score,
+      tetris.nb_lines
+    );
+  }
+}
 
-  let ghost_textures = [
-    texture!(128, 34, 34),
-    texture!(128, 110, 34),
-    texture!(118, 75, 18),
-    texture!(85, 49, 118),
-    texture!(38, 74, 119),
-    texture!(19, 109, 112),
-    texture!(22, 108, 23),
-  ];
+// A bot plays behind the title until Enter is pressed. Returns false if the
+// player would rather quit.
+fn run_title_screen(screen: &mut Screen, weights: Weights) -> bool {
+  let mut next_frame = SystemTime::now();
+  let mut demo = Tetris::new();
+  let mut bot = Bot::with_weights(3, weights);
 
   loop {
-    if is_time_over(&tetris, &timer) {
-      let mut make_permanent = false;
-      if let Some(ref mut piece) = tetris.current_piece {
-        let x = piece.x;
-        let y = piece.y + 1;
-        make_permanent = !piece.change_position(&tetris.game_map, x, y);
-      }
-      if make_permanent {
-        tetris.make_permanent();
+    for event in screen.event_pump.poll_iter() {
+      match event {
+        Event::Quit { .. } |
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+        Event::KeyDown { keycode: Some(Keycode::Return), .. } => return true,
+        _ => {}
       }
-      timer = SystemTime::now();
     }
 
-    canvas.set_draw_color(Color::RGB(255, 0, 0));
-    canvas.clear();
+    if demo.game_over {
+      demo = Tetris::new();
+    }
+    let inputs = bot.inputs(&demo);
+    demo.step(&inputs);
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &demo, 0);
+    for &(text, y) in &[("TETRIS", 300), ("DEMO", 360), ("Press Enter", 660)] {
+      let texture = create_texture_from_text(
+        screen.texture_creator, &screen.font, text, 255, 255, 255
+      ).expect("Cannot render text");
+      screen.canvas.copy(
+        &texture, None,
+        get_rect_from_text(text, 220 - text.len() as i32 * 10, y)
+      ).expect("Couldn't copy text");
+    }
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
 
-    canvas.copy(
-      &border, None,
-      Rect::new(
-        10,
-        (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2 - 10,
-        TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20
-      )
-    ).expect("Couldn't copy texture into window");
-    canvas.copy(
-      &grid,
-      None,
+// Where a piece should go, drawn in the grid like a ghost, with a label
+// under the grid.
+fn display_target(
+  screen: &mut Screen,
+  cells: &[(isize, usize)],
+  color: u8,
+  label: &str,
+  offset_x: i32
+) {
+  let grid_x = offset_x + 20;
+  let grid_y = (screen.height - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
+  for &(x, y) in cells {
+    screen.canvas.copy(
+      &screen.textures.ghosts[color as usize - 1], None,
       Rect::new(
-        20,
-        (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2,
-        TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16
+        grid_x + x as i32 * TETRIS_HEIGHT as i32,
+        grid_y + y as i32 * TETRIS_HEIGHT as i32,
+        TETRIS_HEIGHT as u32,
+        TETRIS_HEIGHT as u32
       )
     ).expect("Couldn't copy texture into window");
+  }
+  let label_y = screen.height as i32 - 40;
+  display_label(screen, label, grid_x, label_y);
+}
 
-    if tetris.current_piece.is_none() {
-      let current_piece = tetris.next_piece.unwrap_or(
-        Tetris::create_new_tetrimino()
-      );
-      tetris.next_piece = Some(Tetris::create_new_tetrimino());
-      if !current_piece.test_current_position(&tetris.game_map) {
-        print_game_information(&tetris);
-        break
+// Solves in the background, the game goes on meanwhile.
+fn start_hint(tetris: &Tetris) -> Receiver<Option<Move>> {
+  let (sender, receiver) = mpsc::channel();
+  let tetris = tetris.clone();
+  thread::spawn(move || {
+    sender.send(perfect_clear::hint(&tetris, HINT_LINES)).ok();
+  });
+  receiver
+}
+
+// A single player game can be a saved game going on, with its recording so
+// far. A `ranked` game goes on the leaderboard, and is saved when left
+// before it's over. The replay is saved under `mode`.
+fn run_local_game(
+  screen: &mut Screen,
+  mode: &str,
+  mut players: Vec<Player>,
+  resumed: Option<Recording>,
+  ranked: bool
+) {
+  let versus = 1 < players.len();
+  let mut next_frame = SystemTime::now();
+  // H shows where to play for a perfect clear and F prints the board as a
+  // fumen, alone on the keyboard.
+  let can_hint = !versus && players[0].bot.is_none();
+  let mut hints = false;
+  let mut hint = None;
+  let mut hinted_piece = None;
+  let mut solving: Option<Receiver<Option<Move>>> = None;
+  let mut finesse = Finesse::new();
+  let mut recordings = players.iter().enumerate().map(|(nb, player)| {
+    let name = if versus { format!("Player {}", nb + 1) } else {
+      "Player".to_owned()
+    };
+    Recording::starting(&name, &player.tetris)
+  }).collect::<Vec<_>>();
+  let resuming = resumed.is_some();
+  if let Some(recording) = resumed {
+    recordings[0] = recording;
+  }
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let garbage = players.iter_mut()
+      .map(|player| player.tetris.take_garbage())
+      .collect::<Vec<_>>();
+    for (nb, player) in players.iter_mut().enumerate() {
+      let mut inputs = handle_events(&player.keys, &mut quit, &events);
+      if let Some(ref mut bot) = player.bot {
+        inputs = bot.inputs(&player.tetris);
+      }
+      if versus && 0 < garbage[1 - nb] {
+        inputs.push(Input::Garbage(garbage[1 - nb]));
+      }
+      if can_hint {
+        finesse.step(&mut player.tetris, &inputs);
+      } else {
+        player.tetris.step(&inputs);
       }
-      tetris.current_piece = Some(current_piece);
+      recordings[nb].record(&inputs);
     }
-    let mut quit = false;
-    if !handle_events(&mut tetris, &mut quit, &mut timer, &mut event_pump) {
-      if let Some(ref mut piece) = tetris.current_piece {
-        let mut ghost_y = 0;
-        while true == piece.test_position(
-          &tetris.game_map,
-          piece.current_state as usize,
-          piece.x,
-          piece.y + ghost_y
-        ) {
-          ghost_y += 1;
+    if quit {
+      if ranked && !players[0].tetris.game_over {
+        let saved = SavedGame::new(&players[0].tetris, &recordings[0]);
+        match saved.save() {
+          Ok(_) => {
+            println!(
+              "Game saved to {}, it goes on next time",
+              SavedGame::path().display()
+            );
+            return;
+          }
+          Err(e) => println!("Couldn't save the game: {}", e),
         }
-        ghost_y -= 1;
-        display_game_map(
-          grid_x + piece.x as i32 * TETRIS_HEIGHT as i32,
-          grid_y + (ghost_y + piece.y) as i32 * TETRIS_HEIGHT as i32,
-          &piece.states[piece.current_state as usize],
-          &mut canvas,
-          &ghost_textures
-        );
-        display_game_map(
-          grid_x + piece.x as i32 * TETRIS_HEIGHT as i32,
-          grid_y + piece.y as i32 * TETRIS_HEIGHT as i32,
-          &piece.states[piece.current_state as usize],
-          &mut canvas,
-          &textures
-        );
       }
-      if let Some(ref mut piece) = tetris.next_piece {
-        display_game_map(
-          435,
-          205,
-          &piece.states[piece.current_state as usize],
-          &mut canvas,
-          &textures
-        );
+      break
+    }
+    if can_hint {
+      let toggled = events.iter().any(|event| {
+        matches!(*event, Event::KeyDown { keycode: Some(Keycode::H), .. })
+      });
+      if toggled {
+        hints = !hints;
+        hinted_piece = None;
+      }
+      let export = events.iter().any(|event| {
+        matches!(*event, Event::KeyDown { keycode: Some(Keycode::F), .. })
+      });
+      if export {
+        println!("{}", fumen::encode(&[Page::of_game(&players[0].tetris)]));
+      }
+      let tetris = &players[0].tetris;
+      if !hints || tetris.current_piece.is_none() {
+        hint = None;
+      } else if hinted_piece != Some((tetris.nb_pieces, tetris.can_hold)) {
+        // Again after a hold too.
+        hinted_piece = Some((tetris.nb_pieces, tetris.can_hold));
+        hint = None;
+        solving = Some(start_hint(tetris));
+      }
+      if let Some(found) = solving.as_ref()
+        .and_then(|receiver| receiver.try_recv().ok())
+      {
+        hint = found;
+        solving = None;
       }
     }
-    if quit {
-      print_game_information(&tetris);
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    for (nb, player) in players.iter().enumerate() {
+      display_board(screen, &player.tetris, nb as i32 * BOARD_WIDTH as i32);
+    }
+    if let Some(ref hint) = hint {
+      let cells = movegen::cells(&hint.piece);
+      display_target(screen, &cells, hint.piece.color(), "PC hint", 0);
+    }
+    if can_hint {
+      display_label(screen, &format!("Faults: {}", finesse.faults), 430, 520);
+    }
+    let game_over = players.iter().any(|player| player.tetris.game_over);
+    if game_over && versus {
+      display_versus_result(screen, &versus_results(&players));
+    }
+    screen.canvas.present();
+    if game_over {
       break
     }
 
-    let ttf_context = sdl2::ttf::init()
-      .expect("SDL TTF initialization failed");
-    let mut font = ttf_context.load_font("assets/lucon.ttf", 128)
-      .expect("Couldn't load the font");
-    font.set_style(sdl2::ttf::STYLE_BOLD);
+    wait_for_next_frame(&mut next_frame);
+  }
 
-     display_game_information(
-       &tetris,
-       &mut canvas,
-       &texture_creator,
-       &font,
-       430 // width as i32 - grid_x - 10
-     );
+  // The saved game is over, it can't be resumed again.
+  if resuming {
+    if let Err(e) = SavedGame::remove() {
+      println!("Couldn't remove {}: {}", SavedGame::path().display(), e);
+    }
+  }
+  for (recording, player) in recordings.iter_mut().zip(players.iter()) {
+    recording.finish(&player.tetris);
+  }
+  let replay = save_replay(&Replay::new(mode, recordings));
+  if versus {
+    let results = versus_results(&players);
+    if players.iter().any(|player| player.tetris.game_over) {
+      wait_for_key(&mut screen.event_pump, &[Keycode::Return, Keycode::Escape]);
+    }
+    print_versus_information(&[
+      ("Player 1", &players[0].tetris, results[0]),
+      ("Player 2", &players[1].tetris, results[1]),
+    ]);
+  } else {
+    print_game_information(&players[0].tetris);
+    if ranked {
+      record_score(screen, &players[0].tetris, "single", replay, None);
+    }
+    if can_hint {
+      println!(
+        "Finesse faults:  {} in {} pieces", finesse.faults, finesse.pieces
+      );
+    }
+  }
+}
 
-    display_game_map(grid_x, grid_y, &tetris.game_map, &mut canvas, &textures);
-    canvas.present();
+fn versus_results(players: &[Player]) -> Vec<&'static str> {
+  let all_over = players.iter().all(|player| player.tetris.game_over);
+  players.iter().map(|player| {
+    if all_over {
+      "DRAW"
+    } else if player.tetris.game_over {
+      "YOU LOSE"
+    } else {
+      "YOU WIN"
+    }
+  }).collect()
+}
 
-    sleep(Duration::new(0, 1_000_000_000u32 / 60));
+fn outcome_text(outcome: Outcome) -> &'static str {
+  match outcome {
+    Outcome::Win => "YOU WIN",
+    Outcome::Lose => "YOU LOSE",
+    Outcome::Draw => "DRAW",
+    Outcome::Disconnected => "OPPONENT LEFT",
   }
 }
 
+fn run_network_game(screen: &mut Screen, mut session: Session) {
+  let keys = KeyLayout::arrows();
+  let mut next_frame = SystemTime::now();
+  let mut outcome = None;
+  let mut recording =
+    Recording::new(&session.local_name, session.local.seed);
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let inputs = handle_events(&keys, &mut quit, &events);
+    if quit && outcome.is_none() {
+      // The others will see us disconnect and we lose by forfeit.
+      session.quit();
+      recording.finish(&session.local);
+      let replay = save_replay(&Replay::new("network", vec![recording]));
+      print_game_information(&session.local);
+      let name = Some(session.local_name.as_str());
+      record_score(screen, &session.local, "network", replay, name);
+      return;
+    }
+    let done = events.iter().any(|event| {
+      matches!(*event, Event::KeyDown { keycode: Some(Keycode::Return), .. })
+    });
+    if outcome.is_some() && (quit || done) {
+      break
+    }
+
+    if outcome.is_none() && !session.local.game_over {
+      recording.record(&session.step(inputs));
+    }
+    session.sync();
+    if outcome.is_none() {
+      outcome = session.outcome();
+    }
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &session.local, 0);
+    let two_players = 2 == session.nb_players();
+    for (nb, peer) in session.peers.iter().filter_map(|peer| peer.as_ref())
+      .enumerate()
+    {
+      if two_players {
+        display_board(screen, peer.board.predicted(), BOARD_WIDTH as i32);
+      } else {
+        let (x, y) = small_board_position(nb);
+        display_small_board(screen, peer.board.predicted(), &peer.name, x, y);
+      }
+    }
+    if let Some(outcome) = outcome {
+      display_versus_result(screen, &[outcome_text(outcome)]);
+    }
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+
+  session.quit();
+  recording.finish(&session.local);
+  let replay = save_replay(&Replay::new("network", vec![recording]));
+  print_versus_information(&[(
+    &session.local_name,
+    &session.local,
+    outcome.map(outcome_text).unwrap_or("")
+  )]);
+  let name = Some(session.local_name.as_str());
+  record_score(screen, &session.local, "network", replay, name);
+  let peers = session.peers.iter().filter_map(|peer| peer.as_ref());
+  println!("Rollbacks:       {}",
+    peers.map(|peer| peer.board.rollbacks).sum::<u32>()
+  );
+}
+
+fn run_spectator(screen: &mut Screen, mut spectator: Spectator) {
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let quit = events.iter().any(|event| {
+      matches!(
+        *event,
+        Event::Quit { .. } |
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } |
+        Event::KeyDown { keycode: Some(Keycode::Return), .. }
+      )
+    });
+    if quit {
+      break
+    }
+
+    spectator.update();
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    let two_players = 2 == spectator.boards.len();
+    for (nb, board) in spectator.boards.iter().enumerate() {
+      if two_players || 0 == nb {
+        display_board(screen, &board.tetris, nb as i32 * BOARD_WIDTH as i32);
+      } else {
+        let (x, y) = small_board_position(nb - 1);
+        display_small_board(screen, &board.tetris, &board.name, x, y);
+      }
+    }
+    let winner = spectator.winner().map(|winner| match winner {
+      Some(name) => format!("{} WINS", name.to_uppercase()),
+      None => "DRAW".to_owned(),
+    });
+    if let Some(ref winner) = winner {
+      display_versus_result(screen, &[winner]);
+    }
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+
+  spectator.quit();
+  let boards = spectator.boards.iter()
+    .map(|board| (board.name.as_str(), &board.tetris, ""))
+    .collect::<Vec<_>>();
+  print_versus_information(&boards);
+}
+
+// The biggest miniature boards that fit the opponents, and how many of them
+// go on a row.
+fn royale_layout(nb_opponents: usize) -> (u32, u32) {
+  let space = ROYALE_WIDTH - 20;
+  for case_size in (3..13).rev() {
+    let columns = space / (case_size * 10 + 8);
+    let rows = space / (case_size * 16 + 8);
+    if nb_opponents as u32 <= columns * rows {
+      return (case_size, columns);
+    }
+  }
+  (2, space / 28)
+}
+
+fn display_mini_board(
+  screen: &mut Screen,
+  tetris: &Tetris,
+  x: i32,
+  y: i32,
+  case_size: u32,
+  outline: Color
+) {
+  let width = case_size * 10;
+  let height = case_size * 16;
+  let canvas = &mut screen.canvas;
+  canvas.set_draw_color(outline);
+  canvas.fill_rect(Rect::new(x - 2, y - 2, width + 4, height + 4))
+    .expect("Couldn't draw the outline");
+  canvas.copy(&screen.textures.grid, None, Rect::new(x, y, width, height))
+    .expect("Couldn't copy texture into window");
+  if tetris.game_over {
+    // Knocked out.
+    canvas.draw_line((x, y), (x + width as i32, y + height as i32))
+      .expect("Couldn't draw a line");
+    canvas.draw_line((x + width as i32, y), (x, y + height as i32))
+      .expect("Couldn't draw a line");
+    return;
+  }
+  if let Some(ref piece) = tetris.current_piece {
+    display_game_map_scaled(
+      x + piece.x as i32 * case_size as i32,
+      y + piece.y as i32 * case_size as i32,
+      &piece.states[piece.current_state as usize],
+      canvas,
+      &screen.textures.pieces,
+      case_size
+    );
+  }
+  display_game_map_scaled(
+    x, y, &tetris.game_map, canvas, &screen.textures.pieces, case_size
+  );
+}
+
+fn display_label(screen: &mut Screen, text: &str, x: i32, y: i32) {
+  let label = create_texture_from_text(
+    screen.texture_creator, &screen.font, text, 255, 255, 255
+  ).expect("Cannot render text");
+  screen.canvas.copy(
+    &label, None,
+    Rect::new(x, y, text.len() as u32 * 10, 18)
+  ).expect("Couldn't copy text");
+}
+
+fn display_royale(screen: &mut Screen, royale: &Royale) {
+  let human = royale.human();
+  display_board(screen, &human.tetris, 0);
+  let texts = [
+    format!("Alive: {}", royale.nb_alive()),
+    format!("Badges: {}", human.badges),
+    format!("Bonus: {}%", human.badge_bonus()),
+    format!("Target: {}", human.strategy.name()),
+    "1 random".to_owned(),
+    "2 attackers".to_owned(),
+    "3 KOs".to_owned(),
+    "4 badges".to_owned(),
+  ];
+  for (nb, text) in texts.iter().enumerate() {
+    display_label(screen, text, 430, 570 + nb as i32 * 25);
+  }
+
+  let opponents = &royale.players[1..];
+  let (case_size, columns) = royale_layout(opponents.len());
+  let attackers = royale.attackers(0);
+  for (nb, opponent) in opponents.iter().enumerate() {
+    let index = nb + 1;
+    let outline = if !opponent.is_alive() {
+      Color::RGB(75, 75, 75)
+    } else if Some(index) == human.target {
+      Color::RGB(255, 220, 69)
+    } else if attackers.contains(&index) {
+      Color::RGB(171, 99, 237)
+    } else {
+      Color::RGB(255, 255, 255)
+    };
+    let column = nb as u32 % columns;
+    let row = nb as u32 / columns;
+    display_mini_board(
+      screen,
+      &opponent.tetris,
+      (BOARD_WIDTH + 10 + column * (case_size * 10 + 8)) as i32,
+      (10 + row * (case_size * 16 + 8)) as i32,
+      case_size,
+      outline
+    );
+  }
+}
+
+fn run_royale(screen: &mut Screen, mut royale: Royale) {
+  let keys = KeyLayout::arrows();
+  let mut next_frame = SystemTime::now();
+  let mut recording = Recording::new("Player", royale.human().tetris.seed);
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let inputs = handle_events(&keys, &mut quit, &events);
+    let finished = !royale.human().is_alive() || royale.winner().is_some();
+    for event in &events {
+      let strategy = match *event {
+        Event::KeyDown { keycode: Some(Keycode::Num1), .. } => Strategy::Random,
+        Event::KeyDown { keycode: Some(Keycode::Num2), .. } => {
+          Strategy::Attackers
+        }
+        Event::KeyDown { keycode: Some(Keycode::Num3), .. } => Strategy::KOs,
+        Event::KeyDown { keycode: Some(Keycode::Num4), .. } => Strategy::Badges,
+        Event::KeyDown { keycode: Some(Keycode::Return), .. } if finished => {
+          quit = true;
+          continue
+        }
+        _ => continue,
+      };
+      royale.set_strategy(0, strategy);
+    }
+    if quit {
+      break
+    }
+
+    // Once knocked out, the others keep playing until Enter is pressed.
+    let alive = royale.human().is_alive();
+    let inputs = royale.step(inputs);
+    if alive {
+      recording.record(&inputs);
+    }
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_royale(screen, &royale);
+    if let Some(place) = royale.human().place {
+      let result = if 1 == place {
+        "YOU WIN".to_owned()
+      } else {
+        format!("#{} OF {}", place, royale.players.len())
+      };
+      display_versus_result(screen, &[&result]);
+    }
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+
+  let human = royale.human();
+  recording.finish(&human.tetris);
+  let replay = save_replay(&Replay::new("royale", vec![recording]));
+  print_game_information(&human.tetris);
+  if let Some(place) = human.place {
+    println!("Place:           {} of {}", place, royale.players.len());
+  }
+  println!("Badges:          {}", human.badges);
+  record_score(screen, &human.tetris, "royale", replay, None);
+}
+
+// Returns the name of the replay in the replay directory.
+fn save_replay(replay: &Replay) -> Option<String> {
+  match replay.save_new() {
+    Ok(file_name) => {
+      println!("Replay saved to {}", Replay::path(&file_name).display());
+      Some(file_name)
+    }
+    Err(e) => {
+      println!("Couldn't save the replay: {}", e);
+      None
+    }
+  }
+}
+
+// R starts the opener again.
+fn run_opener(screen: &mut Screen, opener: Opener) {
+  let keys = KeyLayout::arrows();
+  let mut trainer = Trainer::new(opener.clone());
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let inputs = handle_events(&keys, &mut quit, &events);
+    if quit {
+      break
+    }
+    let retry = events.iter().any(|event| {
+      matches!(*event, Event::KeyDown { keycode: Some(Keycode::R), .. })
+    });
+    if retry {
+      trainer = Trainer::new(opener.clone());
+    }
+    // The grid stays as it is after a mistake.
+    let stopped = match trainer.progress {
+      Progress::Deviated(_) => true,
+      _ => trainer.tetris.game_over,
+    };
+    if !stopped {
+      trainer.step(&inputs);
+    }
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &trainer.tetris, 0);
+    let nb_pieces = opener.pieces.len();
+    let color = opener.pieces.get(trainer.step).cloned().unwrap_or(0);
+    let label_y = screen.height as i32 - 40;
+    match trainer.progress {
+      Progress::Playing if trainer.tetris.game_over => {
+        display_label(screen, "Game over, R to retry", 20, label_y);
+      }
+      Progress::Playing => {
+        let label = format!(
+          "{} {}/{}", opener.name, trainer.step + 1, nb_pieces
+        );
+        display_target(screen, &trainer.target_cells(), color, &label, 0);
+      }
+      Progress::Deviated(step) => {
+        let label = format!("Piece {} went elsewhere, R to retry", step + 1);
+        display_target(screen, &trainer.target_cells(), color, &label, 0);
+      }
+      Progress::Done => {
+        let label = format!("{} done! R to retry", opener.name);
+        display_label(screen, &label, 20, label_y);
+      }
+    }
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
+
+// Targets come one after the other, to reach with as few presses as
+// possible.
+fn run_finesse_drill(screen: &mut Screen) {
+  let keys = KeyLayout::arrows();
+  let mut drill = Drill::new(rand::random::<u64>());
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let inputs = handle_events(&keys, &mut quit, &events);
+    if quit {
+      break
+    }
+    drill.step(&inputs);
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &drill.tetris, 0);
+    let result = match drill.last {
+      Some(FinesseOutcome::Perfect) => "Perfect".to_owned(),
+      Some(FinesseOutcome::Fault(placed)) => {
+        format!("{} presses for {}", placed.presses, placed.needed)
+      }
+      Some(FinesseOutcome::Missed) => "Missed".to_owned(),
+      None => "Finesse".to_owned(),
+    };
+    let label = format!(
+      "{}, {}/{}, streak {}",
+      result, drill.perfect, drill.attempts, drill.streak
+    );
+    let cells = drill.target.cells();
+    display_target(screen, &cells, drill.target.piece.color(), &label, 0);
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+  println!(
+    "Finesse drill: {} perfect out of {}", drill.perfect, drill.attempts
+  );
+}
+
+// Up and down pick a puzzle, Enter plays it.
+fn run_puzzle_browser(screen: &mut Screen, puzzles: Vec<Puzzle>) {
+  let mut progress = load_puzzle_progress();
+  let mut selected = 0;
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    for event in screen.event_pump.poll_iter().collect::<Vec<_>>() {
+      match event {
+        Event::Quit { .. } |
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return,
+        Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
+          selected = (selected + puzzles.len() - 1) % puzzles.len();
+        }
+        Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
+          selected = (selected + 1) % puzzles.len();
+        }
+        Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
+          run_puzzle(screen, puzzles[selected].clone(), &mut progress);
+        }
+        _ => {}
+      }
+    }
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_label(screen, "PUZZLES", 20, 20);
+    for (nb, puzzle) in puzzles.iter().enumerate() {
+      let (attempts, solved) = progress.of(&puzzle.name);
+      let line = format!(
+        "{} [{}] {}: {}, {} tries",
+        if nb == selected { ">" } else { " " },
+        if solved { "x" } else { " " },
+        puzzle.name,
+        puzzle.goal.describe(),
+        attempts
+      );
+      display_label(screen, &line, 20, 60 + nb as i32 * 25);
+    }
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
+
+// R starts the puzzle again, Escape leaves it. Every attempt that ends is
+// saved with the progress.
+fn run_puzzle(
+  screen: &mut Screen,
+  puzzle: Puzzle,
+  progress: &mut PuzzleProgress
+) {
+  let keys = KeyLayout::arrows();
+  let mut attempt = Attempt::new(puzzle.clone());
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let inputs = handle_events(&keys, &mut quit, &events);
+    if quit {
+      break
+    }
+    let retry = events.iter().any(|event| {
+      matches!(*event, Event::KeyDown { keycode: Some(Keycode::R), .. })
+    });
+    if retry {
+      attempt = Attempt::new(puzzle.clone());
+    }
+    let playing = Status::Playing == attempt.status;
+    attempt.step(&inputs);
+    if playing && Status::Playing != attempt.status {
+      progress.record(&puzzle.name, Status::Solved == attempt.status);
+      if let Err(e) = progress.save() {
+        println!("Couldn't save the puzzle progress: {}", e);
+      }
+    }
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &attempt.tetris, 0);
+    let label = match attempt.status {
+      Status::Playing => format!(
+        "{}: {}, {} pieces left",
+        puzzle.name, puzzle.goal.describe(), attempt.pieces_left()
+      ),
+      Status::Solved => format!("{} solved! R to retry", puzzle.name),
+      Status::Failed => "Failed, R to retry".to_owned(),
+    };
+    let label_y = screen.height as i32 - 40;
+    display_label(screen, &label, 20, label_y);
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
+
+// Free practice: Z takes the last placement back and Y plays it again, as
+// far back as the start. R starts over and Escape leaves. The game isn't
+// recorded, nor its score kept.
+fn run_practice(screen: &mut Screen, start: Tetris) {
+  let keys = KeyLayout::arrows();
+  let mut practice = Practice::new(start.clone());
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    let events = screen.event_pump.poll_iter().collect::<Vec<_>>();
+    let mut quit = false;
+    let inputs = handle_events(&keys, &mut quit, &events);
+    if quit {
+      break
+    }
+    for event in events.iter() {
+      match *event {
+        Event::KeyDown { keycode: Some(Keycode::R), .. } => {
+          practice = Practice::new(start.clone());
+        }
+        Event::KeyDown { keycode: Some(Keycode::Z), .. } => practice.undo(),
+        Event::KeyDown { keycode: Some(Keycode::Y), .. } => practice.redo(),
+        _ => {}
+      }
+    }
+    practice.step(&inputs);
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &practice.tetris, 0);
+    let label = format!(
+      "{} {} placed, Z undo{}, R restart",
+      if practice.tetris.game_over { "Game over," } else { "Practice," },
+      practice.placements(),
+      if practice.can_redo() { ", Y redo" } else { "" }
+    );
+    let label_y = screen.height as i32 - 40;
+    display_label(screen, &label, 20, label_y);
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
+
+// The case of the grid under the pointer, if any.
+fn grid_case(screen: &Screen, x: i32, y: i32) -> Option<(usize, usize)> {
+  let grid_x = 20;
+  let grid_y = (screen.height - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
+  let (x, y) = (x - grid_x, y - grid_y);
+  if x < 0 || y < 0 {
+    return None;
+  }
+  let (x, y) = (
+    x as usize / TETRIS_HEIGHT,
+    y as usize / TETRIS_HEIGHT,
+  );
+  if x < 10 && y < 16 { Some((x, y)) } else { None }
+}
+
+// The left button paints the cases, the right one erases them. 0 to 8 pick
+// the color, the letters of the pieces fill the queue and Backspace takes
+// the last one back, Space holds the first. Insert and Delete add and remove
+// the row under the pointer, Tab changes the goal. Enter plays from the
+// board, F2 saves it as a puzzle and F3 prints it as a fumen.
+fn run_editor(screen: &mut Screen, mut editor: Editor) {
+  let mut pointer = (0, 0);
+  let mut next_frame = SystemTime::now();
+
+  loop {
+    for event in screen.event_pump.poll_iter().collect::<Vec<_>>() {
+      match event {
+        Event::Quit { .. } |
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return,
+        Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+          pointer = (x, y);
+          let color = match mouse_btn {
+            MouseButton::Left => editor.color,
+            MouseButton::Right => 0,
+            _ => continue,
+          };
+          if let Some((x, y)) = grid_case(screen, x, y) {
+            editor.paint(x, y, color);
+          }
+        }
+        Event::MouseMotion { mousestate, x, y, .. } => {
+          pointer = (x, y);
+          let color = if mousestate.left() {
+            editor.color
+          } else if mousestate.right() {
+            0
+          } else {
+            continue
+          };
+          if let Some((x, y)) = grid_case(screen, x, y) {
+            editor.paint(x, y, color);
+          }
+        }
+        Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+          Keycode::Num0 => editor.color = 0,
+          Keycode::Num1 => editor.color = 1,
+          Keycode::Num2 => editor.color = 2,
+          Keycode::Num3 => editor.color = 3,
+          Keycode::Num4 => editor.color = 4,
+          Keycode::Num5 => editor.color = 5,
+          Keycode::Num6 => editor.color = 6,
+          Keycode::Num7 => editor.color = 7,
+          Keycode::Num8 => editor.color = 8,
+          Keycode::I => editor.push_piece(1),
+          Keycode::L => editor.push_piece(2),
+          Keycode::J => editor.push_piece(3),
+          Keycode::O => editor.push_piece(4),
+          Keycode::S => editor.push_piece(5),
+          Keycode::Z => editor.push_piece(6),
+          Keycode::T => editor.push_piece(7),
+          Keycode::Backspace => editor.pop_piece(),
+          Keycode::Space => editor.toggle_hold(),
+          Keycode::Tab => editor.next_goal(),
+          Keycode::Insert | Keycode::Delete => {
+            if let Some((_, y)) = grid_case(screen, pointer.0, pointer.1) {
+              if Keycode::Insert == keycode {
+                editor.insert_row(y);
+              } else {
+                editor.remove_row(y);
+              }
+            }
+          }
+          Keycode::Return => {
+            run_practice(screen, editor.to_game(rand::random::<u64>()));
+          }
+          Keycode::F2 => match editor.save() {
+            Ok(file_name) => println!("Saved {}", file_name),
+            Err(e) => println!("Couldn't save the puzzle: {}", e),
+          },
+          Keycode::F3 => println!("{}", editor.to_fumen()),
+          _ => {}
+        },
+        _ => {}
+      }
+    }
+
+    // The queue shows as the next piece and the held one.
+    let mut board = editor.to_game(0);
+    board.next_piece = editor.queue.first().cloned()
+      .and_then(Tetrimino::from_color);
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    display_board(screen, &board, 0);
+    let color = match editor.color {
+      0 => "erase",
+      color => piece_letter(color),
+    };
+    display_label(screen, &format!("Paint: {}", color), 430, 520);
+    display_label(screen, &editor.goal.describe(), 430, 545);
+    let label = format!("Queue: {}", editor.describe_queue());
+    let label_y = screen.height as i32 - 40;
+    display_label(screen, &label, 20, label_y);
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
+
+// Space pauses, left and right go 5 seconds back or forward, up and down
+// change the speed, Home restarts.
+fn run_replay(screen: &mut Screen, mut playback: Playback) {
+  let mut next_frame = SystemTime::now();
+  let mut paused = false;
+  // Speed in quarters of the normal speed.
+  let mut speed = 4;
+  let mut quarters = 0;
+  let seek = 5 * FRAMES_PER_SECOND;
+
+  'running: loop {
+    for event in screen.event_pump.poll_iter() {
+      match event {
+        Event::Quit { .. } |
+        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+          break 'running
+        }
+        Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+          Keycode::Space => paused = !paused,
+          Keycode::Left => {
+            let frame = playback.frame.saturating_sub(seek);
+            playback.seek(frame);
+          }
+          Keycode::Right => {
+            let frame = playback.frame + seek;
+            playback.seek(frame);
+          }
+          Keycode::Up => speed = (speed * 2).min(32),
+          Keycode::Down => speed = (speed / 2).max(1),
+          Keycode::Home => playback.seek(0),
+          _ => {}
+        },
+        _ => {}
+      }
+    }
+
+    if !paused {
+      quarters += speed;
+      while 4 <= quarters {
+        playback.step();
+        quarters -= 4;
+      }
+    }
+
+    screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    screen.canvas.clear();
+    let two_players = 2 == playback.boards.len();
+    for (nb, board) in playback.boards.iter().enumerate() {
+      if two_players || 0 == nb {
+        display_board(screen, board, nb as i32 * BOARD_WIDTH as i32);
+      } else {
+        let (x, y) = small_board_position(nb - 1);
+        let name = playback.replay.players[nb].name.clone();
+        display_small_board(screen, board, &name, x, y);
+      }
+    }
+    let status = format!(
+      "{:.1}s / {:.1}s  x{}{}",
+      playback.frame as f64 / FRAMES_PER_SECOND as f64,
+      playback.replay.nb_frames() as f64 / FRAMES_PER_SECOND as f64,
+      speed as f64 / 4.,
+      if paused { "  PAUSED" } else { "" }
+    );
+    let y = screen.height as i32 - 50;
+    display_label(screen, &status, 20, y);
+    screen.canvas.present();
+
+    wait_for_next_frame(&mut next_frame);
+  }
+}
+
+fn has_flag(args: &[String], name: &str) -> bool {
+  args.iter().any(|arg| arg == name)
+}
+
+fn parse_mode(args: &[String]) -> Mode {
+  if has_flag(args, "--host") {
+    Mode::Host(
+      option_value(args, "--host")
+        .and_then(|port| port.parse::<u16>().ok())
+        .unwrap_or(DEFAULT_PORT)
+    )
+  } else if has_flag(args, "--connect") {
+    let address = option_value(args, "--connect").unwrap_or("127.0.0.1");
+    if address.contains(':') {
+      Mode::Connect(address.to_owned())
+    } else {
+      Mode::Connect(format!("{}:{}", address, DEFAULT_PORT))
+    }
+  } else if has_flag(args, "--lobby") {
+    let address = option_value(args, "--lobby")
+      .filter(|address| !address.starts_with("--"))
+      .unwrap_or("127.0.0.1");
+    let address = if address.contains(':') {
+      address.to_owned()
+    } else {
+      format!("{}:{}", address, DEFAULT_LOBBY_PORT)
+    };
+    let name = option_value(args, "--name").map(|name| name.to_owned())
+      .or_else(|| env::var("USER").ok())
+      .unwrap_or_else(|| "player".to_owned());
+    let players = option_value(args, "--players")
+      .and_then(|players| players.parse::<u32>().ok())
+      .unwrap_or(2);
+    if has_flag(args, "--list") {
+      Mode::ListLobby(address)
+    } else if has_flag(args, "--watch") {
+      let game = option_value(args, "--watch")
+        .and_then(|game| game.parse::<u64>().ok())
+        .unwrap_or(0);
+      Mode::Watch { address, game }
+    } else {
+      Mode::Lobby { address, name, players }
+    }
+  } else if has_flag(args, "--opener") {
+    match option_value(args, "--opener") {
+      Some(opener) if !opener.starts_with("--") => {
+        Mode::Opener(opener.to_owned())
+      }
+      _ => Mode::ListOpeners,
+    }
+  } else if has_flag(args, "--scores") {
+    Mode::ListScores(
+      option_value(args, "--scores")
+        .filter(|mode| !mode.starts_with("--"))
+        .map(|mode| mode.to_owned())
+    )
+  } else if has_flag(args, "--puzzle") {
+    Mode::Puzzle(
+      option_value(args, "--puzzle")
+        .filter(|puzzle| !puzzle.starts_with("--"))
+        .map(|puzzle| puzzle.to_owned())
+    )
+  } else if has_flag(args, "--editor") {
+    Mode::Editor(
+      option_value(args, "--editor")
+        .filter(|board| !board.starts_with("--"))
+        .map(|board| board.to_owned())
+    )
+  } else if has_flag(args, "--fumen") {
+    Mode::Fumen {
+      data: option_value(args, "--fumen").unwrap_or("").to_owned(),
+      page: option_value(args, "--page")
+        .and_then(|page| page.parse::<usize>().ok())
+        .unwrap_or(1),
+    }
+  } else if has_flag(args, "--practice") {
+    Mode::Practice
+  } else if has_flag(args, "--finesse") {
+    Mode::FinesseDrill
+  } else if has_flag(args, "--replay") {
+    Mode::Replay(option_value(args, "--replay").unwrap_or("").to_owned())
+  } else if has_flag(args, "--versus") {
+    let bot = if has_flag(args, "--bot") {
+      Some(
+        option_value(args, "--bot")
+          .and_then(|delay| delay.parse::<u32>().ok())
+          .unwrap_or(5)
+      )
+    } else {
+      None
+    };
+    Mode::Versus { bot }
+  } else if has_flag(args, "--royale") {
+    Mode::Royale(
+      option_value(args, "--royale")
+        .and_then(|nb| nb.parse::<usize>().ok())
+        .unwrap_or(49)
+    )
+  } else {
+    Mode::Single
+  }
+}
+
+fn open_session(mode: &Mode) -> io::Result<Session> {
+  match *mode {
+    Mode::Host(port) => {
+      println!("Waiting for an opponent on port {}...", port);
+      let mut connection = Connection::host(port)?;
+      let seed = rand::random::<u64>();
+      net::handshake_host(&mut connection, seed)?;
+      Ok(Session::direct(connection, seed, true))
+    }
+    Mode::Connect(ref address) => {
+      println!("Connecting to {}...", address);
+      let mut connection = Connection::connect(address.as_str())?;
+      let seed = net::handshake_client(&mut connection)?;
+      Ok(Session::direct(connection, seed, false))
+    }
+    Mode::Lobby { ref address, ref name, players } => {
+      println!("Connecting to the lobby at {}...", address);
+      let mut connection = Connection::connect(address.as_str())?;
+      println!("Waiting for a {} players game...", players);
+      let (seed, index, names) =
+        net::join_lobby(&mut connection, name, players)?;
+      println!("Playing against {}", names.iter().enumerate()
+        .filter(|&(nb, _)| nb != index)
+        .map(|(_, name)| name.as_str())
+        .collect::<Vec<_>>().join(", "));
+      Ok(Session::relayed(connection, seed, index, names))
+    }
+    _ => unreachable!(),
+  }
+}
+
+fn print_lobby(address: &str) -> io::Result<()> {
+  let mut connection = Connection::connect(address)?;
+  let (waiting, games) = net::list_lobby(&mut connection)?;
+  connection.send(&Message::Bye).ok();
+  if waiting.is_empty() {
+    println!("Nobody is waiting.");
+  }
+  for (name, players) in waiting {
+    println!("{} is waiting for a {} players game", name, players);
+  }
+  for (game, names) in games {
+    println!("Game {}: {}", game, names.join(" vs "));
+  }
+  Ok(())
+}
+
+fn print_scores(mode: Option<&str>) -> io::Result<()> {
+  let leaderboard =
+    check_loaded("leaderboard", &Leaderboard::path(), Leaderboard::load())?
+      .unwrap_or_default();
+  let modes = match mode {
+    Some(mode) => vec![mode],
+    None => leaderboard.modes(),
+  };
+  for mode in modes {
+    println!("{}", mode);
+    for (nb, entry) in leaderboard.table(mode).iter().enumerate() {
+      println!(
+        "{:>3}. {:<16} {:>7} {:>4} lines  level {:>2}  {}  {:>6}  {}",
+        nb + 1, entry.name, entry.score, entry.lines, entry.level,
+        entry.describe_date(), entry.describe_duration(),
+        entry.replay.as_deref().unwrap_or("")
+      );
+    }
+  }
+  Ok(())
+}
+
+fn print_openers() -> io::Result<()> {
+  for file_name in Opener::list()? {
+    match Opener::load(&file_name).map_err(|e| e.to_string())
+      .and_then(|opener| opener.check().map(|_| opener))
+    {
+      Ok(opener) => {
+        println!("{}: {} pieces ({})", opener.name, opener.pieces.len(),
+          file_name);
+      }
+      Err(e) => println!("{} can't be played: {}", file_name, e),
+    }
+  }
+  Ok(())
+}
+
+fn open_spectator(address: &str, game: u64) -> io::Result<Spectator> {
+  println!("Connecting to the lobby at {}...", address);
+  let mut connection = Connection::connect(address)?;
+  let (seed, names) = net::watch_game(&mut connection, game)?;
+  println!("Watching {}", names.join(" vs "));
+  Ok(Spectator::new(connection, seed, names))
+}
+
+// Older versions only kept the best scores and lines, in scores.txt. They
+// join the single player table, then the file goes to the data directory
+// like the others.
+fn migrate_legacy_scores() -> io::Result<()> {
+  if !Path::new(LEGACY_FILE).is_file() {
+    return Ok(());
+  }
+  let entries = leaderboard::load_legacy(Path::new(LEGACY_FILE))?;
+  let mut leaderboard =
+    check_loaded("leaderboard", &Leaderboard::path(), Leaderboard::load())?
+      .unwrap_or_default();
+  let merged = leaderboard.merge(entries);
+  if 0 < merged.added {
+    leaderboard.save()?;
+    println!("Added {} scores of {} to the leaderboard", merged.added,
+      LEGACY_FILE);
+  }
+  storage::migrate(LEGACY_FILE)?;
+  Ok(())
+}
+
+fn main() {
+  let args = env::args().collect::<Vec<_>>();
+  let mode = parse_mode(&args);
+  // Older versions wrote in the working directory.
+  for name in &[LEADERBOARD_FILE, SAVE_FILE, PROGRESS_FILE, REPLAY_DIRECTORY] {
+    match storage::migrate(name) {
+      Ok(true) => {
+        println!("Moved {} to {}", name, storage::data_path(name).display());
+      }
+      Ok(false) => {}
+      Err(e) => println!("Couldn't move {} to the data directory: {}", name, e),
+    }
+  }
+  if let Err(e) = migrate_legacy_scores() {
+    println!("Couldn't add {} to the leaderboard: {}", LEGACY_FILE, e);
+  }
+  let weights = match option_value(&args, "--weights") {
+    Some(file_name) => match Weights::load(file_name) {
+      Ok(weights) => weights,
+      Err(e) => {
+        println!("Couldn't load the weights {}: {}", file_name, e);
+        return;
+      }
+    },
+    None => Weights::default(),
+  };
+  // An external bot playing instead of the keyboard, or as player 2.
+  let external = match option_value(&args, "--tbp") {
+    Some(command) => match ExternalBot::spawn(command) {
+      Ok(external) => {
+        println!("{} is playing", external.name);
+        Some(external)
+      }
+      Err(e) => {
+        println!("Couldn't start the bot {}: {}", command, e);
+        return;
+      }
+    },
+    None => None,
+  };
+  let session = match mode {
+    Mode::ListLobby(ref address) => {
+      if let Err(e) = print_lobby(address) {
+        println!("Couldn't reach the lobby: {}", e);
+      }
+      return;
+    }
+    Mode::Host(_) | Mode::Connect(_) | Mode::Lobby { .. } => {
+      match open_session(&mode) {
+        Ok(session) => Some(session),
+        Err(e) => {
+          println!("Couldn't start the network game: {}", e);
+          return;
+        }
+      }
+    }
+    _ => None,
+  };
+  let spectator = match mode {
+    Mode::Watch { ref address, game } => {
+      match open_spectator(address, game) {
+        Ok(spectator) => Some(spectator),
+        Err(e) => {
+          println!("Couldn't watch the game: {}", e);
+          return;
+        }
+      }
+    }
+    _ => None,
+  };
+  if let Mode::ListScores(ref mode) = mode {
+    print_scores(mode.as_ref().map(|mode| mode.as_str())).ok();
+    return;
+  }
+  let opener = match mode {
+    Mode::ListOpeners => {
+      if let Err(e) = print_openers() {
+        println!("Couldn't list the openers: {}", e);
+      }
+      return;
+    }
+    Mode::Opener(ref name) => {
+      match Opener::find(name).map_err(|e| e.to_string())
+        .and_then(|opener| opener.check().map(|_| opener))
+      {
+        Ok(opener) => Some(opener),
+        Err(e) => {
+          println!("Couldn't load the opener {}: {}", name, e);
+          return;
+        }
+      }
+    }
+    _ => None,
+  };
+  let setup = match mode {
+    Mode::Fumen { ref data, page } => {
+      let tetris = fumen::decode(data).and_then(|pages| {
+        match pages.get(page.max(1) - 1) {
+          Some(page) => page.to_game(rand::random::<u64>()),
+          None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("only {} pages", pages.len())
+          )),
+        }
+      });
+      match tetris {
+        Ok(tetris) => Some(tetris),
+        Err(e) => {
+          println!("Couldn't load the fumen: {}", e);
+          return;
+        }
+      }
+    }
+    _ => None,
+  };
+  let puzzles = match mode {
+    Mode::Puzzle(ref name) => {
+      let puzzles = match *name {
+        Some(ref name) => Puzzle::find(name).map(|puzzle| vec![puzzle]),
+        None => Puzzle::list(),
+      };
+      match puzzles {
+        Ok(ref puzzles) if puzzles.is_empty() => {
+          println!("No puzzle in {}", PUZZLE_DIRECTORY);
+          return;
+        }
+        Ok(puzzles) => puzzles,
+        Err(e) => {
+          println!("Couldn't load the puzzles: {}", e);
+          return;
+        }
+      }
+    }
+    _ => Vec::new(),
+  };
+  let editor = match mode {
+    Mode::Editor(Some(ref name)) => match Editor::open(name) {
+      Ok(editor) => Some(editor),
+      Err(e) => {
+        println!("Couldn't open the board {}: {}", name, e);
+        return;
+      }
+    },
+    Mode::Editor(None) => Some(Editor::new()),
+    _ => None,
+  };
+  // A single player game left before it was over goes on. The file stays
+  // until the game ends or is saved again, in case it can't be played.
+  let saved = match (&mode, &external) {
+    (&Mode::Single, &None) => {
+      let path = SavedGame::path();
+      match check_loaded("saved game", &path, SavedGame::load()) {
+        Ok(Some(saved)) => {
+          println!("Resuming the game saved in {}", path.display());
+          Some(saved)
+        }
+        _ => None,
+      }
+    }
+    _ => None,
+  };
+  let playback = match mode {
+    Mode::Replay(ref file_name) => match Replay::find(file_name) {
+      Ok(replay) => Some(Playback::new(replay)),
+      Err(e) => {
+        println!("Couldn't load the replay {}: {}", file_name, e);
+        return;
+      }
+    },
+    _ => None,
+  };
+  let nb_boards = match mode {
+    Mode::Single | Mode::Opener(_) | Mode::FinesseDrill => 1,
+    Mode::Practice => 1,
+    Mode::Fumen { .. } | Mode::Puzzle(_) | Mode::Editor(_) => 1,
+    Mode::Replay(_) => {
+      playback.as_ref().map(|playback| playback.boards.len()).unwrap_or(1)
+    }
+    Mode::Watch { .. } => {
+      spectator.as_ref().map(|spectator| spectator.boards.len()).unwrap_or(2)
+    }
+    _ => session.as_ref().map(|session| session.nb_players()).unwrap_or(2),
+  };
+
+  let sdl_context = sdl2::init().expect("SDL initialization failed");
+  let video_subsystem =
+    sdl_context.video().expect("Couldn't get SDL video subsystem");
+  let width = match mode {
+    Mode::Royale(_) => BOARD_WIDTH + ROYALE_WIDTH,
+    _ => window_width(nb_boards),
+  };
+  let height = 800;
+  let event_pump =
+    sdl_context.event_pump().expect("Failed to get SDL event pump");
+
+  let window = video_subsystem.window("Tetris", width, height)
+    .position_centered().build().expect("Failed to create window");
+
+  let mut canvas = window.into_canvas()
+    .target_texture().present_vsync().build()
+    .expect("Couldn't get window's canvas");
+
+  let texture_creator: TextureCreator<_> = canvas.texture_creator();
+
+  let ttf_context = sdl2::ttf::init()
+    .expect("SDL TTF initialization failed");
+  let mut font = ttf_context.load_font("assets/lucon.ttf", 128)
+    .expect("Couldn't load the font");
+  font.set_style(sdl2::ttf::STYLE_BOLD);
+
+  let grid = create_texture_square(
+    &mut canvas,
+    &texture_creator,
+    TextureColor::Black,
+    TETRIS_HEIGHT as u32 * 10
+  ).expect("Failed to create a texture");
+
+  let border = create_texture_square(
+    &mut canvas,
+    &texture_creator,
+    TextureColor::White,
+    TETRIS_HEIGHT as u32 * 10 + 20
+  ).expect("Failed to create a texture");
+
+  macro_rules! texture {
+    ($r:expr, $g:expr, $b:expr) => (
+      create_texture_rect(
+        &mut canvas,
+        &texture_creator,
+        $r, $g, $b,
+        TETRIS_HEIGHT as u32,
+        TETRIS_HEIGHT as u32
+      ).unwrap()
+    )
+  }
+
+  let textures = vec![
+    texture!(255, 69, 69),
+    texture!(255, 220, 69),
+    texture!(237, 150, 37),
+    texture!(171, 99, 237),
+    texture!(77, 149, 239),
+    texture!(39, 218, 225),
+    texture!(45, 216, 47),
+    texture!(150, 150, 150),
+  ];
+
+  let ghost_textures = vec![
+    texture!(128, 34, 34),
+    texture!(128, 110, 34),
+    texture!(118, 75, 18),
+    texture!(85, 49, 118),
+    texture!(38, 74, 119),
+    texture!(19, 109, 112),
+    texture!(22, 108, 23),
+    texture!(75, 75, 75),
+  ];
+
+  let mut screen = Screen {
+    canvas,
+    texture_creator: &texture_creator,
+    font,
+    textures: BoardTextures {
+      grid,
+      border,
+      pieces: textures,
+      ghosts: ghost_textures,
+    },
+    event_pump,
+    height,
+  };
+
+  match mode {
+    Mode::Single => match external {
+      Some(external) => run_local_game(&mut screen, "tbp", vec![Player {
+        tetris: Tetris::new(),
+        keys: KeyLayout::arrows(),
+        bot: Some(Box::new(external)),
+      }], None, false),
+      None => match saved {
+        Some(saved) => run_local_game(&mut screen, "single", vec![Player {
+          tetris: saved.tetris,
+          keys: KeyLayout::arrows(),
+          bot: None,
+        }], Some(saved.recording), true),
+        None => {
+          if run_title_screen(&mut screen, weights) {
+            run_local_game(&mut screen, "single", vec![Player {
+              tetris: Tetris::new(),
+              keys: KeyLayout::arrows(),
+              bot: None,
+            }], None, true);
+          }
+        }
+      },
+    },
+    Mode::Versus { bot } => {
+      let opponent: Option<Box<dyn Controller>> = match (external, bot) {
+        (Some(mut external), delay) => {
+          external.delay = delay.unwrap_or(5);
+          Some(Box::new(external))
+        }
+        (None, Some(delay)) => {
+          Some(Box::new(Bot::with_weights(delay, weights)))
+        }
+        (None, None) => None,
+      };
+      let (first_keys, second_keys) = if opponent.is_some() {
+        (KeyLayout::arrows(), KeyLayout::wasd())
+      } else {
+        (KeyLayout::wasd(), KeyLayout::arrows_versus())
+      };
+      run_local_game(&mut screen, "versus", vec![
+        Player { tetris: Tetris::new(), keys: first_keys, bot: None },
+        Player { tetris: Tetris::new(), keys: second_keys, bot: opponent },
+      ], None, false);
+    }
+    Mode::Royale(nb_opponents) => run_royale(
+      &mut screen,
+      Royale::new(rand::random::<u64>(), nb_opponents, weights)
+    ),
+    Mode::Replay(_) => run_replay(&mut screen, playback.unwrap()),
+    Mode::Opener(_) => run_opener(&mut screen, opener.unwrap()),
+    Mode::FinesseDrill => run_finesse_drill(&mut screen),
+    Mode::Practice => run_practice(&mut screen, Tetris::new()),
+    Mode::Puzzle(Some(_)) => {
+      let mut progress = load_puzzle_progress();
+      run_puzzle(&mut screen, puzzles[0].clone(), &mut progress);
+    }
+    Mode::Puzzle(None) => run_puzzle_browser(&mut screen, puzzles),
+    Mode::Editor(_) => run_editor(&mut screen, editor.unwrap()),
+    Mode::Fumen { .. } => run_local_game(&mut screen, "fumen", vec![Player {
+      tetris: setup.unwrap(),
+      keys: KeyLayout::arrows(),
+      bot: None,
+    }], None, false),
+    Mode::Watch { .. } => run_spectator(&mut screen, spectator.unwrap()),
+    _ => {
+      let mut session = session.unwrap();
+      let latency = option_value(&args, "--latency")
+        .and_then(|latency| latency.parse::<u64>().ok());
+      let loss = option_value(&args, "--loss")
+        .and_then(|loss| loss.parse::<u32>().ok());
+      if latency.is_some() || loss.is_some() {
+        session.simulate_bad_network(latency.unwrap_or(0), loss.unwrap_or(0));
+      }
+      run_network_game(&mut screen, session);
+    }
+  }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+
+use game::{Input, Line};
+use tetrimino::Tetrimino;
+
+// Where a piece can be locked, and the fewest inputs getting it there from
+// where it entered the grid. The inputs always end with a hard drop.
+#[derive(Clone)]
+pub struct Placement {
+  pub piece: Tetrimino,
+  pub inputs: Vec<Input>,
+}
+
+impl Placement {
+  // The cases taken by the piece once locked.
+  pub fn cells(&self) -> Vec<(isize, usize)> {
+    cells(&self.piece)
+  }
+}
+
+pub fn cells(piece: &Tetrimino) -> Vec<(isize, usize)> {
+  let mut cells = Vec::new();
+  for (shift_y, line) in piece.states[piece.current_state as usize].iter()
+    .enumerate()
+  {
+    for (shift_x, case) in line.iter().enumerate() {
+      if 0 != *case {
+        cells.push((piece.x + shift_x as isize, piece.y + shift_y));
+      }
+    }
+  }
+  cells
+}
+
+// Where the piece lands if hard dropped from its position.
+pub fn drop(game_map: &[Line], mut piece: Tetrimino) -> Tetrimino {
+  while piece.test_position(
+    game_map,
+    piece.current_state as usize,
+    piece.x,
+    piece.y + 1
+  ) {
+    piece.y += 1;
+  }
+  piece
+}
+
+type Position = (isize, usize, u8);
+
+fn position(piece: &Tetrimino) -> Position {
+  (piece.x, piece.y, piece.current_state)
+}
+
+// Moves `piece` with `input`, if it goes anywhere. Soft drops only count when
+// the piece goes down, otherwise they would lock it.
+pub fn apply(game_map: &[Line], piece: &Tetrimino, input: Input)
+  -> Option<Tetrimino>
+{
+  let mut moved = *piece;
+  let changed = match input {
+    Input::Left => moved.change_position(game_map, piece.x - 1, piece.y),
+    Input::Right => moved.change_position(game_map, piece.x + 1, piece.y),
+    Input::SoftDrop => moved.change_position(game_map, piece.x, piece.y + 1),
+    Input::Rotate => {
+      moved.rotate(game_map);
+      moved.current_state != piece.current_state
+    }
+    _ => false,
+  };
+  if changed { Some(moved) } else { None }
+}
+
+// Every position the piece can get to with left, right, rotate and soft
+// drop, in the order they were found, with the one it came from and how.
+fn search(game_map: &[Line], piece: Tetrimino)
+  -> Vec<(Tetrimino, Option<(usize, Input)>)>
+{
+  let moves = [Input::Left, Input::Right, Input::Rotate, Input::SoftDrop];
+  let mut found = vec![(piece, None)];
+  let mut known = HashSet::new();
+  known.insert(position(&piece));
+  let mut current = 0;
+  while current < found.len() {
+    for input in moves.iter() {
+      let moved = match apply(game_map, &found[current].0, *input) {
+        Some(moved) => moved,
+        None => continue,
+      };
+      if known.insert(position(&moved)) {
+        found.push((moved, Some((current, *input))));
+      }
+    }
+    current += 1;
+  }
+  found
+}
+
+// The shortest way to the position `index` of a search.
+fn path(found: &[(Tetrimino, Option<(usize, Input)>)], mut index: usize)
+  -> Vec<Input>
+{
+  let mut inputs = Vec::new();
+  while let Some((previous, input)) = found[index].1 {
+    inputs.push(input);
+    index = previous;
+  }
+  inputs.reverse();
+  inputs
+}
+
+// Every distinct placement reachable by `piece`, tucks and spins included.
+pub fn placements(game_map: &[Line], piece: Tetrimino) -> Vec<Placement> {
+  if !piece.test_current_position(game_map) {
+    return Vec::new();
+  }
+  let mut placements = Vec::new();
+  let mut by_cells = HashSet::new();
+  let found = search(game_map, piece);
+  for (index, &(moved, _)) in found.iter().enumerate() {
+    let landed = drop(game_map, moved);
+    let mut key = cells(&landed);
+    key.sort();
+    // The search finds the shortest ways first.
+    if by_cells.insert(key) {
+      let mut inputs = path(&found, index);
+      inputs.push(Input::HardDrop);
+      placements.push(Placement { piece: landed, inputs });
+    }
+  }
+  placements
+}
+
+// The shortest inputs locking `piece` on the cases of `target`.
+pub fn path_to(
+  game_map: &[Line],
+  piece: Tetrimino,
+  target: &Tetrimino
+) -> Option<Vec<Input>> {
+  let mut goal = cells(target);
+  goal.sort();
+  placements(game_map, piece).into_iter()
+    .find(|placement| {
+      let mut cells = placement.cells();
+      cells.sort();
+      goal == cells
+    })
+    .map(|placement| placement.inputs)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+
+  use game::{Input, Tetris, HEIGHT, WIDTH};
+  use tetrimino::{
+    TetriminoGenerator, TetriminoI, TetriminoJ, TetriminoL, TetriminoO,
+    TetriminoS, TetriminoT, TetriminoZ,
+  };
+  use super::{drop, path_to, placements};
+
+  #[test]
+  fn every_placement_of_an_empty_grid() {
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    let pieces = [
+      TetriminoI::new(), TetriminoJ::new(), TetriminoL::new(),
+      TetriminoO::new(), TetriminoS::new(), TetriminoZ::new(),
+      TetriminoT::new(),
+    ];
+    let counts = pieces.iter().map(|&piece| {
+      let found = placements(&game_map, piece);
+      let mut distinct = HashSet::new();
+      for placement in &found {
+        assert_eq!(Some(&Input::HardDrop), placement.inputs.last());
+        assert!(!placement.inputs.contains(&Input::SoftDrop));
+        let landed = drop(&game_map, placement.piece);
+        assert_eq!(placement.piece.y, landed.y);
+        let mut cells = placement.cells();
+        cells.sort();
+        assert!(distinct.insert(cells));
+      }
+      found.len()
+    }).collect::<Vec<_>>();
+    assert_eq!(vec![17, 34, 34, 9, 17, 17, 34], counts);
+  }
+
+  #[test]
+  fn placements_are_played_by_the_game() {
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    for placement in placements(&game_map, TetriminoO::new()) {
+      let mut tetris = Tetris::with_seed(1);
+      tetris.next_piece = Some(TetriminoO::new());
+      for input in &placement.inputs {
+        tetris.step(&[*input]);
+      }
+      for (x, y) in placement.cells() {
+        assert_eq!(4, tetris.game_map[y][x as usize]);
+      }
+    }
+  }
+
+  #[test]
+  fn pieces_tuck_under_overhangs() {
+    let mut game_map = vec![[0; WIDTH]; HEIGHT];
+    for case in game_map[HEIGHT - 3][3..].iter_mut() {
+      *case = 8;
+    }
+    let mut tucked = TetriminoO::new();
+    tucked.x = 8;
+    tucked.y = HEIGHT - 2;
+    let inputs = path_to(&game_map, TetriminoO::new(), &tucked).unwrap();
+    let soft_drop = inputs.iter().position(|input| Input::SoftDrop == *input)
+      .unwrap();
+    assert!(inputs[soft_drop..].contains(&Input::Right));
+
+    // The overhang itself can't be reached.
+    let mut inside = tucked;
+    inside.y = HEIGHT - 3;
+    assert!(path_to(&game_map, TetriminoO::new(), &inside).is_none());
+  }
+}
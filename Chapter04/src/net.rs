@@ -0,0 +1,730 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime};
+
+use game::Input;
+use rng::Rng;
+
+pub const DEFAULT_PORT: u16 = 7878;
+pub const DEFAULT_LOBBY_PORT: u16 = 7879;
+// Version 4 rotations kick down and version 5 spectators get the boards
+// first, older peers would play or watch differently.
+pub const PROTOCOL_VERSION: u32 = 5;
+const HANDSHAKE_TIMEOUT: u64 = 10;
+// Bytes a connection with buffered writes may have waiting to be sent.
+const MAX_PENDING: usize = 1 << 20;
+// A longer line is refused and the connection closed, before it takes all
+// the memory.
+pub const MAX_LINE: usize = 1 << 20;
+
+// Messages are sent as text, one per line:
+//
+//   HELLO <version> <seed>                  sent by the host, answered by
+//                                           HELLO <version>
+//   INPUTS <ack> <first> <count> <frames>   the inputs of the sender's frames
+//                                           first..first + count, separated
+//                                           by '/', and the last frame of the
+//                                           receiver's game the sender has
+//   BYE                                     the sender is leaving
+//
+// Every INPUTS message repeats all the frames that weren't acknowledged yet,
+// so losing some of them doesn't matter.
+//
+// When playing through the lobby server, clients also use:
+//
+//   JOIN <name> <players>                   wait for a game of that size
+//   LIST                                    ask for the waiting players
+//   WAITING <name>:<players>...             answer to LIST
+//   START <seed> <index> <names...>         the game begins, we are player
+//                                           <index> of the listed ones
+//   TO <index> <message>                    relay a message to a player
+//   FROM <index> <message>                  a message relayed from a player,
+//                                           which isn't a TO or FROM itself
+//   LEFT <index>                            that player disconnected
+//   PLAYING <game>:<name>,<name>...         the games being played, sent
+//                                           after WAITING
+//   WATCH <game>                            watch a game, 0 for the latest;
+//                                           answered by START with an index
+//                                           past the last player, then STATE
+//                                           and FROM with the inputs of every
+//                                           player
+//   STATE <index> <state>                   the board of a player, in the
+//                                           format of the saves with '/' for
+//                                           the line breaks
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+  Hello { version: u32, seed: Option<u64> },
+  Inputs { ack: u32, first: u32, frames: Vec<Vec<Input>> },
+  Bye,
+  Join { name: String, players: u32 },
+  List,
+  Waiting(Vec<(String, u32)>),
+  Start { seed: u64, index: u32, names: Vec<String> },
+  To { index: u32, message: Box<Message> },
+  From { index: u32, message: Box<Message> },
+  Left { index: u32 },
+  Playing(Vec<(u64, Vec<String>)>),
+  Watch { game: u64 },
+  State { index: u32, state: String },
+}
+
+// Names are sent as a single word.
+pub fn sanitize_name(name: &str) -> String {
+  let name = name.chars()
+    .map(|c| if c.is_whitespace() || ':' == c { '_' } else { c })
+    .take(16)
+    .collect::<String>();
+  if name.is_empty() { "player".to_owned() } else { name }
+}
+
+impl Message {
+  pub fn is_inputs(&self) -> bool {
+    match *self {
+      Message::Inputs { .. } => true,
+      Message::To { ref message, .. } |
+      Message::From { ref message, .. } => message.is_inputs(),
+      _ => false,
+    }
+  }
+
+  pub fn encode(&self) -> String {
+    match *self {
+      Message::Hello { version, seed: Some(seed) } => {
+        format!("HELLO {} {}", version, seed)
+      }
+      Message::Hello { version, seed: None } => format!("HELLO {}", version),
+      Message::Inputs { ack, first, ref frames } => {
+        let payload = frames.iter().map(|inputs| {
+          inputs.iter().map(|input| input.encode())
+            .collect::<Vec<_>>().join(",")
+        }).collect::<Vec<_>>().join("/");
+        format!("INPUTS {} {} {} {}", ack, first, frames.len(), payload)
+      }
+      Message::Bye => "BYE".to_owned(),
+      Message::Join { ref name, players } => {
+        format!("JOIN {} {}", sanitize_name(name), players)
+      }
+      Message::List => "LIST".to_owned(),
+      Message::Waiting(ref waiting) => {
+        let mut line = "WAITING".to_owned();
+        for &(ref name, players) in waiting {
+          line.push_str(&format!(" {}:{}", sanitize_name(name), players));
+        }
+        line
+      }
+      Message::Start { seed, index, ref names } => {
+        let names = names.iter().map(|name| sanitize_name(name))
+          .collect::<Vec<_>>().join(" ");
+        format!("START {} {} {}", seed, index, names)
+      }
+      Message::To { index, ref message } => {
+        format!("TO {} {}", index, message.encode())
+      }
+      Message::From { index, ref message } => {
+        format!("FROM {} {}", index, message.encode())
+      }
+      Message::Left { index } => format!("LEFT {}", index),
+      Message::Playing(ref games) => {
+        let mut line = "PLAYING".to_owned();
+        for &(game, ref names) in games {
+          let names = names.iter().map(|name| sanitize_name(name))
+            .collect::<Vec<_>>().join(",");
+          line.push_str(&format!(" {}:{}", game, names));
+        }
+        line
+      }
+      Message::Watch { game } => format!("WATCH {}", game),
+      Message::State { index, ref state } => {
+        format!("STATE {} {}", index, state.trim_end().replace('\n', "/"))
+      }
+    }
+  }
+
+  pub fn decode(line: &str) -> Option<Message> {
+    Message::decode_relayed(line, false)
+  }
+
+  // The message relayed by a TO or a FROM can't be relayed again.
+  fn decode_relayed(line: &str, relayed: bool) -> Option<Message> {
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("HELLO") => {
+        let version = words.next()?.parse::<u32>().ok()?;
+        let seed = match words.next() {
+          Some(seed) => Some(seed.parse::<u64>().ok()?),
+          None => None,
+        };
+        Some(Message::Hello { version, seed })
+      }
+      Some("INPUTS") => {
+        let ack = words.next()?.parse::<u32>().ok()?;
+        let first = words.next()?.parse::<u32>().ok()?;
+        let count = words.next()?.parse::<usize>().ok()?;
+        let mut frames = Vec::new();
+        if 0 < count {
+          for frame in words.next().unwrap_or("").split('/') {
+            let mut inputs = Vec::new();
+            for token in frame.split(',').filter(|token| !token.is_empty()) {
+              inputs.push(Input::decode(token)?);
+            }
+            frames.push(inputs);
+          }
+        }
+        if count != frames.len() {
+          return None;
+        }
+        Some(Message::Inputs { ack, first, frames })
+      }
+      Some("BYE") => Some(Message::Bye),
+      Some("JOIN") => {
+        let name = words.next()?.to_owned();
+        let players = words.next()?.parse::<u32>().ok()?;
+        Some(Message::Join { name, players })
+      }
+      Some("LIST") => Some(Message::List),
+      Some("WAITING") => {
+        let mut waiting = Vec::new();
+        for word in words {
+          let mut parts = word.rsplitn(2, ':');
+          let players = parts.next()?.parse::<u32>().ok()?;
+          waiting.push((parts.next()?.to_owned(), players));
+        }
+        Some(Message::Waiting(waiting))
+      }
+      Some("START") => {
+        let seed = words.next()?.parse::<u64>().ok()?;
+        let index = words.next()?.parse::<u32>().ok()?;
+        let names = words.map(|name| name.to_owned()).collect();
+        Some(Message::Start { seed, index, names })
+      }
+      Some(kind @ "TO") | Some(kind @ "FROM") if !relayed => {
+        let mut parts = line.trim().splitn(3, ' ').skip(1);
+        let index = parts.next()?.parse::<u32>().ok()?;
+        let message = Box::new(Message::decode_relayed(parts.next()?, true)?);
+        if "TO" == kind {
+          Some(Message::To { index, message })
+        } else {
+          Some(Message::From { index, message })
+        }
+      }
+      Some("LEFT") => {
+        let index = words.next()?.parse::<u32>().ok()?;
+        Some(Message::Left { index })
+      }
+      Some("PLAYING") => {
+        let mut games = Vec::new();
+        for word in words {
+          let mut parts = word.splitn(2, ':');
+          let game = parts.next()?.parse::<u64>().ok()?;
+          let names = parts.next()?.split(',')
+            .map(|name| name.to_owned()).collect();
+          games.push((game, names));
+        }
+        Some(Message::Playing(games))
+      }
+      Some("WATCH") => {
+        let game = words.next()?.parse::<u64>().ok()?;
+        Some(Message::Watch { game })
+      }
+      Some("STATE") => {
+        let mut parts = line.trim().splitn(3, ' ').skip(1);
+        let index = parts.next()?.parse::<u32>().ok()?;
+        let state = parts.next()?.replace('/', "\n") + "\n";
+        Some(Message::State { index, state })
+      }
+      _ => None,
+    }
+  }
+}
+
+// Delays and drops outgoing INPUTS messages, to try the netcode on loopback.
+struct Conditioner {
+  latency: Duration,
+  loss_percent: u32,
+  rng: Rng,
+  queue: VecDeque<(SystemTime, String)>,
+}
+
+pub struct Connection {
+  stream: TcpStream,
+  buffer: Vec<u8>,
+  closed: bool,
+  conditioner: Option<Conditioner>,
+  // Whether writes go through `pending` instead of blocking.
+  buffered: bool,
+  pending: Vec<u8>,
+}
+
+impl Connection {
+  // Waits for one opponent to connect on the given port.
+  pub fn host(port: u16) -> io::Result<Connection> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let (stream, _) = listener.accept()?;
+    Connection::from_stream(stream)
+  }
+
+  pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Connection> {
+    let stream = TcpStream::connect(address)?;
+    Connection::from_stream(stream)
+  }
+
+  pub fn from_stream(stream: TcpStream) -> io::Result<Connection> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(Duration::from_secs(HANDSHAKE_TIMEOUT)))?;
+    Ok(Connection {
+      stream,
+      buffer: Vec::new(),
+      closed: false,
+      conditioner: None,
+      buffered: false,
+      pending: Vec::new(),
+    })
+  }
+
+  pub fn simulate_bad_network(&mut self, latency_ms: u64, loss_percent: u32) {
+    self.conditioner = Some(Conditioner {
+      latency: Duration::from_millis(latency_ms),
+      loss_percent,
+      rng: Rng::new(latency_ms ^ u64::from(loss_percent)),
+      queue: VecDeque::new(),
+    });
+  }
+
+  // Writes are kept aside and sent as the socket takes them, so that a
+  // server isn't held up by someone who doesn't read. The connection is
+  // closed once more than `MAX_PENDING` bytes are waiting.
+  pub fn buffer_writes(&mut self) {
+    self.buffered = true;
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed
+  }
+
+  pub fn close(&mut self) {
+    self.flush().ok();
+    self.stream.shutdown(Shutdown::Both).ok();
+    self.closed = true;
+  }
+
+  pub fn send(&mut self, message: &Message) -> io::Result<()> {
+    if self.closed {
+      return Err(io::Error::new(ErrorKind::NotConnected, "connection closed"));
+    }
+    let line = message.encode() + "\n";
+    if message.is_inputs() {
+      if let Some(ref mut conditioner) = self.conditioner {
+        if conditioner.rng.gen_range(100) < conditioner.loss_percent {
+          return Ok(());
+        }
+        let due = SystemTime::now() + conditioner.latency;
+        conditioner.queue.push_back((due, line));
+        return self.flush();
+      }
+    }
+    self.write_line(&line)
+  }
+
+  // Sends the delayed messages whose time has come, and what the socket
+  // takes of the buffered writes.
+  fn flush(&mut self) -> io::Result<()> {
+    let now = SystemTime::now();
+    loop {
+      let line = match self.conditioner {
+        Some(ref mut conditioner) => match conditioner.queue.front() {
+          Some(&(due, _)) if due <= now => conditioner.queue.pop_front(),
+          _ => None,
+        },
+        None => None,
+      };
+      match line {
+        Some((_, line)) => self.write_line(&line)?,
+        None => break,
+      }
+    }
+    if self.buffered && !self.pending.is_empty() {
+      let result = self.write_pending();
+      if result.is_err() {
+        self.closed = true;
+      }
+      return result;
+    }
+    Ok(())
+  }
+
+  fn write_line(&mut self, line: &str) -> io::Result<()> {
+    let result = if self.buffered {
+      self.pending.extend_from_slice(line.as_bytes());
+      self.write_pending()
+    } else {
+      self.stream.set_nonblocking(false)
+        .and_then(|_| self.stream.write_all(line.as_bytes()))
+    };
+    if result.is_err() {
+      self.closed = true;
+    }
+    result
+  }
+
+  fn write_pending(&mut self) -> io::Result<()> {
+    self.stream.set_nonblocking(true)?;
+    while !self.pending.is_empty() {
+      match self.stream.write(&self.pending) {
+        Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "closed")),
+        Ok(size) => {
+          self.pending.drain(..size);
+        }
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+        Err(e) => return Err(e),
+      }
+    }
+    if MAX_PENDING < self.pending.len() {
+      return Err(io::Error::new(ErrorKind::TimedOut, "not reading"));
+    }
+    Ok(())
+  }
+
+  // Blocks until a whole message is received. Used during the handshake.
+  pub fn receive_blocking(&mut self) -> io::Result<Message> {
+    self.stream.set_nonblocking(false)?;
+    self.stream.set_read_timeout(
+      Some(Duration::from_secs(HANDSHAKE_TIMEOUT))
+    )?;
+    let mut chunk = [0; 4096];
+    loop {
+      if let Some(line) = self.pop_line() {
+        return Message::decode(&line).ok_or_else(|| io::Error::new(
+          ErrorKind::InvalidData,
+          format!("bad message: {}", line)
+        ));
+      }
+      if self.closed {
+        return Err(io::Error::new(ErrorKind::InvalidData, "line too long"));
+      }
+      let size = self.stream.read(&mut chunk)?;
+      if 0 == size {
+        self.closed = true;
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "disconnected"));
+      }
+      self.buffer.extend_from_slice(&chunk[..size]);
+    }
+  }
+
+  // Returns every message received so far without blocking. Once the other
+  // side is gone the connection is marked as closed.
+  pub fn receive(&mut self) -> Vec<Message> {
+    let mut messages = Vec::new();
+    if !self.closed {
+      self.flush().ok();
+    }
+    if self.closed || self.stream.set_nonblocking(true).is_err() {
+      self.closed = true;
+      return messages;
+    }
+    let mut chunk = [0; 4096];
+    loop {
+      match self.stream.read(&mut chunk) {
+        Ok(0) => {
+          self.closed = true;
+          break
+        }
+        Ok(size) => {
+          self.buffer.extend_from_slice(&chunk[..size]);
+          // The rest waits until the lines there are taken.
+          if MAX_LINE < self.buffer.len() {
+            break
+          }
+        }
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+        Err(_) => {
+          self.closed = true;
+          break
+        }
+      }
+    }
+    while let Some(line) = self.pop_line() {
+      match Message::decode(&line) {
+        Some(Message::Bye) => {
+          self.closed = true;
+          break
+        }
+        Some(message) => messages.push(message),
+        None => {}
+      }
+    }
+    messages
+  }
+
+  // The next line received. One longer than `MAX_LINE` closes the
+  // connection.
+  fn pop_line(&mut self) -> Option<String> {
+    let end = self.buffer.iter().position(|byte| b'\n' == *byte);
+    let end = match end {
+      Some(end) if end <= MAX_LINE => end,
+      None if self.buffer.len() <= MAX_LINE => return None,
+      _ => {
+        self.closed = true;
+        self.buffer.clear();
+        return None;
+      }
+    };
+    let line = self.buffer.drain(..end + 1).collect::<Vec<_>>();
+    Some(String::from_utf8_lossy(&line).trim_end().to_owned())
+  }
+}
+
+// The host picks the seed and tells the client.
+pub fn handshake_host(
+  connection: &mut Connection,
+  seed: u64
+) -> io::Result<()> {
+  connection.send(&Message::Hello {
+    version: PROTOCOL_VERSION,
+    seed: Some(seed),
+  })?;
+  match connection.receive_blocking()? {
+    Message::Hello { version, .. } if version == PROTOCOL_VERSION => Ok(()),
+    message => Err(io::Error::new(
+      ErrorKind::InvalidData,
+      format!("unexpected answer from client: {:?}", message)
+    )),
+  }
+}
+
+pub fn handshake_client(connection: &mut Connection) -> io::Result<u64> {
+  match connection.receive_blocking()? {
+    Message::Hello { version, seed: Some(seed) }
+      if version == PROTOCOL_VERSION =>
+    {
+      connection.send(&Message::Hello {
+        version: PROTOCOL_VERSION,
+        seed: None,
+      })?;
+      Ok(seed)
+    }
+    message => Err(io::Error::new(
+      ErrorKind::InvalidData,
+      format!("unexpected greeting from host: {:?}", message)
+    )),
+  }
+}
+
+fn lobby_handshake(connection: &mut Connection) -> io::Result<()> {
+  connection.send(&Message::Hello {
+    version: PROTOCOL_VERSION,
+    seed: None,
+  })?;
+  match connection.receive_blocking()? {
+    Message::Hello { version, .. } if version == PROTOCOL_VERSION => Ok(()),
+    message => Err(io::Error::new(
+      ErrorKind::InvalidData,
+      format!("unexpected greeting from lobby: {:?}", message)
+    )),
+  }
+}
+
+// The players waiting in a lobby, with the size of the game they are waiting
+// for.
+pub type WaitingPlayers = Vec<(String, u32)>;
+// The games being played in a lobby, with the names of their players.
+pub type PlayedGames = Vec<(u64, Vec<String>)>;
+
+// Returns the players waiting in the lobby and the games being played.
+pub fn list_lobby(
+  connection: &mut Connection
+) -> io::Result<(WaitingPlayers, PlayedGames)> {
+  lobby_handshake(connection)?;
+  connection.send(&Message::List)?;
+  let mut waiting = None;
+  loop {
+    match connection.receive_blocking()? {
+      Message::Waiting(players) => waiting = Some(players),
+      Message::Playing(games) => {
+        return Ok((waiting.unwrap_or_else(Vec::new), games));
+      }
+      _ => {}
+    }
+  }
+}
+
+// Starts watching a game, 0 for the latest one. Returns the seed and the
+// names of the players.
+pub fn watch_game(
+  connection: &mut Connection,
+  game: u64
+) -> io::Result<(u64, Vec<String>)> {
+  lobby_handshake(connection)?;
+  connection.send(&Message::Watch { game })?;
+  loop {
+    match connection.receive_blocking()? {
+      Message::Start { seed, names, .. } => return Ok((seed, names)),
+      Message::Bye => {
+        return Err(io::Error::new(ErrorKind::NotFound, "no such game"));
+      }
+      _ => {}
+    }
+  }
+}
+
+// Waits in the lobby until a game starts. Returns the seed, our index and
+// the names of all the players.
+pub fn join_lobby(
+  connection: &mut Connection,
+  name: &str,
+  players: u32
+) -> io::Result<(u64, usize, Vec<String>)> {
+  lobby_handshake(connection)?;
+  connection.send(&Message::Join {
+    name: name.to_owned(),
+    players,
+  })?;
+  loop {
+    match connection.receive_blocking() {
+      Ok(Message::Start { seed, index, names }) => {
+        return Ok((seed, index as usize, names));
+      }
+      Ok(_) => {}
+      Err(ref e) if e.kind() == ErrorKind::WouldBlock ||
+        e.kind() == ErrorKind::TimedOut => {}
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+  use std::net::{TcpListener, TcpStream};
+  use std::thread;
+  use std::time::{Duration, SystemTime};
+
+  use game::Input;
+  use super::{
+    handshake_client, handshake_host, Connection, Message, MAX_LINE,
+    MAX_PENDING,
+  };
+
+  // A host and a client connected on loopback, before the handshake.
+  fn connect() -> (Connection, Connection) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = Connection::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    (Connection::from_stream(stream).unwrap(), client)
+  }
+
+  // Receives until `count` messages came or the connection closed.
+  fn receive(connection: &mut Connection, count: usize) -> Vec<Message> {
+    let start = SystemTime::now();
+    let mut messages = Vec::new();
+    while messages.len() < count && !connection.is_closed() {
+      assert!(start.elapsed().unwrap() < Duration::from_secs(10));
+      messages.extend(connection.receive());
+    }
+    messages
+  }
+
+  #[test]
+  fn buffered_writes_never_block() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    // Never reads.
+    let _stalled = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let mut connection = Connection::from_stream(stream).unwrap();
+    connection.buffer_writes();
+    let message = Message::Inputs {
+      ack: 0,
+      first: 1,
+      frames: vec![vec![Input::Left, Input::Rotate]; 1000],
+    };
+    let size = message.encode().len() + 1;
+    let mut sent = 0;
+    loop {
+      // A blocking write would wait for the timeout of the connection.
+      let start = SystemTime::now();
+      let result = connection.send(&message);
+      assert!(start.elapsed().unwrap() < Duration::from_secs(1));
+      if result.is_err() {
+        break
+      }
+      sent += size;
+    }
+    assert!(MAX_PENDING < sent);
+    assert!(connection.is_closed());
+  }
+
+  #[test]
+  fn messages_round_trip() {
+    let messages = vec![
+      Message::Hello { version: 2, seed: Some(42) },
+      Message::Hello { version: 2, seed: None },
+      Message::Inputs { ack: 0, first: 1, frames: Vec::new() },
+      Message::Inputs {
+        ack: 3,
+        first: 4,
+        frames: vec![vec![], vec![Input::HardDrop, Input::Garbage(2)]],
+      },
+      Message::To { index: 1, message: Box::new(Message::Bye) },
+      Message::Bye,
+      Message::State { index: 2, state: "SEED 1\nBOARD\n...\n".to_owned() },
+    ];
+    for message in messages {
+      assert_eq!(Some(message.clone()), Message::decode(&message.encode()));
+    }
+  }
+
+  #[test]
+  fn relayed_messages_are_not_relayed_again() {
+    let to = Message::To { index: 1, message: Box::new(Message::Bye) };
+    let relayed = Message::From { index: 0, message: Box::new(to) };
+    assert_eq!(None, Message::decode(&relayed.encode()));
+    // Would overflow the stack if decoded all the way down.
+    let nested = format!("{}LIST", "TO 0 ".repeat(100_000));
+    assert_eq!(None, Message::decode(&nested));
+  }
+
+  #[test]
+  fn the_client_gets_the_seed_then_the_inputs() {
+    let (mut host, mut client) = connect();
+    let handshake = thread::spawn(move || {
+      handshake_client(&mut client).map(|seed| (seed, client))
+    });
+    handshake_host(&mut host, 42).unwrap();
+    let (seed, mut client) = handshake.join().unwrap().unwrap();
+    assert_eq!(42, seed);
+    let inputs = Message::Inputs {
+      ack: 0,
+      first: 1,
+      frames: vec![vec![Input::Rotate]],
+    };
+    host.send(&inputs).unwrap();
+    host.send(&Message::Bye).unwrap();
+    assert_eq!(vec![inputs], receive(&mut client, 2));
+    assert!(client.is_closed());
+  }
+
+  #[test]
+  fn closed_connections_are_noticed() {
+    let (mut host, client) = connect();
+    drop(client);
+    assert!(receive(&mut host, 1).is_empty());
+    assert!(host.is_closed());
+  }
+
+  #[test]
+  fn lines_too_long_close_the_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut sender =
+      TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let mut connection = Connection::from_stream(stream).unwrap();
+    sender.write_all(b"INPUTS 0 1 0\n").unwrap();
+    let line = vec![b'A'; MAX_LINE + 1];
+    sender.write_all(&line).unwrap();
+    let inputs = Message::Inputs { ack: 0, first: 1, frames: Vec::new() };
+    assert_eq!(vec![inputs], receive(&mut connection, 2));
+    assert!(connection.is_closed());
+  }
+}
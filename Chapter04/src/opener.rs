@@ -0,0 +1,311 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use game::{Input, Line, Tetris, GARBAGE, HEIGHT, WIDTH};
+use movegen::{self, Placement};
+use tetrimino::{color_of_letter, Tetrimino};
+
+pub const OPENER_DIRECTORY: &str = "assets/openers";
+// Marks the cases of the pieces in the layouts, in the order they come.
+const STEP_MARKS: &str = "123456789abcdefghijklmnopqrstuvwxyz";
+// A case taken from the start.
+const PRESET: u8 = 255;
+
+// Opener files are text:
+//
+//   # The first perfect clear.      comments
+//   NAME PCO
+//   PIECES I O L J T S Z I L J      the pieces, in the order they are dealt
+//   LAYOUT                          then the bottom rows of the grid
+//   8aa6633999
+//   8a66223974
+//
+// A row of the layout has `.` for empty cases, `X` for the blocks there
+// from the start and the number of the piece taking the case otherwise,
+// 1 to 9 then a to z. Full rows are cleared like in the game, the rows
+// above them go down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Opener {
+  pub name: String,
+  pub pieces: Vec<u8>,
+  // Every row of the grid, 0 when empty, otherwise PRESET or the number of
+  // the piece from 1.
+  layout: Vec<[u8; WIDTH]>,
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Opener {
+  pub fn decode(content: &str) -> io::Result<Opener> {
+    let mut name = None;
+    let mut pieces = Vec::new();
+    let mut rows = Vec::new();
+    let mut in_layout = false;
+    for line in content.lines() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue
+      }
+      if in_layout {
+        rows.push(line);
+        continue
+      }
+      let mut words = line.splitn(2, ' ');
+      let keyword = words.next().unwrap_or("");
+      let rest = words.next().unwrap_or("").trim();
+      match keyword {
+        "NAME" => name = Some(rest.to_owned()),
+        "PIECES" => {
+          pieces = rest.chars().filter(|letter| !letter.is_whitespace())
+            .map(color_of_letter)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid(format!("bad pieces {}", rest)))?;
+        }
+        "LAYOUT" => in_layout = true,
+        _ => return Err(invalid(format!("unknown line {}", line))),
+      }
+    }
+    let name = name.ok_or_else(|| invalid("no name".to_owned()))?;
+    if STEP_MARKS.len() < pieces.len() {
+      return Err(invalid(format!("more than {} pieces", STEP_MARKS.len())));
+    }
+    if HEIGHT < rows.len() {
+      return Err(invalid(format!("more than {} rows", HEIGHT)));
+    }
+    let mut layout = vec![[0; WIDTH]; HEIGHT - rows.len()];
+    for row in rows {
+      if WIDTH != row.chars().count() {
+        return Err(invalid(format!("{} cases expected: {}", WIDTH, row)));
+      }
+      let mut line = [0; WIDTH];
+      for (x, case) in row.chars().enumerate() {
+        line[x] = match case {
+          '.' => 0,
+          'X' => PRESET,
+          mark => match STEP_MARKS.find(mark) {
+            Some(step) if step < pieces.len() => step as u8 + 1,
+            _ => return Err(invalid(format!("bad case {} in {}", mark, row))),
+          },
+        };
+      }
+      layout.push(line);
+    }
+    for step in 1..pieces.len() + 1 {
+      let cases = layout.iter().flat_map(|line| line.iter())
+        .filter(|case| step as u8 == **case)
+        .count();
+      if 4 != cases {
+        return Err(invalid(format!("piece {} takes {} cases", step, cases)));
+      }
+    }
+    Ok(Opener { name, pieces, layout })
+  }
+
+  pub fn load(file_name: &str) -> io::Result<Opener> {
+    let mut f = File::open(file_name)?;
+    let mut content = String::new();
+    f.read_to_string(&mut content)?;
+    Opener::decode(&content)
+  }
+
+  // An opener file, or the file of `OPENER_DIRECTORY` named after it.
+  pub fn find(name: &str) -> io::Result<Opener> {
+    if Path::new(name).is_file() {
+      return Opener::load(name);
+    }
+    Opener::load(&format!(
+      "{}/{}.txt", OPENER_DIRECTORY, name.to_lowercase().replace(' ', "_")
+    ))
+  }
+
+  // The files of `OPENER_DIRECTORY`, sorted.
+  pub fn list() -> io::Result<Vec<String>> {
+    let mut files = fs::read_dir(OPENER_DIRECTORY)?
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| Some("txt") == path.extension().and_then(|ext| {
+        ext.to_str()
+      }))
+      .filter_map(|path| path.to_str().map(|path| path.to_owned()))
+      .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+  }
+
+  // The grid at the start, with the preset blocks.
+  pub fn start(&self) -> Vec<Line> {
+    self.layout.iter().map(|line| {
+      let mut start = [0; WIDTH];
+      for (x, case) in line.iter().enumerate() {
+        if PRESET == *case {
+          start[x] = GARBAGE;
+        }
+      }
+      start
+    }).collect()
+  }
+
+  // Plays the whole opener as planned, to make sure every piece can get to
+  // its place.
+  pub fn check(&self) -> Result<(), String> {
+    let mut trainer = Trainer::new(self.clone());
+    while Progress::Playing == trainer.progress && !trainer.tetris.game_over {
+      let inputs = match trainer.target_placement() {
+        Some(placement) => placement.inputs,
+        None if trainer.tetris.current_piece.is_none() => Vec::new(),
+        None => {
+          let step = trainer.step + 1;
+          return Err(format!("piece {} can't get to its place", step));
+        }
+      };
+      trainer.step(&inputs);
+    }
+    match trainer.progress {
+      Progress::Done => Ok(()),
+      _ => Err(format!("piece {} didn't end up in place", trainer.step + 1)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+  Playing,
+  // The piece with that index went somewhere else.
+  Deviated(usize),
+  Done,
+}
+
+// Guides the player through an opener: the pieces come in its order, the
+// place of each one is shown, and placing one elsewhere ends the attempt.
+pub struct Trainer {
+  pub opener: Opener,
+  pub tetris: Tetris,
+  // Index of the piece being placed.
+  pub step: usize,
+  pub progress: Progress,
+  // The grid before the piece being placed.
+  board: Vec<Line>,
+  // Rows of the layout cleared so far.
+  cleared: Vec<usize>,
+}
+
+impl Trainer {
+  pub fn new(opener: Opener) -> Trainer {
+    let pieces = opener.pieces.iter()
+      .filter_map(|color| Tetrimino::from_color(*color))
+      .collect::<Vec<_>>();
+    let board = opener.start();
+    Trainer {
+      tetris: Tetris::with_board(0, board.clone(), &pieces),
+      opener,
+      step: 0,
+      progress: Progress::Playing,
+      board,
+      cleared: Vec::new(),
+    }
+  }
+
+  // The cases the current piece has to take, in the grid as it is now.
+  pub fn target_cells(&self) -> Vec<(isize, usize)> {
+    let mut cells = Vec::new();
+    for (y, line) in self.opener.layout.iter().enumerate() {
+      let below = self.cleared.iter().filter(|row| y < **row).count();
+      for (x, case) in line.iter().enumerate() {
+        if self.step as u8 + 1 == *case {
+          cells.push((x as isize, y + below));
+        }
+      }
+    }
+    cells.sort();
+    cells
+  }
+
+  // Where the current piece has to go, and how to get it there.
+  pub fn target_placement(&self) -> Option<Placement> {
+    let piece = self.tetris.current_piece?;
+    if Progress::Done == self.progress {
+      return None;
+    }
+    let target = self.target_cells();
+    movegen::placements(&self.board, piece).into_iter()
+      .find(|placement| {
+        let mut cells = placement.cells();
+        cells.sort();
+        target == cells
+      })
+  }
+
+  // Plays a frame. The hold is off, the pieces have to come in order.
+  pub fn step(&mut self, inputs: &[Input]) {
+    let inputs = inputs.iter().cloned()
+      .filter(|input| Input::Hold != *input)
+      .collect::<Vec<_>>();
+    let piece = self.tetris.nb_pieces;
+    self.tetris.step(&inputs);
+    let locked = self.tetris.current_piece.is_none() &&
+      0 < piece && !self.tetris.game_over;
+    if !locked || Progress::Playing != self.progress {
+      return;
+    }
+    let mut expected = self.board.clone();
+    for (x, y) in self.target_cells() {
+      expected[y][x as usize] = self.opener.pieces[self.step];
+    }
+    if !same_cases(&cleared(&expected), &self.tetris.game_map) {
+      self.progress = Progress::Deviated(self.step);
+      return;
+    }
+    self.clear_layout_rows();
+    self.step += 1;
+    self.board = self.tetris.game_map.clone();
+    if self.opener.pieces.len() <= self.step {
+      self.progress = Progress::Done;
+    }
+  }
+
+  // Notes the rows of the layout the last piece filled.
+  fn clear_layout_rows(&mut self) {
+    for (y, line) in self.opener.layout.iter().enumerate() {
+      let full = line.iter().all(|case| {
+        PRESET == *case || (0 < *case && *case as usize <= self.step + 1)
+      });
+      if full && !self.cleared.contains(&y) {
+        self.cleared.push(y);
+      }
+    }
+  }
+}
+
+fn cleared(game_map: &[Line]) -> Vec<Line> {
+  let mut game_map = game_map.iter()
+    .filter(|line| line.contains(&0))
+    .cloned()
+    .collect::<Vec<_>>();
+  while game_map.len() < HEIGHT {
+    game_map.insert(0, [0; WIDTH]);
+  }
+  game_map
+}
+
+// Whether the same cases are taken, whatever their colors.
+fn same_cases(a: &[Line], b: &[Line]) -> bool {
+  a.iter().zip(b.iter()).all(|(a, b)| {
+    a.iter().zip(b.iter()).all(|(a, b)| (0 == *a) == (0 == *b))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Opener;
+
+  #[test]
+  fn bundled_openers_can_be_played() {
+    for file_name in Opener::list().unwrap() {
+      let opener = Opener::load(&file_name).unwrap();
+      assert_eq!(Ok(()), opener.check(), "{}", file_name);
+    }
+  }
+}
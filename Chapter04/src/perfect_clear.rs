@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+
+use game::{Input, Line, Tetris, HEIGHT, WIDTH};
+use movegen::{self, Placement};
+use tetrimino::Tetrimino;
+
+// Boards tried before giving up, a few seconds.
+pub const MAX_NODES: usize = 50_000;
+// Pieces of the preview the in-game hint knows about.
+pub const HINT_PREVIEW: usize = 6;
+
+// A piece of the solution, held first when `hold` is set.
+#[derive(Clone)]
+pub struct Move {
+  pub hold: bool,
+  // Where the piece ends up.
+  pub piece: Tetrimino,
+  pub inputs: Vec<Input>,
+}
+
+// A board with the height left, the pieces in the grid and aside, whether
+// the hold is free, and the queue position.
+type Position = (Vec<Line>, usize, u8, Option<u8>, bool, usize);
+
+struct Search<'a> {
+  queue: &'a [Tetrimino],
+  // Positions known not to be solvable.
+  failed: HashSet<Position>,
+  nodes: usize,
+}
+
+fn is_empty(game_map: &[Line]) -> bool {
+  game_map.iter().all(|line| line.iter().all(|case| 0 == *case))
+}
+
+fn filled_cases(game_map: &[Line]) -> usize {
+  game_map.iter().flat_map(|line| line.iter()).filter(|case| 0 != **case)
+    .count()
+}
+
+// Rows from the bottom up to the highest block.
+fn stack_height(game_map: &[Line]) -> usize {
+  game_map.iter().position(|line| line.iter().any(|case| 0 != *case))
+    .map(|top| HEIGHT - top)
+    .unwrap_or(0)
+}
+
+// Whether every empty area of the bottom `height` rows could be filled by
+// whole pieces.
+fn areas_fit(game_map: &[Line], height: usize) -> bool {
+  let mut seen = [[false; WIDTH]; HEIGHT];
+  for start_y in HEIGHT - height..HEIGHT {
+    for start_x in 0..WIDTH {
+      if seen[start_y][start_x] || 0 != game_map[start_y][start_x] {
+        continue
+      }
+      let mut size = 0;
+      let mut stack = vec![(start_x, start_y)];
+      seen[start_y][start_x] = true;
+      while let Some((x, y)) = stack.pop() {
+        size += 1;
+        let neighbours = [
+          (x.wrapping_sub(1), y),
+          (x + 1, y),
+          (x, y.wrapping_sub(1)),
+          (x, y + 1),
+        ];
+        for &(x, y) in neighbours.iter() {
+          if x < WIDTH && HEIGHT - height <= y && y < HEIGHT &&
+            !seen[y][x] && 0 == game_map[y][x]
+          {
+            seen[y][x] = true;
+            stack.push((x, y));
+          }
+        }
+      }
+      if 0 != size % 4 {
+        return false;
+      }
+    }
+  }
+  true
+}
+
+// Locks `piece` and clears the full lines, like the game does. Returns the
+// number of lines cleared.
+fn lock(game_map: &mut Vec<Line>, piece: &Tetrimino) -> usize {
+  for (x, y) in movegen::cells(piece) {
+    game_map[y][x as usize] = piece.color();
+  }
+  let before = game_map.len();
+  game_map.retain(|line| line.contains(&0));
+  let lines = before - game_map.len();
+  for _ in 0..lines {
+    game_map.insert(0, [0; WIDTH]);
+  }
+  lines
+}
+
+// The placements of `piece` when the stack is at most `height` rows high.
+// The search only looks at the rows just above the stack, the piece is soft
+// dropped there first when it has to move down the stack.
+fn placements(game_map: &[Line], height: usize, piece: Tetrimino)
+  -> Vec<Placement>
+{
+  let offset = HEIGHT.saturating_sub(height + 4);
+  let mut placements = movegen::placements(&game_map[offset..], piece.spawn());
+  for placement in placements.iter_mut() {
+    placement.piece.y += offset;
+    if placement.inputs.contains(&Input::SoftDrop) {
+      let mut inputs = vec![Input::SoftDrop; offset];
+      inputs.append(&mut placement.inputs);
+      placement.inputs = inputs;
+    }
+  }
+  placements
+}
+
+impl<'a> Search<'a> {
+  // Solves with `current` in the grid, `held` aside and the queue from
+  // `next` on, without going above `height` rows.
+  fn solve(
+    &mut self,
+    game_map: &[Line],
+    height: usize,
+    current: Option<Tetrimino>,
+    (held, can_hold): (Option<Tetrimino>, bool),
+    next: usize
+  ) -> Option<Vec<Move>> {
+    let current = current?;
+    let nb_pieces = 1 + held.iter().count() + self.queue.len() - next;
+    if nb_pieces * 4 < height * WIDTH - filled_cases(game_map) ||
+      MAX_NODES <= self.nodes
+    {
+      return None;
+    }
+    let key = (
+      game_map.to_vec(),
+      height,
+      current.color(),
+      held.map(|piece| piece.color()),
+      can_hold,
+      next,
+    );
+    if self.failed.contains(&key) || !areas_fit(game_map, height) {
+      return None;
+    }
+    self.nodes += 1;
+
+    // The piece played, whether it was held first, what is left aside and
+    // where the queue goes on.
+    let mut choices = vec![(current, false, held, next)];
+    match held {
+      _ if !can_hold => {}
+      Some(held) if held.color() != current.color() => {
+        choices.push((held, true, Some(current), next));
+      }
+      None if next < self.queue.len() => {
+        choices.push((self.queue[next], true, Some(current), next + 1));
+      }
+      _ => {}
+    }
+    for (piece, hold, held, next) in choices {
+      for placement in placements(game_map, height, piece) {
+        if placement.cells().iter().any(|&(_, y)| y < HEIGHT - height) {
+          continue
+        }
+        let mut after = game_map.to_vec();
+        let lines = lock(&mut after, &placement.piece);
+        let height = height - lines;
+        let current = self.queue.get(next).cloned();
+        let solution = if is_empty(&after) {
+          Some(Vec::new())
+        } else {
+          self.solve(&after, height, current, (held, true), next + 1)
+        };
+        if let Some(mut moves) = solution {
+          let mut inputs = if hold { vec![Input::Hold] } else { Vec::new() };
+          inputs.extend(placement.inputs);
+          moves.insert(0, Move {
+            hold,
+            piece: placement.piece,
+            inputs,
+          });
+          return Some(moves);
+        }
+      }
+    }
+    self.failed.insert(key);
+    None
+  }
+}
+
+// The moves clearing the whole board without stacking above `max_lines`
+// rows, with `current` in the grid, `held` aside and the pieces of `queue`
+// coming next. Empty areas that can't be filled by whole pieces are given
+// up on, which misses the rare solutions where a line clear joins two of
+// them. Gives up after `MAX_NODES` boards.
+pub fn solve(
+  game_map: &[Line],
+  current: Tetrimino,
+  held: Option<Tetrimino>,
+  queue: &[Tetrimino],
+  max_lines: usize
+) -> Option<Vec<Move>> {
+  solve_with_hold(game_map, current, (held, true), queue, max_lines)
+}
+
+fn solve_with_hold(
+  game_map: &[Line],
+  current: Tetrimino,
+  hold: (Option<Tetrimino>, bool),
+  queue: &[Tetrimino],
+  max_lines: usize
+) -> Option<Vec<Move>> {
+  let mut search = Search {
+    queue,
+    failed: HashSet::new(),
+    nodes: 0,
+  };
+  let filled = filled_cases(game_map);
+  for height in stack_height(game_map).max(1)..max_lines.min(HEIGHT) + 1 {
+    if 0 != (height * WIDTH - filled) % 4 {
+      continue
+    }
+    let solution = search.solve(game_map, height, Some(current), hold, 0);
+    if solution.is_some() {
+      return solution;
+    }
+  }
+  None
+}
+
+// The next move of a perfect clear of `tetris` within `max_lines` rows,
+// knowing the pieces of the preview.
+pub fn hint(tetris: &Tetris, max_lines: usize) -> Option<Move> {
+  let current = tetris.current_piece?;
+  let solution = solve_with_hold(
+    &tetris.game_map,
+    current,
+    (tetris.held_piece, tetris.can_hold),
+    &tetris.preview(HINT_PREVIEW),
+    max_lines
+  );
+  solution.and_then(|moves| moves.into_iter().next())
+}
+
+#[cfg(test)]
+mod tests {
+  use game::{Input, Line, Tetris, HEIGHT, WIDTH};
+  use tetrimino::{
+    Tetrimino, TetriminoGenerator, TetriminoI, TetriminoO, TetriminoS,
+    TetriminoT,
+  };
+  use super::{hint, is_empty, lock, solve, Move};
+
+  // The bottom rows full but for the columns of `holes`.
+  fn board(rows: usize, holes: &[usize]) -> Vec<Line> {
+    let mut game_map = vec![[0; WIDTH]; HEIGHT];
+    for line in game_map[HEIGHT - rows..].iter_mut() {
+      *line = [8; WIDTH];
+      for &x in holes {
+        line[x] = 0;
+      }
+    }
+    game_map
+  }
+
+  // Whether the moves clear the board, playing the pieces in their order.
+  fn clears(game_map: &[Line], moves: &[Move], pieces: &[Tetrimino]) -> bool {
+    let mut game_map = game_map.to_vec();
+    let mut played = moves.iter().map(|m| m.piece.color()).collect::<Vec<_>>();
+    let mut pieces = pieces.iter().map(|piece| piece.color())
+      .collect::<Vec<_>>();
+    played.sort();
+    pieces.sort();
+    for m in moves {
+      lock(&mut game_map, &m.piece);
+    }
+    played == pieces && is_empty(&game_map)
+  }
+
+  #[test]
+  fn solves_with_the_pieces_given() {
+    let game_map = board(4, &[0, 1, 2, 3]);
+    let moves = solve(&game_map, TetriminoI::new(), None,
+      &[TetriminoI::new(); 3], 4).unwrap();
+    assert!(clears(&game_map, &moves, &[TetriminoI::new(); 4]));
+    assert!(moves.iter().all(|m| !m.hold));
+
+    // The O fills the hole once the T is held.
+    let game_map = board(2, &[4, 5]);
+    let moves = solve(&game_map, TetriminoT::new(), None,
+      &[TetriminoO::new()], 2).unwrap();
+    assert_eq!(1, moves.len());
+    assert!(moves[0].hold && Input::Hold == moves[0].inputs[0]);
+    assert!(clears(&game_map, &moves, &[TetriminoO::new()]));
+  }
+
+  #[test]
+  fn gives_up_on_boards_that_cant_be_cleared() {
+    let game_map = board(2, &[4, 5]);
+    let queue = [TetriminoS::new(), TetriminoI::new()];
+    assert!(solve(&game_map, TetriminoI::new(), None, &queue, 2).is_none());
+    // Three cases left can't be filled.
+    let game_map = board(1, &[0, 1, 2]);
+    let queue = [TetriminoI::new(); 6];
+    assert!(solve(&game_map, TetriminoI::new(), None, &queue, 4).is_none());
+  }
+
+  #[test]
+  fn hints_lead_to_the_perfect_clear() {
+    let game_map = board(2, &[4, 5]);
+    let mut tetris = Tetris::with_seed(2);
+    tetris.game_map = game_map;
+    tetris.next_piece = Some(TetriminoT::new());
+    tetris.step(&[]);
+    tetris.next_piece = Some(TetriminoO::new());
+    let next = hint(&tetris, 2).unwrap();
+    assert!(next.hold && 4 == next.piece.color());
+    for input in next.inputs {
+      tetris.step(&[input]);
+    }
+    assert!(is_empty(&tetris.game_map));
+  }
+}
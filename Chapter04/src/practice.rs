@@ -0,0 +1,86 @@
+use game::{Input, Tetris};
+
+// A game whose placements can be taken back and played again, as many times
+// as wanted. The game is kept as it was at the end of the frame of each
+// lock: board, queue, hold and score.
+pub struct Practice {
+  pub tetris: Tetris,
+  // The game at the start, then after each placement.
+  history: Vec<Tetris>,
+  // The placements taken back, the latest last.
+  undone: Vec<Tetris>,
+}
+
+impl Practice {
+  pub fn new(tetris: Tetris) -> Practice {
+    Practice {
+      history: vec![tetris.clone()],
+      tetris,
+      undone: Vec::new(),
+    }
+  }
+
+  // Plays a frame. A new placement can't be redone over anymore.
+  pub fn step(&mut self, inputs: &[Input]) {
+    let before = locked(&self.tetris);
+    let game_map = self.tetris.game_map.clone();
+    self.tetris.step(inputs);
+    if before != locked(&self.tetris) || game_map != self.tetris.game_map {
+      self.undone.clear();
+      self.history.push(self.tetris.clone());
+    }
+  }
+
+  // Takes the last placement back, its piece comes again. Without any, the
+  // piece in play starts over.
+  pub fn undo(&mut self) {
+    if 1 < self.history.len() {
+      self.undone.extend(self.history.pop());
+    }
+    self.tetris = self.history[self.history.len() - 1].clone();
+  }
+
+  pub fn redo(&mut self) {
+    if let Some(state) = self.undone.pop() {
+      self.tetris = state.clone();
+      self.history.push(state);
+    }
+  }
+
+  pub fn placements(&self) -> usize {
+    self.history.len() - 1
+  }
+
+  pub fn can_redo(&self) -> bool {
+    !self.undone.is_empty()
+  }
+}
+
+// The piece locked last and where it went.
+fn locked(tetris: &Tetris) -> Option<(u8, isize, usize, u8)> {
+  tetris.last_locked.as_ref()
+    .map(|piece| (piece.color(), piece.x, piece.y, piece.current_state))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Practice;
+  use game::{Input, Tetris};
+
+  #[test]
+  fn undo_and_redo_placements() {
+    let mut practice = Practice::new(Tetris::with_seed(3));
+    for _ in 0..6 {
+      practice.step(&[Input::HardDrop]);
+    }
+    assert_eq!(6, practice.placements());
+    let game_map = practice.tetris.game_map.clone();
+    practice.undo();
+    assert_eq!(5, practice.placements());
+    assert!(game_map != practice.tetris.game_map);
+    practice.redo();
+    assert_eq!(6, practice.placements());
+    assert_eq!(game_map, practice.tetris.game_map);
+    assert!(!practice.can_redo());
+  }
+}
@@ -0,0 +1,455 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use game::{Input, Line, Tetris, GARBAGE, HEIGHT, WIDTH};
+use storage::{self, Loaded};
+use tetrimino::{color_of_letter, piece_letter, Tetrimino};
+
+pub const PUZZLE_DIRECTORY: &str = "assets/puzzles";
+// The puzzles made with the editor, in the data directory.
+pub const USER_PUZZLE_DIRECTORY: &str = "puzzles";
+pub const PROGRESS_FILE: &str = "puzzles.txt";
+
+// Puzzle files are text:
+//
+//   # Clear the bottom four rows.   comments
+//   NAME Tetris
+//   GOAL LINES 4                    LINES <n>, PC, TSS, TSD or TSPIN <lines>
+//   PIECES I                        the pieces, in the order they are dealt
+//   HOLD T                          a piece aside from the start, optional
+//   BOARD                           then the bottom rows of the grid
+//   XXXXXXXXX.
+//
+// A row of the board has `.` for empty cases, the letter of a piece for its
+// blocks and anything else for garbage. There are no other pieces than the
+// ones of the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Goal {
+  Lines(u32),
+  PerfectClear,
+  // A T-spin clearing that many lines.
+  TSpin(u32),
+}
+
+impl Goal {
+  fn decode(goal: &str) -> Option<Goal> {
+    let words = goal.split_whitespace().collect::<Vec<_>>();
+    let count = words.get(1).and_then(|count| count.parse::<u32>().ok());
+    match (words.first().cloned(), count) {
+      (Some("LINES"), Some(lines)) if 0 < lines => Some(Goal::Lines(lines)),
+      (Some("PC"), None) => Some(Goal::PerfectClear),
+      (Some("TSS"), None) => Some(Goal::TSpin(1)),
+      (Some("TSD"), None) => Some(Goal::TSpin(2)),
+      (Some("TSPIN"), Some(lines)) if 0 < lines => Some(Goal::TSpin(lines)),
+      _ => None,
+    }
+  }
+
+  fn encode(&self) -> String {
+    match *self {
+      Goal::Lines(lines) => format!("LINES {}", lines),
+      Goal::PerfectClear => "PC".to_owned(),
+      Goal::TSpin(1) => "TSS".to_owned(),
+      Goal::TSpin(2) => "TSD".to_owned(),
+      Goal::TSpin(lines) => format!("TSPIN {}", lines),
+    }
+  }
+
+  pub fn describe(&self) -> String {
+    match *self {
+      Goal::Lines(1) => "Clear a line".to_owned(),
+      Goal::Lines(lines) => format!("Clear {} lines", lines),
+      Goal::PerfectClear => "Perfect clear".to_owned(),
+      Goal::TSpin(1) => "T-spin single".to_owned(),
+      Goal::TSpin(2) => "T-spin double".to_owned(),
+      Goal::TSpin(3) => "T-spin triple".to_owned(),
+      Goal::TSpin(lines) => format!("T-spin clearing {} lines", lines),
+    }
+  }
+
+  // Whether `tetris` got there, `cleared` being the lines of the last frame.
+  fn is_reached(&self, tetris: &Tetris, cleared: u32) -> bool {
+    match *self {
+      Goal::Lines(lines) => lines <= tetris.nb_lines,
+      Goal::PerfectClear => 0 < cleared && tetris.game_map.iter()
+        .all(|line| line.iter().all(|case| 0 == *case)),
+      Goal::TSpin(lines) => lines == cleared && tetris.last_t_spin,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Puzzle {
+  pub name: String,
+  pub goal: Goal,
+  pub pieces: Vec<u8>,
+  pub hold: Option<u8>,
+  pub board: Vec<Line>,
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Puzzle {
+  pub fn decode(content: &str) -> io::Result<Puzzle> {
+    let mut name = None;
+    let mut goal = None;
+    let mut pieces = Vec::new();
+    let mut hold = None;
+    let mut rows = Vec::new();
+    let mut in_board = false;
+    for line in content.lines() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue
+      }
+      if in_board {
+        rows.push(line);
+        continue
+      }
+      let mut words = line.splitn(2, ' ');
+      let keyword = words.next().unwrap_or("");
+      let rest = words.next().unwrap_or("").trim();
+      let letters = |rest: &str| {
+        rest.chars().filter(|letter| !letter.is_whitespace())
+          .map(color_of_letter)
+          .collect::<Option<Vec<_>>>()
+          .ok_or_else(|| invalid(format!("bad pieces {}", rest)))
+      };
+      match keyword {
+        "NAME" => name = Some(rest.to_owned()),
+        "GOAL" => {
+          goal = Some(Goal::decode(rest)
+            .ok_or_else(|| invalid(format!("bad goal {}", rest)))?);
+        }
+        "PIECES" => pieces = letters(rest)?,
+        "HOLD" => match letters(rest)?.as_slice() {
+          [piece] => hold = Some(*piece),
+          _ => return Err(invalid(format!("bad hold {}", rest))),
+        },
+        "BOARD" => in_board = true,
+        _ => return Err(invalid(format!("unknown line {}", line))),
+      }
+    }
+    let name = name.ok_or_else(|| invalid("no name".to_owned()))?;
+    let goal = goal.ok_or_else(|| invalid("no goal".to_owned()))?;
+    if pieces.is_empty() {
+      return Err(invalid("no pieces".to_owned()));
+    }
+    if HEIGHT < rows.len() {
+      return Err(invalid(format!("more than {} rows", HEIGHT)));
+    }
+    let mut board = vec![[0; WIDTH]; HEIGHT - rows.len()];
+    for row in rows {
+      if WIDTH != row.chars().count() {
+        return Err(invalid(format!("{} cases expected: {}", WIDTH, row)));
+      }
+      let mut line = [0; WIDTH];
+      for (x, case) in row.chars().enumerate() {
+        line[x] = match case {
+          '.' => 0,
+          case => color_of_letter(case).unwrap_or(GARBAGE),
+        };
+      }
+      board.push(line);
+    }
+    Ok(Puzzle {
+      name,
+      goal,
+      pieces,
+      hold,
+      board,
+    })
+  }
+
+  // The file of the puzzle, the board from its highest block.
+  pub fn encode(&self) -> String {
+    let letters = |pieces: &[u8]| {
+      pieces.iter().map(|color| piece_letter(*color))
+        .collect::<Vec<_>>()
+        .join(" ")
+    };
+    let mut content = format!("NAME {}\n", self.name);
+    content.push_str(&format!("GOAL {}\n", self.goal.encode()));
+    content.push_str(&format!("PIECES {}\n", letters(&self.pieces)));
+    if let Some(hold) = self.hold {
+      content.push_str(&format!("HOLD {}\n", piece_letter(hold)));
+    }
+    content.push_str("BOARD\n");
+    let top = self.board.iter()
+      .position(|line| line.iter().any(|case| 0 != *case))
+      .unwrap_or(self.board.len());
+    for line in &self.board[top..] {
+      let row = line.iter().map(|case| match *case {
+        0 => ".",
+        GARBAGE => "X",
+        color => piece_letter(color),
+      }).collect::<String>();
+      content.push_str(&format!("{}\n", row));
+    }
+    content
+  }
+
+  pub fn save(&self, file_name: &str) -> io::Result<()> {
+    storage::write_atomic(Path::new(file_name), self.encode().as_bytes())
+  }
+
+  pub fn load(file_name: &str) -> io::Result<Puzzle> {
+    let mut f = File::open(file_name)?;
+    let mut content = String::new();
+    f.read_to_string(&mut content)?;
+    Puzzle::decode(&content)
+  }
+
+  pub fn user_directory() -> PathBuf {
+    storage::data_path(USER_PUZZLE_DIRECTORY)
+  }
+
+  // A puzzle file, or the file named after it among the puzzles of the
+  // user, then of `PUZZLE_DIRECTORY`.
+  pub fn file_name(name: &str) -> String {
+    if Path::new(name).is_file() {
+      return name.to_owned();
+    }
+    let file = format!("{}.txt", name.to_lowercase().replace(' ', "_"));
+    let user_file = Puzzle::user_directory().join(&file);
+    if user_file.is_file() {
+      return user_file.to_string_lossy().into_owned();
+    }
+    format!("{}/{}", PUZZLE_DIRECTORY, file)
+  }
+
+  // Where changes to a puzzle file go: the puzzles of `PUZZLE_DIRECTORY`
+  // are kept as they are, and copied among the puzzles of the user, which
+  // then replace them.
+  pub fn writable_file(file_name: &str) -> String {
+    let path = Path::new(file_name);
+    match path.file_name() {
+      Some(file) if path.starts_with(PUZZLE_DIRECTORY) => {
+        Puzzle::user_directory().join(file).to_string_lossy().into_owned()
+      }
+      _ => file_name.to_owned(),
+    }
+  }
+
+  pub fn find(name: &str) -> io::Result<Puzzle> {
+    Puzzle::load(&Puzzle::file_name(name))
+  }
+
+  // The puzzles of `PUZZLE_DIRECTORY` then the ones of the user, sorted by
+  // file name. A puzzle of the user replaces the one of `PUZZLE_DIRECTORY`
+  // with the same file name.
+  pub fn list() -> io::Result<Vec<Puzzle>> {
+    let mut files = Vec::new();
+    let directories = [
+      PathBuf::from(PUZZLE_DIRECTORY),
+      Puzzle::user_directory(),
+    ];
+    for directory in &directories {
+      let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(ref e) if ErrorKind::NotFound == e.kind() => continue,
+        Err(e) => return Err(e),
+      };
+      let mut found = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| Some("txt") == path.extension().and_then(|ext| {
+          ext.to_str()
+        }))
+        .filter_map(|path| path.to_str().map(|path| path.to_owned()))
+        .collect::<Vec<_>>();
+      found.sort();
+      files.extend(found);
+    }
+    files.retain(|file_name| {
+      let copy = Puzzle::writable_file(file_name);
+      copy == *file_name || !Path::new(&copy).is_file()
+    });
+    files.iter().map(|file_name| {
+      Puzzle::load(file_name).map_err(|e| {
+        invalid(format!("{}: {}", file_name, e))
+      })
+    }).collect()
+  }
+
+  // The game the puzzle starts with. It ends when the pieces run out.
+  pub fn start(&self) -> Tetris {
+    let pieces = self.pieces.iter()
+      .filter_map(|color| Tetrimino::from_color(*color))
+      .collect::<Vec<_>>();
+    let mut tetris = Tetris::with_board(0, self.board.clone(), &pieces);
+    tetris.random_pieces = false;
+    tetris.held_piece = self.hold.and_then(Tetrimino::from_color);
+    tetris
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+  Playing,
+  Solved,
+  // Out of pieces, or topped out.
+  Failed,
+}
+
+pub struct Attempt {
+  pub puzzle: Puzzle,
+  pub tetris: Tetris,
+  pub status: Status,
+}
+
+impl Attempt {
+  pub fn new(puzzle: Puzzle) -> Attempt {
+    Attempt {
+      tetris: puzzle.start(),
+      puzzle,
+      status: Status::Playing,
+    }
+  }
+
+  // Plays a frame, until the puzzle is solved or failed.
+  pub fn step(&mut self, inputs: &[Input]) {
+    if Status::Playing != self.status {
+      return;
+    }
+    let lines = self.tetris.nb_lines;
+    self.tetris.step(inputs);
+    let cleared = self.tetris.nb_lines - lines;
+    if self.puzzle.goal.is_reached(&self.tetris, cleared) {
+      self.status = Status::Solved;
+    } else if self.tetris.game_over {
+      self.status = Status::Failed;
+    }
+  }
+
+  // The pieces not locked yet, held piece aside.
+  pub fn pieces_left(&self) -> usize {
+    self.tetris.current_piece.iter().count() +
+      self.tetris.next_piece.iter().count() +
+      self.tetris.scripted_pieces.len()
+  }
+}
+
+// How many times each puzzle was tried and whether it was solved, kept in
+// `PROGRESS_FILE` of the data directory with a line per puzzle:
+// `<attempts> <solved> <name>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+  entries: Vec<(String, u32, bool)>,
+}
+
+impl Progress {
+  pub fn decode(content: &str) -> io::Result<Progress> {
+    let entries = content.lines().filter(|line| !line.trim().is_empty())
+      .map(|line| {
+        let mut words = line.splitn(3, ' ');
+        let attempts = words.next()
+          .and_then(|attempts| attempts.parse::<u32>().ok());
+        let solved = match words.next() {
+          Some("0") => Some(false),
+          Some("1") => Some(true),
+          _ => None,
+        };
+        match (attempts, solved, words.next()) {
+          (Some(attempts), Some(solved), Some(name)) => {
+            Ok((name.to_owned(), attempts, solved))
+          }
+          _ => Err(invalid(format!("bad progress {}", line))),
+        }
+      })
+      .collect::<io::Result<_>>()?;
+    Ok(Progress { entries })
+  }
+
+  pub fn encode(&self) -> String {
+    self.entries.iter().map(|&(ref name, attempts, solved)| {
+      format!("{} {} {}\n", attempts, solved as u8, name)
+    }).collect()
+  }
+
+  pub fn path() -> PathBuf {
+    storage::data_path(PROGRESS_FILE)
+  }
+
+  pub fn load() -> io::Result<Loaded<Progress>> {
+    storage::load(&Progress::path(), Progress::decode)
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    storage::write_with_backup(&Progress::path(), self.encode().as_bytes())
+  }
+
+  // The attempts at the puzzle and whether it was ever solved.
+  pub fn of(&self, name: &str) -> (u32, bool) {
+    self.entries.iter().find(|entry| name == entry.0)
+      .map(|&(_, attempts, solved)| (attempts, solved))
+      .unwrap_or((0, false))
+  }
+
+  pub fn record(&mut self, name: &str, solved: bool) {
+    match self.entries.iter_mut().find(|entry| name == entry.0) {
+      Some(entry) => {
+        entry.1 += 1;
+        entry.2 |= solved;
+      }
+      None => self.entries.push((name.to_owned(), 1, solved)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use game::Input;
+  use storage;
+  use super::{Attempt, Goal, Progress, Puzzle, Status, PUZZLE_DIRECTORY};
+
+  #[test]
+  fn bundled_puzzles_load() {
+    storage::test_data_dir();
+    let puzzles = Puzzle::list().unwrap();
+    assert_eq!(4, puzzles.len());
+    assert_eq!(Goal::Lines(4), puzzles[0].goal);
+  }
+
+  #[test]
+  fn puzzles_are_solved_or_failed() {
+    let puzzle = Puzzle::find("assets/puzzles/1_tetris.txt").unwrap();
+    let mut attempt = Attempt::new(puzzle.clone());
+    attempt.step(&[]);
+    attempt.step(&[Input::HardDrop]);
+    attempt.step(&[]);
+    assert_eq!(Status::Failed, attempt.status);
+
+    let mut attempt = Attempt::new(puzzle);
+    attempt.step(&[]);
+    attempt.step(&[Input::Rotate]);
+    for _ in 0..8 {
+      attempt.step(&[Input::Right]);
+    }
+    attempt.step(&[Input::HardDrop]);
+    assert_eq!(Status::Solved, attempt.status);
+    assert_eq!(0, attempt.pieces_left());
+
+    let mut progress = Progress::default();
+    progress.record("Tetris", false);
+    progress.record("Tetris", true);
+    progress.record("Tetris", false);
+    assert_eq!((3, true), progress.of("Tetris"));
+    assert_eq!((0, false), progress.of("T-spin"));
+    assert_eq!(progress, Progress::decode(&progress.encode()).unwrap());
+  }
+
+  #[test]
+  fn bundled_puzzles_are_written_among_the_user_ones() {
+    storage::test_data_dir();
+    let bundled = format!("{}/4_t_spin.txt", PUZZLE_DIRECTORY);
+    let copy = Puzzle::writable_file(&bundled);
+    assert_eq!(
+      Puzzle::user_directory().join("4_t_spin.txt").to_str(),
+      Some(copy.as_str())
+    );
+    assert_eq!(copy, Puzzle::writable_file(&copy));
+  }
+}
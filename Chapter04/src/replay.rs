@@ -0,0 +1,556 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use game::{Input, Tetris, FRAMES_PER_SECOND};
+use save;
+use storage;
+
+// Replays of other versions are refused: before version 2 rotations didn't
+// kick down, the games would play out differently, and before version 3
+// there were no START lines.
+pub const REPLAY_VERSION: u32 = 3;
+pub const REPLAY_DIRECTORY: &str = "replays";
+// Frames of a four hour game, longer recordings aren't read.
+pub const MAX_FRAMES: usize = 4 * 60 * 60 * FRAMES_PER_SECOND as usize;
+// The playback keeps a copy of the boards this often, to seek quickly.
+const SNAPSHOT_FRAMES: u32 = 600;
+
+// Replays are text files:
+//
+//   TETRIS-REPLAY <version>
+//   MODE <mode>                         single, versus, network, royale...
+//   PLAYER <seed> <frames> <name>
+//   START <state>                       how the game started, if not empty
+//   <frame> <inputs>                    comma-separated `Input::encode`
+//   RESULT <score> <lines> <level> <frames> <over>
+//                                       what the game ended with, <over> 1
+//                                       when it was lost, 0 when it stopped
+//
+// with a PLAYER section for every recorded board and a line for every frame
+// with inputs. The START state is `save::encode_state` with `/` for the ends
+// of lines: the board, the queue, the held piece and the level a fumen or a
+// puzzle started with. Garbage received is part of the inputs, so every
+// board replays on its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recording {
+  pub name: String,
+  pub seed: u64,
+  // The state of the game on frame 0, when it isn't a new game.
+  pub start: Option<String>,
+  // Inputs of every frame, the first one is frame 1.
+  pub frames: Vec<Vec<Input>>,
+  pub result: Option<Claim>,
+}
+
+impl Recording {
+  pub fn new(name: &str, seed: u64) -> Recording {
+    Recording {
+      name: name.to_owned(),
+      seed,
+      start: None,
+      frames: Vec::new(),
+      result: None,
+    }
+  }
+
+  // The recording of a game that may not start from an empty board.
+  pub fn starting(name: &str, tetris: &Tetris) -> Recording {
+    let state = save::encode_state(tetris);
+    let mut recording = Recording::new(name, tetris.seed);
+    if state != save::encode_state(&Tetris::with_seed(tetris.seed)) {
+      recording.start = Some(state);
+    }
+    recording
+  }
+
+  // The game on frame 0. The start was checked when the replay was read.
+  pub fn initial(&self) -> Tetris {
+    self.start.as_ref()
+      .and_then(|state| save::decode_state(state).ok())
+      .unwrap_or_else(|| Tetris::with_seed(self.seed))
+  }
+
+  pub fn record(&mut self, inputs: &[Input]) {
+    self.frames.push(inputs.to_vec());
+  }
+
+  pub fn finish(&mut self, tetris: &Tetris) {
+    self.result = Some(Claim {
+      score: tetris.score,
+      lines: tetris.nb_lines,
+      level: Some(tetris.current_level),
+      frames: tetris.frame,
+      over: tetris.game_over,
+    });
+  }
+
+  // Runs the recording through the engine.
+  pub fn simulate(&self) -> Tetris {
+    let mut tetris = self.initial();
+    for inputs in &self.frames {
+      tetris.step(inputs);
+    }
+    tetris
+  }
+}
+
+// What a player says a game ended with, checked against the replay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Claim {
+  pub score: u32,
+  pub lines: u32,
+  pub level: Option<u32>,
+  // How long the game lasted.
+  pub frames: u32,
+  // Whether the game was lost. Otherwise it stopped before, a versus game
+  // won or a game left.
+  pub over: bool,
+}
+
+// The games of these modes don't start from an empty board, their replays
+// can't be played without the START of every player.
+pub const STARTED_MODES: [&str; 3] = ["fumen", "puzzle", "practice"];
+
+// More than this in a single frame can't come from a keyboard.
+pub const MAX_INPUTS_PER_FRAME: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+  pub mode: String,
+  pub players: Vec<Recording>,
+}
+
+fn invalid(message: &str) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+impl Replay {
+  pub fn new(mode: &str, players: Vec<Recording>) -> Replay {
+    Replay {
+      mode: mode.to_owned(),
+      players,
+    }
+  }
+
+  pub fn nb_frames(&self) -> u32 {
+    self.players.iter().map(|player| player.frames.len() as u32).max()
+      .unwrap_or(0)
+  }
+
+  // The claim stored with the replay of a player.
+  pub fn recorded_claim(&self, player: usize) -> Option<Claim> {
+    self.players.get(player)?.result
+  }
+
+  // Simulates the board of `player` and checks it ends like `claim`. Returns
+  // the final board, or why the replay was rejected. Games set up from a
+  // fumen or a puzzle don't rank.
+  pub fn verify(&self, player: usize, claim: &Claim) -> Result<Tetris, String> {
+    let recording = self.players.get(player)
+      .ok_or_else(|| format!("no player {} in the replay", player))?;
+    if recording.start.is_some() {
+      return Err("the game didn't start from an empty board".to_owned());
+    }
+    let mut tetris = recording.initial();
+    for (nb, inputs) in recording.frames.iter().enumerate() {
+      let keys = inputs.iter()
+        .filter(|input| !matches!(**input, Input::Garbage(_)))
+        .count();
+      if MAX_INPUTS_PER_FRAME < keys {
+        return Err(format!("{} inputs on frame {}", keys, nb + 1));
+      }
+      if "single" == self.mode && keys < inputs.len() {
+        return Err(format!("garbage received on frame {}", nb + 1));
+      }
+      if tetris.game_over && !inputs.is_empty() {
+        return Err(format!("inputs after the game over, on frame {}", nb + 1));
+      }
+      tetris.step(inputs);
+    }
+    if claim.score != tetris.score {
+      return Err(format!(
+        "score {} claimed, the replay gives {}", claim.score, tetris.score
+      ));
+    }
+    if claim.lines != tetris.nb_lines {
+      return Err(format!(
+        "{} lines claimed, the replay gives {}", claim.lines, tetris.nb_lines
+      ));
+    }
+    if let Some(level) = claim.level {
+      if level != tetris.current_level {
+        return Err(format!(
+          "level {} claimed, the replay gives {}", level, tetris.current_level
+        ));
+      }
+    }
+    if claim.frames != tetris.frame {
+      return Err(format!(
+        "{} frames claimed, the game lasted {}", claim.frames, tetris.frame
+      ));
+    }
+    if claim.over && !tetris.game_over {
+      return Err("the replay stops before the game over".to_owned());
+    }
+    if !claim.over && tetris.game_over {
+      return Err("the game was over, unlike what was claimed".to_owned());
+    }
+    Ok(tetris)
+  }
+
+  pub fn encode(&self) -> String {
+    let mut content = format!(
+      "TETRIS-REPLAY {}\nMODE {}\n", REPLAY_VERSION, self.mode
+    );
+    for player in &self.players {
+      content.push_str(&format!(
+        "PLAYER {} {} {}\n", player.seed, player.frames.len(), player.name
+      ));
+      if let Some(ref state) = player.start {
+        content.push_str(&format!("START {}\n", state.replace('\n', "/")));
+      }
+      for (nb, inputs) in player.frames.iter().enumerate() {
+        if inputs.is_empty() {
+          continue
+        }
+        let inputs = inputs.iter().map(|input| input.encode())
+          .collect::<Vec<_>>();
+        content.push_str(&format!("{} {}\n", nb + 1, inputs.join(",")));
+      }
+      if let Some(ref claim) = player.result {
+        content.push_str(&format!(
+          "RESULT {} {} {} {} {}\n", claim.score, claim.lines,
+          claim.level.unwrap_or(0), claim.frames, claim.over as u8
+        ));
+      }
+    }
+    content
+  }
+
+  pub fn decode(content: &str) -> io::Result<Replay> {
+    let mut lines = content.lines();
+    match lines.next() {
+      Some(header) if header.starts_with("TETRIS-REPLAY ") => {
+        let version = header["TETRIS-REPLAY ".len()..].trim()
+          .parse::<u32>().ok()
+          .filter(|&version| REPLAY_VERSION == version);
+        if version.is_none() {
+          return Err(invalid("unsupported replay version"));
+        }
+      }
+      _ => return Err(invalid("not a replay")),
+    }
+    let mut replay = Replay::new("", Vec::new());
+    // The number of frames of every player, the frames without inputs are
+    // added at the end.
+    let mut nb_frames = Vec::new();
+    for line in lines {
+      let mut words = line.splitn(2, ' ');
+      let keyword = words.next().unwrap_or("");
+      let rest = words.next().unwrap_or("");
+      match keyword {
+        "MODE" => replay.mode = rest.to_owned(),
+        "PLAYER" => {
+          let mut words = rest.splitn(3, ' ');
+          let seed = words.next().and_then(|seed| seed.parse::<u64>().ok())
+            .ok_or_else(|| invalid("bad player seed"))?;
+          nb_frames.push(words.next()
+            .and_then(|frames| frames.parse::<usize>().ok())
+            .filter(|&frames| frames <= MAX_FRAMES)
+            .ok_or_else(|| invalid("bad number of frames"))?);
+          let player = Recording::new(words.next().unwrap_or(""), seed);
+          replay.players.push(player);
+        }
+        "START" => {
+          let player = replay.players.last_mut()
+            .ok_or_else(|| invalid("start before any player"))?;
+          let state = rest.replace('/', "\n");
+          let tetris = save::decode_state(&state)
+            .map_err(|_| invalid("bad start"))?;
+          if player.seed != tetris.seed || 0 != tetris.frame {
+            return Err(invalid("bad start"));
+          }
+          player.start = Some(state);
+        }
+        "RESULT" => {
+          let player = replay.players.last_mut()
+            .ok_or_else(|| invalid("result before any player"))?;
+          let values = rest.split_whitespace()
+            .map(|value| value.parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("bad result"))?;
+          if 5 != values.len() || 1 < values[4] {
+            return Err(invalid("bad result"));
+          }
+          player.result = Some(Claim {
+            score: values[0],
+            lines: values[1],
+            level: Some(values[2]),
+            frames: values[3],
+            over: 1 == values[4],
+          });
+        }
+        "" => {}
+        frame => {
+          let player = replay.players.last_mut()
+            .ok_or_else(|| invalid("inputs before any player"))?;
+          let last = nb_frames[nb_frames.len() - 1];
+          // The frames come in order.
+          let frame = frame.parse::<usize>().ok()
+            .filter(|&frame| player.frames.len() < frame && frame <= last)
+            .ok_or_else(|| invalid("bad frame number"))?;
+          let inputs = rest.split(',')
+            .map(Input::decode)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("bad inputs"))?;
+          player.frames.resize(frame - 1, Vec::new());
+          player.frames.push(inputs);
+        }
+      }
+    }
+    if replay.players.is_empty() {
+      return Err(invalid("no player in the replay"));
+    }
+    for (player, nb_frames) in replay.players.iter_mut().zip(nb_frames) {
+      player.frames.resize(nb_frames, Vec::new());
+    }
+    let started = STARTED_MODES.contains(&replay.mode.as_str());
+    if started && replay.players.iter().any(|player| player.start.is_none()) {
+      return Err(invalid("the replay doesn't say how the game started"));
+    }
+    Ok(replay)
+  }
+
+  pub fn load(file_name: &str) -> io::Result<Replay> {
+    let mut f = File::open(file_name)?;
+    let mut content = String::new();
+    f.read_to_string(&mut content)?;
+    Replay::decode(&content)
+  }
+
+  pub fn save(&self, file_name: &str) -> io::Result<()> {
+    storage::write_atomic(Path::new(file_name), self.encode().as_bytes())
+  }
+
+  // A replay file, or the file of `REPLAY_DIRECTORY` with that name.
+  pub fn find(file_name: &str) -> io::Result<Replay> {
+    if Path::new(file_name).is_file() {
+      return Replay::load(file_name);
+    }
+    Replay::load(&Replay::path(file_name).to_string_lossy())
+  }
+
+  // Where the replay named `file_name` is in the data directory.
+  pub fn path(file_name: &str) -> PathBuf {
+    storage::data_path(REPLAY_DIRECTORY).join(file_name)
+  }
+
+  // Saves the replay in `REPLAY_DIRECTORY` of the data directory, named
+  // after the current time. Returns the name of the file there, without
+  // the directory that can have spaces.
+  pub fn save_new(&self) -> io::Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+      .map(|now| now.as_secs())
+      .unwrap_or(0);
+    let file_name = format!("{}-{}.replay", now, self.mode);
+    self.save(&Replay::path(&file_name).to_string_lossy())?;
+    Ok(file_name)
+  }
+}
+
+// Watching a replay. Every board is simulated from its recorded inputs.
+pub struct Playback {
+  pub replay: Replay,
+  pub boards: Vec<Tetris>,
+  // Frames played so far.
+  pub frame: u32,
+  snapshots: Vec<Vec<Tetris>>,
+}
+
+impl Playback {
+  pub fn new(replay: Replay) -> Playback {
+    let boards = replay.players.iter()
+      .map(|player| player.initial())
+      .collect::<Vec<_>>();
+    Playback {
+      replay,
+      snapshots: vec![boards.clone()],
+      boards,
+      frame: 0,
+    }
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.replay.nb_frames() <= self.frame
+  }
+
+  pub fn step(&mut self) {
+    if self.is_finished() {
+      return;
+    }
+    for (player, board) in self.replay.players.iter()
+      .zip(self.boards.iter_mut())
+    {
+      if let Some(inputs) = player.frames.get(self.frame as usize) {
+        board.step(inputs);
+      }
+    }
+    self.frame += 1;
+    if 0 == self.frame % SNAPSHOT_FRAMES &&
+      self.snapshots.len() as u32 == self.frame / SNAPSHOT_FRAMES
+    {
+      self.snapshots.push(self.boards.clone());
+    }
+  }
+
+  // Goes to `frame` from the closest snapshot before it.
+  pub fn seek(&mut self, frame: u32) {
+    let frame = frame.min(self.replay.nb_frames());
+    let snapshot = ((frame / SNAPSHOT_FRAMES) as usize)
+      .min(self.snapshots.len() - 1);
+    if frame < self.frame || snapshot as u32 * SNAPSHOT_FRAMES > self.frame {
+      self.boards = self.snapshots[snapshot].clone();
+      self.frame = snapshot as u32 * SNAPSHOT_FRAMES;
+    }
+    while self.frame < frame {
+      self.step();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use fumen::Page;
+  use game::{Input, Tetris, HEIGHT};
+  use super::{Claim, Recording, Replay, REPLAY_VERSION};
+
+  #[test]
+  fn replays_play_the_game_again() {
+    let mut tetris = Tetris::with_seed(5);
+    let mut recording = Recording::new("Player", 5);
+    for frame in 0..200 {
+      let inputs = match frame % 20 {
+        3 => vec![Input::Left, Input::Rotate],
+        9 => vec![Input::HardDrop],
+        _ => Vec::new(),
+      };
+      tetris.step(&inputs);
+      recording.record(&inputs);
+    }
+    recording.finish(&tetris);
+
+    let content = Replay::new("single", vec![recording.clone()]).encode();
+    let replay = Replay::decode(&content).unwrap();
+    assert_eq!("single", replay.mode);
+    assert_eq!(vec![recording], replay.players);
+    let replayed = replay.players[0].simulate();
+    assert_eq!(tetris.game_map, replayed.game_map);
+    assert_eq!(tetris.score, replayed.score);
+  }
+
+  #[test]
+  fn refuses_other_versions() {
+    let content = Replay::new("single", vec![Recording::new("Player", 3)])
+      .encode();
+    assert!(Replay::decode(&content).is_ok());
+    for version in 0..REPLAY_VERSION {
+      let old = content.replacen(
+        &format!("TETRIS-REPLAY {}", REPLAY_VERSION),
+        &format!("TETRIS-REPLAY {}", version),
+        1
+      );
+      assert!(Replay::decode(&old).is_err());
+    }
+  }
+
+  fn fumen_game() -> Tetris {
+    let mut map = Tetris::with_seed(3).game_map;
+    map[HEIGHT - 1] = [8, 8, 8, 8, 0, 0, 8, 8, 8, 8];
+    let mut page = Page::new(&map);
+    page.comment = "#Q=[I](T)SZ".to_owned();
+    page.to_game(3).unwrap()
+  }
+
+  #[test]
+  fn replays_keep_how_the_game_started() {
+    let tetris = fumen_game();
+    let mut recording = Recording::starting("Player", &tetris);
+    assert!(recording.start.is_some());
+    assert!(Recording::starting("Player", &Tetris::with_seed(3)).start
+      .is_none());
+    let mut played = tetris.clone();
+    for frame in 0..40 {
+      let inputs = if 5 == frame { vec![Input::HardDrop] } else { Vec::new() };
+      played.step(&inputs);
+      recording.record(&inputs);
+    }
+    recording.finish(&played);
+
+    let replay = Replay::decode(&Replay::new("fumen", vec![recording])
+      .encode()).unwrap();
+    let replayed = replay.players[0].simulate();
+    assert_eq!(played.game_map, replayed.game_map);
+    assert_eq!(played.nb_pieces, replayed.nb_pieces);
+    let claim = replay.recorded_claim(0).unwrap();
+    assert!(replay.verify(0, &claim).is_err());
+  }
+
+  #[test]
+  fn refuses_started_modes_without_a_start() {
+    let recording = Recording::new("Player", 3);
+    let content = Replay::new("fumen", vec![recording.clone()]).encode();
+    assert!(Replay::decode(&content).is_err());
+    let content = Replay::new("single", vec![recording]).encode();
+    assert!(Replay::decode(&content).is_ok());
+  }
+
+  #[test]
+  fn refuses_frame_counts_past_the_longest_game() {
+    let header = format!("TETRIS-REPLAY {}\nMODE single\n", REPLAY_VERSION);
+    let content = format!("{}PLAYER 1 {{}} x\n", header);
+    for frames in &["18446744073709551615", "100000000000", "864001"] {
+      let replay = content.replace("{}", frames);
+      assert!(Replay::decode(&replay).is_err(), "{}", frames);
+    }
+    let replay = Replay::decode(&content.replace("{}", "864000")).unwrap();
+    assert_eq!(864_000, replay.players[0].frames.len());
+    let frames = format!("{}PLAYER 1 9 x\n", header);
+    assert!(Replay::decode(&format!("{}4 H\n3 L\n", frames)).is_err());
+    assert!(Replay::decode(&format!("{}10 H\n", frames)).is_err());
+    let replay = Replay::decode(&format!("{}3 L\n4 H\n", frames)).unwrap();
+    assert_eq!(9, replay.players[0].frames.len());
+  }
+
+  #[test]
+  fn verify_checks_the_time_and_the_end_of_the_game() {
+    let mut tetris = Tetris::with_seed(5);
+    let mut recording = Recording::new("Player", 5);
+    while !tetris.game_over {
+      tetris.step(&[Input::HardDrop]);
+      recording.record(&[Input::HardDrop]);
+    }
+    recording.finish(&tetris);
+    let replay = Replay::decode(&Replay::new("single", vec![recording.clone()])
+      .encode()).unwrap();
+    let claim = replay.recorded_claim(0).unwrap();
+    assert!(claim.over);
+    assert_eq!(tetris.frame, claim.frames);
+    assert!(replay.verify(0, &claim).is_ok());
+    let slower = Claim { frames: claim.frames + 1, ..claim };
+    assert!(replay.verify(0, &slower).is_err());
+    let unfinished = Claim { over: false, ..claim };
+    assert!(replay.verify(0, &unfinished).is_err());
+
+    // The inputs stop before the game over.
+    recording.frames.truncate(3);
+    let played = recording.simulate();
+    recording.finish(&played);
+    let cut = Replay::new("single", vec![recording]);
+    let claim = cut.recorded_claim(0).unwrap();
+    assert!(!claim.over);
+    assert!(cut.verify(0, &claim).is_ok());
+    assert!(cut.verify(0, &Claim { over: true, ..claim }).is_err());
+  }
+}
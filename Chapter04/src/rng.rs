@@ -0,0 +1,39 @@
+// A small xorshift generator. Unlike `rand::random` it can be seeded, so two
+// games started with the same seed get the same sequence of pieces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    // splitmix64 so that close seeds don't give close sequences, and so that
+    // the state is never zero.
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    Rng { state: if 0 == z { 1 } else { z } }
+  }
+
+  // The generator where `state` left it, a state taken from `state`. There
+  // is none at zero.
+  pub fn from_state(state: u64) -> Option<Rng> {
+    if 0 == state { None } else { Some(Rng { state }) }
+  }
+
+  pub fn state(&self) -> u64 {
+    self.state
+  }
+
+  pub fn next_u32(&mut self) -> u32 {
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    (self.state >> 32) as u32
+  }
+
+  pub fn gen_range(&mut self, max: u32) -> u32 {
+    self.next_u32() % max
+  }
+}
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+
+use game::{Input, Tetris};
+use net::Message;
+
+// The local inputs the other side hasn't acknowledged yet.
+pub struct InputHistory {
+  first: u32,
+  frames: VecDeque<Vec<Input>>,
+}
+
+impl Default for InputHistory {
+  fn default() -> InputHistory {
+    InputHistory::new()
+  }
+}
+
+impl InputHistory {
+  pub fn new() -> InputHistory {
+    InputHistory {
+      first: 1,
+      frames: VecDeque::new(),
+    }
+  }
+
+  pub fn push(&mut self, inputs: Vec<Input>) {
+    self.frames.push_back(inputs);
+  }
+
+  pub fn acknowledge(&mut self, frame: u32) {
+    while self.first <= frame && !self.frames.is_empty() {
+      self.frames.pop_front();
+      self.first += 1;
+    }
+  }
+
+  pub fn message(&self, ack: u32) -> Message {
+    Message::Inputs {
+      ack,
+      first: self.first,
+      frames: self.frames.iter().cloned().collect(),
+    }
+  }
+}
+
+// The board of another player. `confirmed` only ever sees the inputs we
+// received, `predicted` is a copy of it run ahead to our own frame assuming
+// nothing was pressed. When late inputs turn out to press something, the
+// prediction is thrown away and simulated again from the confirmed state.
+pub struct RemoteBoard {
+  confirmed: Tetris,
+  predicted: Tetris,
+  // A frame already predicted was confirmed with other inputs.
+  mispredicted: bool,
+  garbage: Vec<(u32, u32)>,
+  pub rollbacks: u32,
+  pub predicted_frames: u32,
+}
+
+impl RemoteBoard {
+  pub fn new(seed: u64) -> RemoteBoard {
+    let tetris = Tetris::with_seed(seed);
+    RemoteBoard {
+      predicted: tetris.clone(),
+      confirmed: tetris,
+      mispredicted: false,
+      garbage: Vec::new(),
+      rollbacks: 0,
+      predicted_frames: 0,
+    }
+  }
+
+  pub fn confirmed(&self) -> &Tetris {
+    &self.confirmed
+  }
+
+  pub fn predicted(&self) -> &Tetris {
+    &self.predicted
+  }
+
+  // The garbage sent on confirmed frames, as (frame, lines). It is only ever
+  // taken from confirmed frames, so we never have to roll back our own board.
+  pub fn take_garbage(&mut self) -> Vec<(u32, u32)> {
+    self.garbage.drain(..).collect()
+  }
+
+  // Applies the frames starting at `first` that we didn't have yet.
+  pub fn confirm(&mut self, first: u32, frames: &[Vec<Input>]) {
+    for (nb, inputs) in frames.iter().enumerate() {
+      if first + nb as u32 == self.confirmed.frame + 1 &&
+        !self.confirmed.game_over
+      {
+        self.confirmed.step(inputs);
+        let lines = self.confirmed.take_garbage();
+        if 0 < lines {
+          self.garbage.push((self.confirmed.frame, lines));
+        }
+        if !inputs.is_empty() && self.confirmed.frame <= self.predicted.frame
+        {
+          self.mispredicted = true;
+        }
+      }
+    }
+  }
+
+  pub fn predict(&mut self, frame: u32) {
+    if self.mispredicted {
+      self.rollbacks += 1;
+    }
+    if self.mispredicted || self.predicted.frame < self.confirmed.frame {
+      self.predicted = self.confirmed.clone();
+      self.mispredicted = false;
+    }
+    while self.predicted.frame < frame && !self.predicted.game_over {
+      self.predicted.step(&[]);
+      self.predicted_frames += 1;
+    }
+    self.predicted.take_garbage();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use game::{Input, Tetris};
+  use super::RemoteBoard;
+
+  #[test]
+  fn late_inputs_roll_the_prediction_back() {
+    let frames = (0..30).map(|frame| match frame {
+      3 => vec![Input::Left],
+      10 => vec![Input::HardDrop],
+      _ => Vec::new(),
+    }).collect::<Vec<_>>();
+    let mut tetris = Tetris::with_seed(7);
+    for inputs in &frames {
+      tetris.step(inputs);
+    }
+
+    let mut board = RemoteBoard::new(7);
+    board.predict(30);
+    assert_eq!(0, board.rollbacks);
+    board.confirm(1, &frames[..20]);
+    board.predict(30);
+    assert_eq!(1, board.rollbacks);
+    // Catching up with the prediction isn't a rollback.
+    board.confirm(21, &frames[20..]);
+    board.predict(30);
+    assert_eq!(1, board.rollbacks);
+    assert_eq!(tetris.game_map, board.predicted().game_map);
+    assert_eq!(tetris.score, board.predicted().score);
+    assert_eq!(30, board.confirmed().frame);
+  }
+
+  #[test]
+  fn right_predictions_are_kept() {
+    let mut board = RemoteBoard::new(7);
+    board.predict(30);
+    assert_eq!(30, board.predicted_frames);
+    board.confirm(1, &vec![Vec::new(); 20]);
+    board.predict(30);
+    assert_eq!((0, 30), (board.rollbacks, board.predicted_frames));
+    // Confirmed frames past the prediction are taken as they are.
+    board.confirm(21, &vec![Vec::new(); 20]);
+    board.predict(30);
+    assert_eq!((0, 30), (board.rollbacks, board.predicted_frames));
+    assert_eq!(40, board.predicted().frame);
+  }
+}
@@ -0,0 +1,318 @@
+use bot::{Bot, Weights};
+use game::{Input, Tetris, FRAMES_PER_SECOND, HEIGHT};
+use rng::Rng;
+
+pub const MIN_OPPONENTS: usize = 10;
+pub const MAX_OPPONENTS: usize = 99;
+// Badges needed for each extra 25% of attack.
+pub const BADGE_LEVELS: [u32; 4] = [2, 6, 14, 30];
+// How often a player aiming at random picks someone else.
+const RANDOM_TARGET_FRAMES: u32 = 5 * FRAMES_PER_SECOND;
+const TARGET_FRAMES: u32 = FRAMES_PER_SECOND / 2;
+
+// Who a player sends its garbage to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+  Random,
+  // The players targeting us.
+  Attackers,
+  // The player closest to topping out.
+  KOs,
+  // The player with the most badges.
+  Badges,
+}
+
+impl Strategy {
+  pub fn all() -> [Strategy; 4] {
+    [Strategy::Random, Strategy::Attackers, Strategy::KOs, Strategy::Badges]
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Strategy::Random => "RANDOM",
+      Strategy::Attackers => "ATTACKERS",
+      Strategy::KOs => "KOS",
+      Strategy::Badges => "BADGES",
+    }
+  }
+}
+
+pub struct Contender {
+  pub tetris: Tetris,
+  // `None` for the human player.
+  pub bot: Option<Bot>,
+  pub strategy: Strategy,
+  pub target: Option<usize>,
+  // One per knock out, plus the badges of the player knocked out.
+  pub badges: u32,
+  // Finishing place, once knocked out.
+  pub place: Option<usize>,
+  incoming_garbage: u32,
+  last_attacker: Option<usize>,
+  target_frames: u32,
+}
+
+impl Contender {
+  pub fn is_alive(&self) -> bool {
+    !self.tetris.game_over
+  }
+
+  // Extra attack given by the badges, in percent.
+  pub fn badge_bonus(&self) -> u32 {
+    BADGE_LEVELS.iter().filter(|&&level| level <= self.badges).count() as u32 *
+      25
+  }
+}
+
+// A free for all against bots, the human is the player 0.
+pub struct Royale {
+  pub players: Vec<Contender>,
+  rng: Rng,
+}
+
+impl Royale {
+  pub fn new(seed: u64, nb_opponents: usize, weights: Weights) -> Royale {
+    let nb_opponents = nb_opponents.clamp(MIN_OPPONENTS, MAX_OPPONENTS);
+    let mut rng = Rng::new(seed);
+    let players = (0..nb_opponents + 1).map(|index| {
+      let bot = if 0 == index {
+        None
+      } else {
+        // From quick to sleepy bots.
+        Some(Bot::with_weights(2 + rng.gen_range(18), weights))
+      };
+      let strategy = Strategy::all()[rng.gen_range(4) as usize];
+      Contender {
+        tetris: Tetris::with_seed(seed.wrapping_add(index as u64)),
+        bot,
+        strategy,
+        target: None,
+        badges: 0,
+        place: None,
+        incoming_garbage: 0,
+        last_attacker: None,
+        target_frames: 0,
+      }
+    }).collect();
+    Royale {
+      players,
+      rng,
+    }
+  }
+
+  pub fn human(&self) -> &Contender {
+    &self.players[0]
+  }
+
+  pub fn set_strategy(&mut self, index: usize, strategy: Strategy) {
+    let player = &mut self.players[index];
+    if player.strategy != strategy {
+      player.strategy = strategy;
+      player.target_frames = 0;
+    }
+  }
+
+  pub fn nb_alive(&self) -> usize {
+    self.players.iter().filter(|player| player.is_alive()).count()
+  }
+
+  // The players aiming at `index`.
+  pub fn attackers(&self, index: usize) -> Vec<usize> {
+    self.players.iter().enumerate()
+      .filter(|&(_, player)| player.is_alive() && Some(index) == player.target)
+      .map(|(attacker, _)| attacker)
+      .collect()
+  }
+
+  // The last player standing.
+  pub fn winner(&self) -> Option<usize> {
+    let alive = self.players.iter().enumerate()
+      .filter(|&(_, player)| player.is_alive())
+      .map(|(index, _)| index)
+      .collect::<Vec<_>>();
+    if 1 == alive.len() { Some(alive[0]) } else { None }
+  }
+
+  // Advances every board by one frame. Returns the inputs the human's board
+  // got, including the garbage received.
+  pub fn step(&mut self, human_inputs: Vec<Input>) -> Vec<Input> {
+    if self.winner().is_some() {
+      return Vec::new();
+    }
+    let mut human_inputs = Some(human_inputs);
+    let mut applied = Vec::new();
+    for player in self.players.iter_mut().filter(|player| player.is_alive()) {
+      let mut inputs = match player.bot {
+        Some(ref mut bot) => bot.inputs(&player.tetris),
+        None => human_inputs.take().unwrap_or_default(),
+      };
+      if 0 < player.incoming_garbage {
+        inputs.push(Input::Garbage(player.incoming_garbage));
+        player.incoming_garbage = 0;
+      }
+      player.tetris.step(&inputs);
+      if player.bot.is_none() {
+        applied = inputs;
+      }
+    }
+
+    for index in 0..self.players.len() {
+      if !self.players[index].is_alive() {
+        continue
+      }
+      self.update_target(index);
+      let attack = self.players[index].tetris.take_garbage();
+      let attack = attack * (100 + self.players[index].badge_bonus()) / 100;
+      if let Some(target) = self.players[index].target {
+        if 0 < attack {
+          self.players[target].incoming_garbage += attack;
+          self.players[target].last_attacker = Some(index);
+        }
+      }
+    }
+
+    self.knock_out();
+    applied
+  }
+
+  // Gives a place to the players who just topped out, and their badges to
+  // whoever attacked them last.
+  fn knock_out(&mut self) {
+    let mut place = self.nb_alive();
+    for index in 0..self.players.len() {
+      let player = &self.players[index];
+      if player.is_alive() || player.place.is_some() {
+        continue
+      }
+      place += 1;
+      let badges = player.badges;
+      let attacker = player.last_attacker;
+      self.players[index].place = Some(place);
+      if let Some(attacker) = attacker {
+        if self.players[attacker].is_alive() {
+          self.players[attacker].badges += 1 + badges;
+        }
+      }
+    }
+    if let Some(winner) = self.winner() {
+      self.players[winner].place = Some(1);
+    }
+  }
+
+  fn update_target(&mut self, index: usize) {
+    let target_alive = self.players[index].target
+      .map(|target| self.players[target].is_alive())
+      .unwrap_or(false);
+    if target_alive && 0 < self.players[index].target_frames {
+      self.players[index].target_frames -= 1;
+      return;
+    }
+    let opponents = self.players.iter().enumerate()
+      .filter(|&(other, player)| other != index && player.is_alive())
+      .map(|(other, _)| other)
+      .collect::<Vec<_>>();
+    if opponents.is_empty() {
+      self.players[index].target = None;
+      return;
+    }
+    let attackers = self.attackers(index);
+    let strategy = self.players[index].strategy;
+    let target = match strategy {
+      Strategy::Attackers if !attackers.is_empty() => {
+        attackers[self.rng.gen_range(attackers.len() as u32) as usize]
+      }
+      Strategy::KOs => *opponents.iter()
+        .max_by_key(|&&other| stack_height(&self.players[other].tetris))
+        .unwrap(),
+      Strategy::Badges => *opponents.iter()
+        .max_by_key(|&&other| self.players[other].badges)
+        .unwrap(),
+      _ => opponents[self.rng.gen_range(opponents.len() as u32) as usize],
+    };
+    let player = &mut self.players[index];
+    player.target = Some(target);
+    player.target_frames = match strategy {
+      Strategy::Random => RANDOM_TARGET_FRAMES,
+      _ => TARGET_FRAMES,
+    };
+  }
+}
+
+// Number of rows holding blocks, plus the garbage about to rise.
+fn stack_height(tetris: &Tetris) -> u32 {
+  let empty = tetris.game_map.iter()
+    .take_while(|line| line.iter().all(|case| 0 == *case))
+    .count();
+  (HEIGHT - empty) as u32 + tetris.pending_garbage
+}
+
+#[cfg(test)]
+mod tests {
+  use bot::Weights;
+  use game::{HEIGHT, WIDTH};
+  use super::{Royale, Strategy, MIN_OPPONENTS};
+
+  fn royale() -> Royale {
+    Royale::new(4, 0, Weights::default())
+  }
+
+  #[test]
+  fn badges_add_a_quarter_of_attack_per_level() {
+    let mut royale = royale();
+    assert_eq!(MIN_OPPONENTS + 1, royale.players.len());
+    let bonuses = [0, 1, 2, 5, 6, 13, 14, 30, 100].iter().map(|&badges| {
+      royale.players[1].badges = badges;
+      royale.players[1].badge_bonus()
+    }).collect::<Vec<_>>();
+    assert_eq!(vec![0, 0, 25, 25, 50, 50, 75, 100, 100], bonuses);
+  }
+
+  #[test]
+  fn strategies_pick_their_targets() {
+    let mut royale = royale();
+    for line in royale.players[3].tetris.game_map[HEIGHT - 4..].iter_mut() {
+      *line = [8; WIDTH];
+      line[0] = 0;
+    }
+    royale.players[5].badges = 9;
+    royale.players[7].target = Some(1);
+    let targets = Strategy::all().iter().map(|&strategy| {
+      royale.set_strategy(1, strategy);
+      royale.players[1].target = None;
+      royale.update_target(1);
+      royale.players[1].target
+    }).collect::<Vec<_>>();
+    assert!(targets[0].is_some() && Some(1) != targets[0]);
+    assert_eq!(vec![Some(7), Some(3), Some(5)], targets[1..].to_vec());
+
+    // With no attackers, any opponent will do.
+    royale.players[7].target = None;
+    royale.set_strategy(1, Strategy::Attackers);
+    royale.players[1].target = None;
+    royale.update_target(1);
+    assert!(royale.players[1].target.is_some());
+  }
+
+  #[test]
+  fn knock_outs_give_places_and_badges() {
+    let mut royale = royale();
+    royale.players[2].badges = 3;
+    royale.players[2].last_attacker = Some(6);
+    royale.players[2].tetris.game_over = true;
+    royale.knock_out();
+    assert_eq!(Some(11), royale.players[2].place);
+    assert_eq!(4, royale.players[6].badges);
+
+    for index in 1..royale.players.len() {
+      royale.players[index].tetris.game_over = true;
+    }
+    royale.knock_out();
+    let mut places = royale.players.iter()
+      .map(|player| player.place.unwrap())
+      .collect::<Vec<_>>();
+    places.sort();
+    assert_eq!((1..12).collect::<Vec<_>>(), places);
+    assert_eq!(Some(1), royale.human().place);
+    assert_eq!(Some(0), royale.winner());
+  }
+}
@@ -0,0 +1,359 @@
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use game::{Internals, Line, Tetris, HEIGHT, LEVEL_TIMES, WIDTH};
+use replay::{Recording, Replay};
+use storage::{self, Loaded};
+use tetrimino::{color_of_letter, piece_letter, Tetrimino};
+
+pub const SAVE_VERSION: u32 = 1;
+pub const SAVE_FILE: &str = "save.txt";
+
+// A single player game put aside, to go on with later. Saves are text files:
+//
+//   TETRIS-SAVE <version>
+//   SEED <seed>
+//   FRAME <frame> <gravity frames>     frames played, frames since the fall
+//   SCORE <score> <lines> <level>
+//   PIECES <pieces> <previous random piece>
+//   RNG <piece state> <garbage state>
+//   CURRENT <piece>                    `-` or `<letter> <x> <y> <rotation>`
+//   NEXT <piece>
+//   HOLD <piece> <can hold>
+//   LOCKED <piece> <T-spin>            the piece locked last
+//   ROTATED <rotated>                  the last move was a rotation
+//   GARBAGE <pending> <outgoing>
+//   OVER <game over>
+//   SCRIPTED <random pieces> <letters> the pieces dealt first
+//   BOARD                              then the rows of the grid
+//   ..........
+//   REPLAY                             then the replay of the game so far
+//
+// with 0 or 1 for the flags. A version other than `SAVE_VERSION` is
+// refused, so that a save is never read wrong by another build.
+pub struct SavedGame {
+  pub tetris: Tetris,
+  pub recording: Recording,
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn encode_piece(piece: &Option<Tetrimino>) -> String {
+  match *piece {
+    Some(ref piece) => format!(
+      "{} {} {} {}",
+      piece_letter(piece.color()), piece.x, piece.y, piece.current_state
+    ),
+    None => "-".to_owned(),
+  }
+}
+
+fn decode_piece(words: &[&str]) -> Option<Option<Tetrimino>> {
+  match *words {
+    ["-"] => Some(None),
+    [letter, x, y, state] => {
+      let mut piece = letter.chars().next()
+        .and_then(color_of_letter)
+        .and_then(Tetrimino::from_color)?;
+      piece.x = x.parse::<isize>().ok()?;
+      piece.y = y.parse::<usize>().ok()?;
+      piece.current_state = state.parse::<u8>().ok()
+        .filter(|&state| (state as usize) < piece.states.len())?;
+      Some(Some(piece))
+    }
+    _ => None,
+  }
+}
+
+fn decode_flag(word: &str) -> Option<bool> {
+  match word {
+    "0" => Some(false),
+    "1" => Some(true),
+    _ => None,
+  }
+}
+
+// The lines of a save from SEED to the rows of the board.
+pub fn encode_state(tetris: &Tetris) -> String {
+  let internals = tetris.internals();
+  let mut content = format!("SEED {}\n", tetris.seed);
+  content.push_str(&format!(
+    "FRAME {} {}\n", tetris.frame, internals.gravity_frames
+  ));
+  content.push_str(&format!(
+    "SCORE {} {} {}\n",
+    tetris.score, tetris.nb_lines, tetris.current_level
+  ));
+  content.push_str(&format!(
+    "PIECES {} {}\n", tetris.nb_pieces, internals.previous_piece
+  ));
+  content.push_str(&format!(
+    "RNG {} {}\n", internals.piece_rng, internals.garbage_rng
+  ));
+  content.push_str(&format!(
+    "CURRENT {}\n", encode_piece(&tetris.current_piece)
+  ));
+  content.push_str(&format!("NEXT {}\n", encode_piece(&tetris.next_piece)));
+  content.push_str(&format!(
+    "HOLD {} {}\n",
+    encode_piece(&tetris.held_piece), tetris.can_hold as u8
+  ));
+  content.push_str(&format!(
+    "LOCKED {} {}\n",
+    encode_piece(&tetris.last_locked), tetris.last_t_spin as u8
+  ));
+  content.push_str(&format!("ROTATED {}\n", internals.rotated as u8));
+  content.push_str(&format!(
+    "GARBAGE {} {}\n", tetris.pending_garbage, tetris.outgoing_garbage
+  ));
+  content.push_str(&format!("OVER {}\n", tetris.game_over as u8));
+  let scripted = tetris.scripted_pieces.iter()
+    .map(|piece| piece_letter(piece.color()))
+    .collect::<String>();
+  content.push_str(&format!(
+    "SCRIPTED {} {}\n", tetris.random_pieces as u8, scripted
+  ));
+  content.push_str("BOARD\n");
+  for line in &tetris.game_map {
+    let row = line.iter().map(|case| match *case {
+      0 => '.',
+      case => (b'0' + case) as char,
+    }).collect::<String>();
+    content.push_str(&format!("{}\n", row));
+  }
+  content
+}
+
+// A game from the lines of `encode_state`.
+pub fn decode_state(content: &str) -> io::Result<Tetris> {
+  let mut values = Vec::new();
+  let mut rows = Vec::new();
+  let mut in_board = false;
+  for line in content.lines() {
+    if in_board {
+      rows.push(line);
+      continue
+    }
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    match words.split_first() {
+      Some((&"BOARD", _)) => in_board = true,
+      Some((keyword, rest)) => values.push((*keyword, rest.to_vec())),
+      None => {}
+    }
+  }
+  let field = |keyword: &str| {
+    values.iter().find(|value| keyword == value.0)
+      .map(|value| value.1.clone())
+      .ok_or_else(|| invalid(format!("no {}", keyword)))
+  };
+  let numbers = |keyword: &str, nb: usize| -> io::Result<Vec<u64>> {
+    field(keyword)?.iter().map(|word| word.parse::<u64>().ok())
+      .collect::<Option<Vec<_>>>()
+      .filter(|numbers| nb == numbers.len())
+      .ok_or_else(|| invalid(format!("bad {}", keyword)))
+  };
+  let piece = |keyword: &str, nb_flags: usize| {
+    let words = field(keyword)?;
+    let bad = || invalid(format!("bad {}", keyword));
+    if words.len() < nb_flags {
+      return Err(bad());
+    }
+    let (piece, flags) = words.split_at(words.len() - nb_flags);
+    let flags = flags.iter().map(|flag| decode_flag(flag))
+      .collect::<Option<Vec<_>>>()
+      .ok_or_else(bad)?;
+    Ok((decode_piece(piece).ok_or_else(bad)?, flags))
+  };
+
+  let seed = numbers("SEED", 1)?[0];
+  let mut tetris = Tetris::with_seed(seed);
+  let frame = numbers("FRAME", 2)?;
+  tetris.frame = frame[0] as u32;
+  let score = numbers("SCORE", 3)?;
+  tetris.score = score[0] as u32;
+  tetris.nb_lines = score[1] as u32;
+  tetris.current_level = score[2] as u32;
+  if 0 == tetris.current_level ||
+    LEVEL_TIMES.len() < tetris.current_level as usize
+  {
+    return Err(invalid(format!("bad level {}", tetris.current_level)));
+  }
+  let pieces = numbers("PIECES", 2)?;
+  tetris.nb_pieces = pieces[0] as u32;
+  let rng = numbers("RNG", 2)?;
+  tetris.current_piece = piece("CURRENT", 0)?.0;
+  tetris.next_piece = piece("NEXT", 0)?.0;
+  let (held_piece, flags) = piece("HOLD", 1)?;
+  tetris.held_piece = held_piece;
+  tetris.can_hold = flags[0];
+  let (last_locked, flags) = piece("LOCKED", 1)?;
+  tetris.last_locked = last_locked;
+  tetris.last_t_spin = flags[0];
+  let flag = |keyword: &str| {
+    field(keyword)?.first().and_then(|flag| decode_flag(flag))
+      .ok_or_else(|| invalid(format!("bad {}", keyword)))
+  };
+  let rotated = flag("ROTATED")?;
+  let garbage = numbers("GARBAGE", 2)?;
+  tetris.pending_garbage = garbage[0] as u32;
+  tetris.outgoing_garbage = garbage[1] as u32;
+  tetris.game_over = flag("OVER")?;
+  let scripted = field("SCRIPTED")?;
+  tetris.random_pieces = flag("SCRIPTED")?;
+  tetris.scripted_pieces = scripted.get(1).cloned().unwrap_or("").chars()
+    .map(|letter| color_of_letter(letter).and_then(Tetrimino::from_color))
+    .collect::<Option<_>>()
+    .ok_or_else(|| invalid("bad SCRIPTED".to_owned()))?;
+  let internals = Internals {
+    rotated,
+    gravity_frames: frame[1] as u32,
+    piece_rng: rng[0],
+    garbage_rng: rng[1],
+    previous_piece: pieces[1] as u32,
+  };
+  if !tetris.set_internals(internals) {
+    return Err(invalid("bad RNG".to_owned()));
+  }
+
+  if HEIGHT != rows.len() {
+    return Err(invalid(format!("{} rows expected", HEIGHT)));
+  }
+  tetris.game_map = rows.iter().map(|row| {
+    let mut line: Line = [0; WIDTH];
+    let cases = row.trim().chars().map(|case| match case {
+      '.' => Some(0),
+      case => case.to_digit(10).filter(|&case| 0 < case && case <= 8)
+        .map(|case| case as u8),
+    }).collect::<Option<Vec<_>>>()
+      .filter(|cases| WIDTH == cases.len())
+      .ok_or_else(|| invalid(format!("bad row {}", row)))?;
+    line.copy_from_slice(&cases);
+    Ok(line)
+  }).collect::<io::Result<Vec<_>>>()?;
+  let fits = tetris.current_piece.as_ref()
+    .map(|piece| piece.test_current_position(&tetris.game_map))
+    .unwrap_or(true);
+  if !fits {
+    return Err(invalid("the piece is out of place".to_owned()));
+  }
+  Ok(tetris)
+}
+
+impl SavedGame {
+  pub fn new(tetris: &Tetris, recording: &Recording) -> SavedGame {
+    SavedGame {
+      tetris: tetris.clone(),
+      recording: recording.clone(),
+    }
+  }
+
+  pub fn encode(&self) -> String {
+    let mut content = format!("TETRIS-SAVE {}\n", SAVE_VERSION);
+    content.push_str(&encode_state(&self.tetris));
+    content.push_str("REPLAY\n");
+    content.push_str(&Replay::new("single", vec![self.recording.clone()])
+      .encode());
+    content
+  }
+
+  // Also checks that the replay leads to the saved game.
+  pub fn decode(content: &str) -> io::Result<SavedGame> {
+    let (state, replay) = match content.find("\nREPLAY\n") {
+      Some(index) => (&content[..index + 1], &content[index + 8..]),
+      None => return Err(invalid("no replay".to_owned())),
+    };
+    let state = match state.find('\n') {
+      Some(end) if state.starts_with("TETRIS-SAVE ") => {
+        let version = state["TETRIS-SAVE ".len()..end].trim();
+        if version != SAVE_VERSION.to_string() {
+          return Err(invalid(format!("unsupported save version {}", version)));
+        }
+        &state[end + 1..]
+      }
+      _ => return Err(invalid("not a saved game".to_owned())),
+    };
+    let tetris = decode_state(state)?;
+    let recording = Replay::decode(replay)?.players.into_iter().next()
+      .ok_or_else(|| invalid("no recording".to_owned()))?;
+    let replayed = recording.simulate();
+    if tetris.seed != recording.seed || replayed.frame != tetris.frame ||
+      replayed.score != tetris.score || replayed.game_map != tetris.game_map
+    {
+      return Err(invalid("the replay doesn't lead to the game".to_owned()));
+    }
+    Ok(SavedGame {
+      tetris,
+      recording,
+    })
+  }
+
+  pub fn path() -> PathBuf {
+    storage::data_path(SAVE_FILE)
+  }
+
+  pub fn load() -> io::Result<Loaded<SavedGame>> {
+    storage::load(&SavedGame::path(), SavedGame::decode)
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    storage::write_with_backup(&SavedGame::path(), self.encode().as_bytes())
+  }
+
+  // Once the resumed game is over, so that it can't be resumed twice.
+  pub fn remove() -> io::Result<()> {
+    storage::remove(&SavedGame::path())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use game::{Input, Tetris};
+  use replay::Recording;
+  use super::{encode_state, SavedGame, SAVE_VERSION};
+
+  fn saved_game() -> SavedGame {
+    let mut tetris = Tetris::with_seed(7);
+    let mut recording = Recording::new("Player", 7);
+    let inputs = [
+      vec![Input::Left], vec![Input::Hold], vec![Input::Rotate],
+      vec![Input::HardDrop], vec![], vec![Input::Right, Input::HardDrop],
+    ];
+    for frame in inputs.iter().cycle().take(20) {
+      tetris.step(frame);
+      recording.record(frame);
+    }
+    SavedGame::new(&tetris, &recording)
+  }
+
+  #[test]
+  fn saved_games_round_trip() {
+    let saved = saved_game();
+    let decoded = SavedGame::decode(&saved.encode()).unwrap();
+    assert_eq!(encode_state(&saved.tetris), encode_state(&decoded.tetris));
+    assert_eq!(saved.recording, decoded.recording);
+    assert!(!decoded.tetris.game_over);
+    assert!(decoded.tetris.held_piece.is_some());
+  }
+
+  #[test]
+  fn refuses_other_versions() {
+    let content = saved_game().encode().replacen(
+      &format!("TETRIS-SAVE {}", SAVE_VERSION),
+      &format!("TETRIS-SAVE {}", SAVE_VERSION + 1),
+      1
+    );
+    assert!(SavedGame::decode(&content).is_err());
+  }
+
+  #[test]
+  fn refuses_replays_that_dont_lead_to_the_game() {
+    let mut saved = saved_game();
+    saved.recording.frames.pop();
+    assert!(SavedGame::decode(&saved.encode()).is_err());
+    let mut saved = saved_game();
+    saved.tetris.score += 1;
+    assert!(SavedGame::decode(&saved.encode()).is_err());
+  }
+}
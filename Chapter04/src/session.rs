@@ -0,0 +1,346 @@
+use std::time::SystemTime;
+
+use game::{Input, Tetris};
+use net::{Connection, Message};
+use rollback::{InputHistory, RemoteBoard};
+
+pub const DISCONNECT_TIMEOUT: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+  Win,
+  Lose,
+  Draw,
+  // Everybody else left the game.
+  Disconnected,
+}
+
+pub struct Peer {
+  pub name: String,
+  pub board: RemoteBoard,
+  pub left: bool,
+  history: InputHistory,
+  last_message: SystemTime,
+}
+
+// A network game seen from one player. The other boards are simulated from
+// their inputs, so every client agrees on where the garbage goes.
+pub struct Session {
+  connection: Connection,
+  relayed: bool,
+  pub local_index: usize,
+  pub local_name: String,
+  pub local: Tetris,
+  // Indexed by player, `None` for ourselves.
+  pub peers: Vec<Option<Peer>>,
+  incoming_garbage: u32,
+}
+
+// Who receives the garbage sent by `attacker` on `frame`. With more than two
+// players it goes around the other players depending on the frame.
+pub fn garbage_target(attacker: usize, frame: u32, nb_players: usize) -> usize {
+  let shift = 1 + frame as usize % (nb_players - 1);
+  (attacker + shift) % nb_players
+}
+
+impl Session {
+  // A game against one opponent connected directly to us.
+  pub fn direct(connection: Connection, seed: u64, host: bool) -> Session {
+    let names = vec!["host".to_owned(), "client".to_owned()];
+    Session::new(connection, false, seed, if host { 0 } else { 1 }, names)
+  }
+
+  // A game through the lobby server, as player `index` of `names`.
+  pub fn relayed(
+    connection: Connection,
+    seed: u64,
+    index: usize,
+    names: Vec<String>
+  ) -> Session {
+    Session::new(connection, true, seed, index, names)
+  }
+
+  fn new(
+    connection: Connection,
+    relayed: bool,
+    seed: u64,
+    local_index: usize,
+    names: Vec<String>
+  ) -> Session {
+    let peers = names.iter().enumerate().map(|(index, name)| {
+      if index == local_index {
+        None
+      } else {
+        Some(Peer {
+          name: name.clone(),
+          board: RemoteBoard::new(seed),
+          left: false,
+          history: InputHistory::new(),
+          last_message: SystemTime::now(),
+        })
+      }
+    }).collect();
+    Session {
+      connection,
+      relayed,
+      local_index,
+      local_name: names[local_index].clone(),
+      local: Tetris::with_seed(seed),
+      peers,
+      incoming_garbage: 0,
+    }
+  }
+
+  pub fn simulate_bad_network(&mut self, latency_ms: u64, loss_percent: u32) {
+    self.connection.simulate_bad_network(latency_ms, loss_percent);
+  }
+
+  pub fn nb_players(&self) -> usize {
+    self.peers.len()
+  }
+
+  // Advances our own board by one frame. Returns the inputs it got,
+  // including the garbage received.
+  pub fn step(&mut self, mut inputs: Vec<Input>) -> Vec<Input> {
+    if self.local.game_over {
+      return Vec::new();
+    }
+    if 0 < self.incoming_garbage {
+      inputs.push(Input::Garbage(self.incoming_garbage));
+      self.incoming_garbage = 0;
+    }
+    self.local.step(&inputs);
+    // The other players work out our garbage from our inputs.
+    self.local.take_garbage();
+    for peer in self.peers.iter_mut().filter_map(|peer| peer.as_mut()) {
+      peer.history.push(inputs.clone());
+    }
+    inputs
+  }
+
+  // Sends our inputs, applies the ones we received and predicts the other
+  // boards up to our frame. Keep calling it once the game is over, the
+  // others might still be missing some of our frames.
+  pub fn sync(&mut self) {
+    for index in 0..self.peers.len() {
+      let message = match self.peers[index] {
+        Some(ref peer) if !peer.left => {
+          peer.history.message(peer.board.confirmed().frame)
+        }
+        _ => continue,
+      };
+      let message = if self.relayed {
+        Message::To { index: index as u32, message: Box::new(message) }
+      } else {
+        message
+      };
+      self.connection.send(&message).ok();
+    }
+
+    for message in self.connection.receive() {
+      let (index, message) = match message {
+        Message::From { index, message } => (index as usize, *message),
+        Message::Left { index } => (index as usize, Message::Bye),
+        message if !self.relayed => (1 - self.local_index, message),
+        _ => continue,
+      };
+      if let Some(&mut Some(ref mut peer)) = self.peers.get_mut(index) {
+        peer.last_message = SystemTime::now();
+        match message {
+          Message::Inputs { ack, first, frames } => {
+            peer.history.acknowledge(ack);
+            peer.board.confirm(first, &frames);
+          }
+          Message::Bye => peer.left = true,
+          _ => {}
+        }
+      }
+    }
+
+    let closed = self.connection.is_closed();
+    let nb_players = self.peers.len();
+    for (index, peer) in self.peers.iter_mut().enumerate() {
+      if let Some(ref mut peer) = *peer {
+        let silent = peer.last_message.elapsed()
+          .map(|elapsed| elapsed.as_secs() >= DISCONNECT_TIMEOUT)
+          .unwrap_or(false);
+        if closed || silent {
+          peer.left = true;
+        }
+        for (frame, lines) in peer.board.take_garbage() {
+          if self.local_index == garbage_target(index, frame, nb_players) {
+            self.incoming_garbage += lines;
+          }
+        }
+        peer.board.predict(self.local.frame);
+      }
+    }
+  }
+
+  // Every client only knows when the others topped out in their own frames,
+  // so the players who lasted the most frames win. Players who left count as
+  // topped out on the last frame we got from them.
+  pub fn outcome(&self) -> Option<Outcome> {
+    let peers = self.peers.iter()
+      .filter_map(|peer| peer.as_ref())
+      .collect::<Vec<_>>();
+    if peers.iter().all(|peer| peer.left && !peer.board.confirmed().game_over) {
+      return Some(Outcome::Disconnected);
+    }
+    let opponents = peers.iter().map(|peer| {
+      let board = peer.board.confirmed();
+      (board.game_over || peer.left, board.frame)
+    }).collect::<Vec<_>>();
+    if self.local.game_over {
+      let death = self.local.frame;
+      let survived = |&(over, frame): &(bool, u32)| {
+        if over { death < frame } else { death <= frame }
+      };
+      if opponents.iter().any(survived) {
+        Some(Outcome::Lose)
+      } else if opponents.iter().any(|&(over, _)| !over) {
+        None
+      } else if opponents.iter().any(|&(_, frame)| death == frame) {
+        Some(Outcome::Draw)
+      } else {
+        Some(Outcome::Win)
+      }
+    } else if opponents.iter()
+      .all(|&(over, frame)| over && frame <= self.local.frame)
+    {
+      Some(Outcome::Win)
+    } else {
+      None
+    }
+  }
+
+  pub fn quit(&mut self) {
+    self.connection.send(&Message::Bye).ok();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpListener;
+  use std::thread;
+  use std::time::{Duration, SystemTime};
+
+  use game::{Input, Line, Tetris};
+  use net::{self, Connection};
+  use rollback::RemoteBoard;
+  use super::{Outcome, Session};
+
+  const SEED: u64 = 42;
+  const FRAMES: u32 = 120;
+
+  // A host and a client connected on loopback, once they said HELLO.
+  fn connect() -> (Session, Session) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+      let mut connection = Connection::connect(address).unwrap();
+      let seed = net::handshake_client(&mut connection).unwrap();
+      Session::direct(connection, seed, false)
+    });
+    let (stream, _) = listener.accept().unwrap();
+    let mut connection = Connection::from_stream(stream).unwrap();
+    net::handshake_host(&mut connection, SEED).unwrap();
+    (Session::direct(connection, SEED, true), client.join().unwrap())
+  }
+
+  // Some moves and a drop every 40 frames, shifted for every player.
+  fn inputs(player: u32, frame: u32) -> Vec<Input> {
+    match (frame + 7 * player) % 40 {
+      5 => vec![Input::Left],
+      10 => vec![Input::Rotate],
+      15 if 0 == player => vec![Input::Right, Input::Right],
+      20 => vec![Input::HardDrop],
+      _ => Vec::new(),
+    }
+  }
+
+  fn summary(tetris: &Tetris) -> (u32, u32, u32, u32, Vec<Line>) {
+    let Tetris { frame, nb_pieces, score, nb_lines, .. } = *tetris;
+    (frame, nb_pieces, score, nb_lines, tetris.game_map.clone())
+  }
+
+  // Syncs both sides until `done`, failing after a few seconds.
+  fn sync_until<F>(sessions: &mut [&mut Session], done: F)
+    where F: Fn(&[&mut Session]) -> bool
+  {
+    let start = SystemTime::now();
+    while !done(sessions) {
+      assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+      for session in sessions.iter_mut() {
+        session.sync();
+      }
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+
+  // Plays `FRAMES` frames on both sides, `frame_time` milliseconds apart,
+  // then waits until each side has confirmed all the frames of the other
+  // one.
+  fn play(host: &mut Session, client: &mut Session, frame_time: u64) {
+    for frame in 1..FRAMES + 1 {
+      host.step(inputs(0, frame));
+      client.step(inputs(1, frame));
+      host.sync();
+      client.sync();
+      thread::sleep(Duration::from_millis(frame_time));
+    }
+    sync_until(&mut [host, client], |sessions| {
+      sessions.iter().all(|session| {
+        session.peers.iter().filter_map(|peer| peer.as_ref())
+          .all(|peer| FRAMES == peer.board.confirmed().frame)
+      })
+    });
+  }
+
+  fn remote(session: &Session) -> &RemoteBoard {
+    &session.peers.iter().filter_map(|peer| peer.as_ref())
+      .next().unwrap().board
+  }
+
+  #[test]
+  fn exchanges_inputs_on_loopback() {
+    let (mut host, mut client) = connect();
+    assert_eq!((0, 1), (host.local_index, client.local_index));
+    play(&mut host, &mut client, 0);
+    assert_eq!(summary(&host.local), summary(remote(&client).confirmed()));
+    assert_eq!(summary(&client.local), summary(remote(&host).confirmed()));
+    assert!(0 < host.local.nb_pieces);
+  }
+
+  #[test]
+  fn bye_disconnects() {
+    let (mut host, mut client) = connect();
+    play(&mut host, &mut client, 0);
+    client.quit();
+    sync_until(&mut [&mut host], |sessions| {
+      Some(Outcome::Disconnected) == sessions[0].outcome()
+    });
+  }
+
+  #[test]
+  fn closed_connection_disconnects() {
+    let (mut host, mut client) = connect();
+    play(&mut host, &mut client, 0);
+    drop(client);
+    sync_until(&mut [&mut host], |sessions| {
+      Some(Outcome::Disconnected) == sessions[0].outcome()
+    });
+  }
+
+  #[test]
+  fn rolls_back_over_a_bad_network() {
+    let (mut host, mut client) = connect();
+    host.simulate_bad_network(30, 20);
+    client.simulate_bad_network(30, 20);
+    play(&mut host, &mut client, 2);
+    assert_eq!(summary(&host.local), summary(remote(&client).confirmed()));
+    assert_eq!(summary(&client.local), summary(remote(&host).confirmed()));
+    assert!(0 < remote(&host).rollbacks);
+    assert!(0 < remote(&client).rollbacks);
+  }
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use game::{Input, Tetris};
+use net::{Connection, Message};
+use save;
+
+// Frames the spectators stay behind the players, so that a late packet from
+// one of them doesn't freeze the view.
+pub const SPECTATOR_DELAY: u32 = 30;
+
+pub struct WatchedBoard {
+  pub name: String,
+  pub tetris: Tetris,
+  pub left: bool,
+  // The frames received and not played yet, from `first` on.
+  first: u32,
+  received: VecDeque<Vec<Input>>,
+}
+
+impl WatchedBoard {
+  // The frame after the last one received.
+  fn next(&self) -> u32 {
+    self.first + self.received.len() as u32
+  }
+
+  // Plays the next frame if it was received.
+  fn play(&mut self) -> bool {
+    match self.received.pop_front() {
+      Some(inputs) => {
+        self.tetris.step(&inputs);
+        self.first += 1;
+        true
+      }
+      None => false,
+    }
+  }
+
+  // The board can't go any further.
+  pub fn is_done(&self) -> bool {
+    self.tetris.game_over || (self.left && self.received.is_empty())
+  }
+}
+
+// A game watched through the lobby. The boards are replayed from the inputs
+// of the players, they carry the garbage they received so nothing else is
+// needed.
+pub struct Spectator {
+  connection: Connection,
+  pub boards: Vec<WatchedBoard>,
+}
+
+impl Spectator {
+  pub fn new(
+    connection: Connection,
+    seed: u64,
+    names: Vec<String>
+  ) -> Spectator {
+    let boards = names.into_iter().map(|name| WatchedBoard {
+      name,
+      tetris: Tetris::with_seed(seed),
+      left: false,
+      first: 1,
+      received: VecDeque::new(),
+    }).collect();
+    Spectator {
+      connection,
+      boards,
+    }
+  }
+
+  // Receives the latest inputs and advances the boards by at most a frame,
+  // staying `SPECTATOR_DELAY` frames behind the most advanced player. The
+  // catch-up frames sent when we join are played a few at a time, from the
+  // boards the lobby sends first.
+  pub fn update(&mut self) {
+    for message in self.connection.receive() {
+      let (index, message) = match message {
+        Message::From { index, message } => (index as usize, *message),
+        Message::Left { index } => (index as usize, Message::Bye),
+        Message::State { index, state } => {
+          (index as usize, Message::State { index, state })
+        }
+        _ => continue,
+      };
+      if let Some(board) = self.boards.get_mut(index) {
+        match message {
+          Message::Inputs { first, frames, .. } => {
+            for (nb, inputs) in frames.into_iter().enumerate() {
+              if first + nb as u32 == board.next() {
+                board.received.push_back(inputs);
+              }
+            }
+          }
+          Message::State { ref state, .. } => {
+            if let Ok(tetris) = save::decode_state(state) {
+              board.first = tetris.frame + 1;
+              board.received.clear();
+              board.tetris = tetris;
+            }
+          }
+          Message::Bye => board.left = true,
+          _ => {}
+        }
+      }
+    }
+    if self.connection.is_closed() {
+      for board in &mut self.boards {
+        board.left = true;
+      }
+    }
+
+    let latest = self.boards.iter()
+      .map(|board| board.next() - 1)
+      .max()
+      .unwrap_or(0);
+    let playhead = latest.saturating_sub(SPECTATOR_DELAY);
+    for board in &mut self.boards {
+      let mut steps = 0;
+      while !board.tetris.game_over && steps < 4 &&
+        (board.tetris.frame < playhead || board.left) && board.play()
+      {
+        steps += 1;
+      }
+    }
+  }
+
+  // The game is over once at most one player is still going. Returns the
+  // name of the winner, `None` for a draw.
+  pub fn winner(&self) -> Option<Option<&str>> {
+    let alive = self.boards.iter()
+      .filter(|board| !board.is_done())
+      .collect::<Vec<_>>();
+    match alive.len() {
+      0 => {
+        let last = self.boards.iter().map(|board| board.tetris.frame).max()
+          .unwrap_or(0);
+        let survivors = self.boards.iter()
+          .filter(|board| last == board.tetris.frame)
+          .collect::<Vec<_>>();
+        if 1 == survivors.len() {
+          Some(Some(survivors[0].name.as_str()))
+        } else {
+          Some(None)
+        }
+      }
+      1 if 1 < self.boards.len() => Some(Some(alive[0].name.as_str())),
+      _ => None,
+    }
+  }
+
+  pub fn quit(&mut self) {
+    self.connection.send(&Message::Bye).ok();
+  }
+}
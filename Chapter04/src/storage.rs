@@ -0,0 +1,259 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Set to keep the data somewhere else, a test directory for instance.
+pub const DATA_DIR_VARIABLE: &str = "TETRIS_DATA_DIR";
+const APPLICATION: &str = "tetris";
+
+fn absolute_var(name: &str) -> Option<PathBuf> {
+  env::var_os(name).map(PathBuf::from).filter(|path| path.is_absolute())
+}
+
+// Where the game keeps what it writes: `$XDG_DATA_HOME/tetris`, or
+// `~/.local/share/tetris`, on Linux and the other Unixes,
+// `~/Library/Application Support/tetris` on macOS and `%APPDATA%\tetris` on
+// Windows. The working directory when none of them is known.
+pub fn data_dir() -> PathBuf {
+  if let Some(dir) = env::var_os(DATA_DIR_VARIABLE) {
+    return PathBuf::from(dir);
+  }
+  let home = absolute_var("HOME");
+  let base = if cfg!(windows) {
+    absolute_var("APPDATA")
+  } else if cfg!(target_os = "macos") {
+    home.map(|home| home.join("Library").join("Application Support"))
+  } else {
+    absolute_var("XDG_DATA_HOME")
+      .or_else(|| home.map(|home| home.join(".local").join("share")))
+  };
+  match base {
+    Some(base) => base.join(APPLICATION),
+    None => PathBuf::from("."),
+  }
+}
+
+// A file or directory of the data directory.
+pub fn data_path(name: &str) -> PathBuf {
+  data_dir().join(name)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(suffix);
+  PathBuf::from(name)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+  with_suffix(path, ".bak")
+}
+
+// Writes the file through a temporary file renamed over it, so that it is
+// never left half written.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let temporary = with_suffix(path, ".tmp");
+  {
+    let mut f = File::create(&temporary)?;
+    f.write_all(content)?;
+    f.sync_all()?;
+  }
+  fs::rename(&temporary, path)
+}
+
+// The same, keeping the previous content in the backup file.
+pub fn write_with_backup(path: &Path, content: &[u8]) -> io::Result<()> {
+  if path.is_file() {
+    fs::copy(path, backup_path(path))?;
+  }
+  write_atomic(path, content)
+}
+
+// Removes the file and its backup.
+pub fn remove(path: &Path) -> io::Result<()> {
+  for path in &[path.to_owned(), backup_path(path)] {
+    match fs::remove_file(path) {
+      Err(ref e) if ErrorKind::NotFound == e.kind() => {}
+      result => result?,
+    }
+  }
+  Ok(())
+}
+
+// What loading a file found.
+pub struct Loaded<T> {
+  // None when there is no file.
+  pub value: Option<T>,
+  // Where the file went if it couldn't be read, and why.
+  pub corrupt: Option<(PathBuf, io::Error)>,
+  // The value is the one of the backup.
+  pub from_backup: bool,
+}
+
+fn read(path: &Path) -> io::Result<Option<Vec<u8>>> {
+  let mut content = Vec::new();
+  match File::open(path) {
+    Ok(mut f) => {
+      f.read_to_end(&mut content)?;
+      Ok(Some(content))
+    }
+    Err(ref e) if ErrorKind::NotFound == e.kind() => Ok(None),
+    Err(e) => Err(e),
+  }
+}
+
+fn decode_bytes<T, F>(content: Vec<u8>, decode: &F) -> io::Result<T>
+  where F: Fn(&str) -> io::Result<T>
+{
+  let content = String::from_utf8(content)
+    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+  decode(&content)
+}
+
+// Reads and decodes a file. One that can't be decoded isn't lost: it is
+// moved aside as `<file>.corrupt-<time>`, and the backup is read instead.
+// Errors are left for the files that can't be read at all.
+pub fn load<T, F>(path: &Path, decode: F) -> io::Result<Loaded<T>>
+  where F: Fn(&str) -> io::Result<T>
+{
+  let error = match read(path)? {
+    None => return Ok(Loaded {
+      value: None,
+      corrupt: None,
+      from_backup: false,
+    }),
+    Some(content) => match decode_bytes(content, &decode) {
+      Ok(value) => return Ok(Loaded {
+        value: Some(value),
+        corrupt: None,
+        from_backup: false,
+      }),
+      Err(e) => e,
+    },
+  };
+  let now = SystemTime::now().duration_since(UNIX_EPOCH)
+    .map(|now| now.as_secs())
+    .unwrap_or(0);
+  let corrupt = with_suffix(path, &format!(".corrupt-{}", now));
+  fs::rename(path, &corrupt)?;
+  let backup = read(&backup_path(path))?
+    .and_then(|content| decode_bytes(content, &decode).ok());
+  Ok(Loaded {
+    from_backup: backup.is_some(),
+    value: backup,
+    corrupt: Some((corrupt, error)),
+  })
+}
+
+// Moves a file the game used to write in the working directory to the data
+// directory, unless it's there already.
+pub fn migrate(name: &str) -> io::Result<bool> {
+  let (old, new) = (Path::new(name), data_path(name));
+  if !old.exists() || new.exists() {
+    return Ok(false);
+  }
+  if let Some(parent) = new.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  if fs::rename(old, &new).is_err() {
+    // Not on the same file system.
+    if old.is_dir() {
+      return Ok(false);
+    }
+    fs::copy(old, &new)?;
+    fs::remove_file(old)?;
+  }
+  Ok(true)
+}
+
+// Points the data directory of the tests at a directory of their own, the
+// same one for every test since they share the environment.
+#[cfg(test)]
+pub fn test_data_dir() -> PathBuf {
+  use std::process;
+  use std::sync::Once;
+  static SET: Once = Once::new();
+  let dir = env::temp_dir().join(format!("tetris-test-{}", process::id()));
+  SET.call_once(|| env::set_var(DATA_DIR_VARIABLE, &dir));
+  dir
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::io::{self, ErrorKind};
+  use std::path::Path;
+  use std::process;
+  use super::{
+    backup_path, data_path, load, migrate, test_data_dir, write_atomic,
+    write_with_backup,
+  };
+
+  fn decode(content: &str) -> io::Result<u32> {
+    content.trim().parse()
+      .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+  }
+
+  #[test]
+  fn data_goes_to_the_data_directory() {
+    assert_eq!(test_data_dir().join("scores"), data_path("scores"));
+  }
+
+  #[test]
+  fn writes_keep_a_backup() {
+    let path = test_data_dir().join("writes").join("value");
+    write_atomic(&path, b"1").unwrap();
+    assert_eq!("1", fs::read_to_string(&path).unwrap());
+    assert!(!backup_path(&path).exists());
+    write_with_backup(&path, b"2").unwrap();
+    assert_eq!("2", fs::read_to_string(&path).unwrap());
+    assert_eq!("1", fs::read_to_string(backup_path(&path)).unwrap());
+    let files = fs::read_dir(path.parent().unwrap()).unwrap().count();
+    assert_eq!(2, files);
+  }
+
+  #[test]
+  fn corrupt_files_are_moved_aside_for_the_backup() {
+    let dir = test_data_dir().join("corrupt");
+    let path = dir.join("value");
+    let loaded = load(&path, decode).unwrap();
+    assert!(loaded.value.is_none() && loaded.corrupt.is_none());
+
+    write_atomic(&path, b"3").unwrap();
+    write_with_backup(&path, b"three").unwrap();
+    let loaded = load(&path, decode).unwrap();
+    assert_eq!(Some(3), loaded.value);
+    assert!(loaded.from_backup);
+    let (corrupt, error) = loaded.corrupt.unwrap();
+    assert_eq!(ErrorKind::InvalidData, error.kind());
+    assert_eq!("three", fs::read_to_string(&corrupt).unwrap());
+    assert!(!path.exists());
+
+    // Without a good backup nothing is read, the file is still kept.
+    write_atomic(&backup_path(&path), b"four").unwrap();
+    write_atomic(&path, b"five").unwrap();
+    let loaded = load(&path, decode).unwrap();
+    assert!(loaded.value.is_none() && !loaded.from_backup);
+    let (corrupt, _) = loaded.corrupt.unwrap();
+    assert_eq!("five", fs::read_to_string(&corrupt).unwrap());
+  }
+
+  #[test]
+  fn files_of_the_working_directory_are_migrated_once() {
+    test_data_dir();
+    let name = format!("target/migrated-{}", process::id());
+    fs::write(&name, b"old").unwrap();
+    assert!(migrate(&name).unwrap());
+    assert!(!Path::new(&name).exists());
+    assert_eq!("old", fs::read_to_string(data_path(&name)).unwrap());
+    fs::write(&name, b"older").unwrap();
+    assert!(!migrate(&name).unwrap());
+    assert_eq!("old", fs::read_to_string(data_path(&name)).unwrap());
+    fs::remove_file(&name).unwrap();
+    assert!(!migrate(&name).unwrap());
+  }
+}
@@ -0,0 +1,433 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use bot::Controller;
+use game::{Input, Line, Tetris, HEIGHT, WIDTH};
+use json::Json;
+use movegen::{self, Placement};
+use tetrimino::{piece_letter, Tetrimino};
+
+pub const ANSWER_TIMEOUT: u64 = 10;
+// Rows of the board sent to the bots, the ones above ours are empty.
+const TBP_HEIGHT: usize = 40;
+
+// Drives an external bot speaking the Tetris Bot Protocol: one JSON message
+// per line on its stdin and stdout.
+//
+//   bot                       game
+//   info {name, ...}   ->
+//                      <-     rules
+//   ready              ->
+//                      <-     start {hold, queue, combo, back_to_back, board}
+//                      <-     suggest
+//   suggestion {moves} ->
+//                      <-     play {move}
+//                      <-     new_piece {piece}      for every piece revealed
+//                      <-     stop, quit
+//
+// Boards are 40 rows of 10 cases, bottom row first, with the letter of the
+// piece or "G" for garbage, null for empty cases. A move is the location of
+// the piece's center like SRS puts it, which is turned into the inputs of
+// our rotation system by looking for the placement taking the same cases.
+// The bot is started again whenever the board doesn't match what it thinks,
+// like after garbage rose.
+pub struct ExternalBot {
+  pub name: String,
+  // Waits for the suggestions instead of letting the piece fall meanwhile,
+  // for headless matches.
+  pub blocking: bool,
+  pub delay: u32,
+  child: Child,
+  stdin: ChildStdin,
+  messages: Receiver<String>,
+  started: bool,
+  waiting: bool,
+  failed: bool,
+  wait: u32,
+  planned_piece: u32,
+  plan: VecDeque<Input>,
+  // The board and hold the bot expects after its move, and how many pieces
+  // it will need to hear about.
+  expected: Option<(Vec<Line>, Option<u8>, u32)>,
+}
+
+// The cases around the center of a piece pointing north, y going up.
+fn srs_shape(letter: &str) -> Option<[(isize, isize); 4]> {
+  match letter {
+    "I" => Some([(-1, 0), (0, 0), (1, 0), (2, 0)]),
+    "O" => Some([(0, 0), (1, 0), (0, 1), (1, 1)]),
+    "T" => Some([(-1, 0), (0, 0), (1, 0), (0, 1)]),
+    "L" => Some([(-1, 0), (0, 0), (1, 0), (1, 1)]),
+    "J" => Some([(-1, 0), (0, 0), (1, 0), (-1, 1)]),
+    "S" => Some([(-1, 0), (0, 0), (0, 1), (1, 1)]),
+    "Z" => Some([(-1, 1), (0, 1), (0, 0), (1, 0)]),
+    _ => None,
+  }
+}
+
+// The cases of our grid taken by a move, sorted.
+fn move_cells(location: &Json) -> Option<(String, Vec<(isize, usize)>)> {
+  let letter = location.get("type")?.as_str()?;
+  let x = location.get("x")?.as_f64()? as isize;
+  let y = location.get("y")?.as_f64()? as isize;
+  let orientation = location.get("orientation")?.as_str()?;
+  let mut cells = Vec::new();
+  for &(shift_x, shift_y) in srs_shape(letter)?.iter() {
+    let (shift_x, shift_y) = match orientation {
+      "north" => (shift_x, shift_y),
+      "east" => (shift_y, -shift_x),
+      "south" => (-shift_x, -shift_y),
+      "west" => (-shift_y, shift_x),
+      _ => return None,
+    };
+    let row = y + shift_y;
+    if row < 0 || HEIGHT as isize <= row {
+      return None;
+    }
+    cells.push((x + shift_x, HEIGHT - 1 - row as usize));
+  }
+  cells.sort();
+  Some((letter.to_owned(), cells))
+}
+
+fn find_placement(
+  game_map: &[Line],
+  piece: Tetrimino,
+  cells: &[(isize, usize)]
+) -> Option<Placement> {
+  movegen::placements(game_map, piece).into_iter().find(|placement| {
+    let mut taken = placement.cells();
+    taken.sort();
+    cells == &taken[..]
+  })
+}
+
+fn board(tetris: &Tetris) -> Json {
+  let rows = (0..TBP_HEIGHT).map(|row| {
+    let line = if row < HEIGHT {
+      Some(&tetris.game_map[HEIGHT - 1 - row])
+    } else {
+      None
+    };
+    Json::Array((0..WIDTH).map(|x| match line {
+      Some(line) if 0 != line[x] => Json::string(piece_letter(line[x])),
+      _ => Json::Null,
+    }).collect())
+  }).collect();
+  Json::Array(rows)
+}
+
+fn letter_of(piece: &Option<Tetrimino>) -> Json {
+  match *piece {
+    Some(ref piece) => Json::string(piece_letter(piece.color())),
+    None => Json::Null,
+  }
+}
+
+impl ExternalBot {
+  // Starts the bot, `command` being the program followed by its arguments,
+  // and waits until it is ready.
+  pub fn spawn(command: &str) -> io::Result<ExternalBot> {
+    let mut words = command.split_whitespace();
+    let program = words.next()
+      .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no command"))?;
+    let mut child = Command::new(program)
+      .args(words)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()?;
+    let stdin = child.stdin.take().expect("The bot has no stdin");
+    let stdout = child.stdout.take().expect("The bot has no stdout");
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+      for line in BufReader::new(stdout).lines() {
+        match line {
+          Ok(line) => if sender.send(line).is_err() { break },
+          Err(_) => break,
+        }
+      }
+    });
+    let mut bot = ExternalBot {
+      name: String::new(),
+      blocking: false,
+      delay: 0,
+      child,
+      stdin,
+      messages,
+      started: false,
+      waiting: false,
+      failed: false,
+      wait: 0,
+      planned_piece: 0,
+      plan: VecDeque::new(),
+      expected: None,
+    };
+    let info = bot.expect_message("info")?;
+    bot.name = info.get("name").and_then(|name| name.as_str())
+      .unwrap_or(program)
+      .to_owned();
+    bot.send(&Json::object(vec![("type", Json::string("rules"))]))?;
+    bot.expect_message("ready")?;
+    Ok(bot)
+  }
+
+  fn send(&mut self, message: &Json) -> io::Result<()> {
+    writeln!(self.stdin, "{}", message)?;
+    self.stdin.flush()
+  }
+
+  fn receive(&mut self, blocking: bool) -> io::Result<Option<Json>> {
+    let line = if blocking {
+      match self.messages.recv_timeout(Duration::from_secs(ANSWER_TIMEOUT)) {
+        Ok(line) => line,
+        Err(RecvTimeoutError::Timeout) => {
+          return Err(io::Error::new(ErrorKind::TimedOut, "the bot is silent"));
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+          return Err(io::Error::new(ErrorKind::UnexpectedEof, "the bot quit"));
+        }
+      }
+    } else {
+      match self.messages.try_recv() {
+        Ok(line) => line,
+        Err(mpsc::TryRecvError::Empty) => return Ok(None),
+        Err(mpsc::TryRecvError::Disconnected) => {
+          return Err(io::Error::new(ErrorKind::UnexpectedEof, "the bot quit"));
+        }
+      }
+    };
+    Json::parse(&line).map(Some)
+      .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+  }
+
+  fn expect_message(&mut self, kind: &str) -> io::Result<Json> {
+    loop {
+      if let Some(message) = self.receive(true)? {
+        match message.get("type").and_then(|kind| kind.as_str()) {
+          Some(received) if received == kind => return Ok(message),
+          Some("error") => {
+            let reason = message.get("reason").and_then(|reason| {
+              reason.as_str()
+            }).unwrap_or("error").to_owned();
+            return Err(io::Error::new(ErrorKind::Other, reason));
+          }
+          _ => {}
+        }
+      }
+    }
+  }
+
+  // Tells the bot about the pieces it didn't see yet, or starts it again
+  // from the current state if it doesn't match what it expects.
+  fn synchronize(&mut self, tetris: &Tetris) -> io::Result<()> {
+    let held = tetris.held_piece.map(|piece| piece.color());
+    match self.expected.take() {
+      Some((ref game_map, hold, reveals)) if self.started &&
+        *game_map == tetris.game_map && hold == held =>
+      {
+        let revealed = if 2 == reveals {
+          vec![tetris.current_piece, tetris.next_piece]
+        } else {
+          vec![tetris.next_piece]
+        };
+        for piece in revealed {
+          self.send(&Json::object(vec![
+            ("type", Json::string("new_piece")),
+            ("piece", letter_of(&piece)),
+          ]))?;
+        }
+      }
+      _ => {
+        if self.started {
+          self.send(&Json::object(vec![("type", Json::string("stop"))]))?;
+        }
+        self.send(&Json::object(vec![
+          ("type", Json::string("start")),
+          ("hold", letter_of(&tetris.held_piece)),
+          ("queue", Json::Array(vec![
+            letter_of(&tetris.current_piece),
+            letter_of(&tetris.next_piece),
+          ])),
+          ("combo", Json::Number(0.)),
+          ("back_to_back", Json::Bool(false)),
+          ("board", board(tetris)),
+        ]))?;
+        self.started = true;
+      }
+    }
+    Ok(())
+  }
+
+  // The inputs for the first suggested move we can play.
+  fn choose(&mut self, tetris: &Tetris, suggestion: &Json)
+    -> io::Result<Vec<Input>>
+  {
+    let moves = suggestion.get("moves").and_then(|moves| moves.as_array())
+      .unwrap_or(&[]);
+    for suggested in moves {
+      let (letter, cells) = match suggested.get("location")
+        .and_then(move_cells)
+      {
+        Some(cells) => cells,
+        None => continue,
+      };
+      let current = match tetris.current_piece {
+        Some(piece) => piece,
+        None => break,
+      };
+      let mut after = tetris.clone();
+      let mut inputs = Vec::new();
+      let mut reveals = 1;
+      if letter != piece_letter(current.color()) {
+        if !after.hold() || after.game_over {
+          continue
+        }
+        inputs.push(Input::Hold);
+        if tetris.held_piece.is_none() {
+          reveals = 2;
+        }
+      }
+      let piece = match after.current_piece {
+        Some(piece) if letter == piece_letter(piece.color()) => piece,
+        _ => continue,
+      };
+      let placement = match find_placement(&after.game_map, piece, &cells) {
+        Some(placement) => placement,
+        None => continue,
+      };
+      inputs.extend(placement.inputs);
+      after.pending_garbage = 0;
+      after.current_piece = Some(placement.piece);
+      after.make_permanent();
+      let held = after.held_piece.map(|piece| piece.color());
+      self.expected = Some((after.game_map, held, reveals));
+      self.send(&Json::object(vec![
+        ("type", Json::string("play")),
+        ("move", suggested.clone()),
+      ]))?;
+      return Ok(inputs);
+    }
+    // Nothing we can play, the bot gets started again on the next piece.
+    self.expected = None;
+    Ok(vec![Input::HardDrop])
+  }
+
+  fn try_inputs(&mut self, tetris: &Tetris) -> io::Result<Vec<Input>> {
+    if self.planned_piece != tetris.nb_pieces {
+      self.planned_piece = tetris.nb_pieces;
+      self.plan.clear();
+      self.synchronize(tetris)?;
+      self.send(&Json::object(vec![("type", Json::string("suggest"))]))?;
+      self.waiting = true;
+    }
+    if self.waiting {
+      let blocking = self.blocking;
+      match self.receive(blocking)? {
+        Some(ref message) if Some("suggestion") ==
+          message.get("type").and_then(|kind| kind.as_str()) =>
+        {
+          self.plan = self.choose(tetris, message)?.into_iter().collect();
+          self.waiting = false;
+          self.wait = self.delay;
+        }
+        _ => return Ok(Vec::new()),
+      }
+    }
+    if 0 < self.wait {
+      self.wait -= 1;
+      return Ok(Vec::new());
+    }
+    self.wait = self.delay;
+    let input = self.plan.pop_front();
+    if Some(Input::Hold) == input {
+      // The held piece comes in as a new one, but the plan goes on.
+      self.planned_piece += 1;
+    }
+    Ok(input.into_iter().collect())
+  }
+
+  // Forgets the game being played, the next one starts the bot again.
+  pub fn new_game(&mut self) {
+    self.planned_piece = 0;
+    self.plan.clear();
+    self.expected = None;
+    self.waiting = false;
+  }
+
+  pub fn quit(&mut self) {
+    self.send(&Json::object(vec![("type", Json::string("quit"))])).ok();
+    for _ in 0..10 {
+      if let Ok(Some(_)) = self.child.try_wait() {
+        return;
+      }
+      thread::sleep(Duration::from_millis(50));
+    }
+    self.child.kill().ok();
+    self.child.wait().ok();
+  }
+}
+
+impl Controller for ExternalBot {
+  fn inputs(&mut self, tetris: &Tetris) -> Vec<Input> {
+    if tetris.current_piece.is_none() || tetris.game_over || self.failed {
+      return Vec::new();
+    }
+    match self.try_inputs(tetris) {
+      Ok(inputs) => inputs,
+      Err(e) => {
+        println!("{} stopped playing: {}", self.name, e);
+        self.failed = true;
+        Vec::new()
+      }
+    }
+  }
+}
+
+impl Drop for ExternalBot {
+  fn drop(&mut self) {
+    self.quit();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use game::{Input, HEIGHT, WIDTH};
+  use json::Json;
+  use tetrimino::{TetriminoGenerator, TetriminoT};
+  use super::{find_placement, move_cells};
+
+  fn location(x: f64, y: f64, orientation: &str) -> Json {
+    Json::object(vec![
+      ("type", Json::string("T")),
+      ("x", Json::Number(x)),
+      ("y", Json::Number(y)),
+      ("orientation", Json::string(orientation)),
+    ])
+  }
+
+  #[test]
+  fn moves_become_the_placement_taking_their_cases() {
+    let game_map = vec![[0; WIDTH]; HEIGHT];
+    let (letter, cells) = move_cells(&location(4., 0., "north")).unwrap();
+    assert_eq!("T", letter);
+    let bottom = HEIGHT - 1;
+    assert_eq!(vec![(3, bottom), (4, bottom - 1), (4, bottom), (5, bottom)],
+      cells);
+    let placement = find_placement(&game_map, TetriminoT::new(), &cells)
+      .unwrap();
+    assert_eq!(Some(&Input::HardDrop), placement.inputs.last());
+
+    // Against the left wall, pointing right.
+    let (_, cells) = move_cells(&location(0., 1., "east")).unwrap();
+    assert!(find_placement(&game_map, TetriminoT::new(), &cells).is_some());
+    // Floating, or out of the grid.
+    let (_, cells) = move_cells(&location(4., 5., "north")).unwrap();
+    assert!(find_placement(&game_map, TetriminoT::new(), &cells).is_none());
+    assert!(move_cells(&location(4., -1., "north")).is_none());
+    assert!(move_cells(&location(4., 0., "up")).is_none());
+  }
+}
@@ -0,0 +1,403 @@
+use game::Line;
+
+pub type Piece = [[u8; 4]; 4];
+pub type States = &'static [Piece];
+
+pub trait TetriminoGenerator {
+  #[allow(clippy::new_ret_no_self)]
+  fn new() -> Tetrimino;
+}
+
+pub struct TetriminoI;
+impl TetriminoGenerator for TetriminoI
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [1, 1, 1, 1],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 1, 0, 0],
+          [0, 1, 0, 0],
+          [0, 1, 0, 0],
+          [0, 1, 0, 0],
+        ],
+      ],
+      x: 4,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+pub struct TetriminoJ;
+impl TetriminoGenerator for TetriminoJ
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [2, 2, 2, 0],
+          [2, 0, 0, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [2, 2, 0, 0],
+          [0, 2, 0, 0],
+          [0, 2, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 0, 2, 0],
+          [2, 2, 2, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [2, 0, 0, 0],
+          [2, 0, 0, 0],
+          [2, 2, 0, 0],
+          [0, 0, 0, 0],
+        ],
+      ],
+      x: 4,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+pub struct TetriminoL;
+impl TetriminoGenerator for TetriminoL
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [3, 3, 3, 0],
+          [0, 0, 3, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 3, 0, 0],
+          [0, 3, 0, 0],
+          [3, 3, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [3, 0, 0, 0],
+          [3, 3, 3, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [3, 3, 0, 0],
+          [3, 0, 0, 0],
+          [3, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+      ],
+      x: 4,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+pub struct TetriminoO;
+impl TetriminoGenerator for TetriminoO
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [4, 4, 0, 0],
+          [4, 4, 0, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+      ],
+      x: 5,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+pub struct TetriminoS;
+impl TetriminoGenerator for TetriminoS
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [0, 5, 5, 0],
+          [5, 5, 0, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 5, 0, 0],
+          [0, 5, 5, 0],
+          [0, 0, 5, 0],
+          [0, 0, 0, 0],
+        ],
+      ],
+      x: 4,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+pub struct TetriminoZ;
+impl TetriminoGenerator for TetriminoZ
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [6, 6, 0, 0],
+          [0, 6, 6, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 0, 6, 0],
+          [0, 6, 6, 0],
+          [0, 6, 0, 0],
+          [0, 0, 0, 0],
+        ],
+      ],
+      x: 4,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+pub struct TetriminoT;
+impl TetriminoGenerator for TetriminoT
+{
+  fn new() -> Tetrimino {
+    Tetrimino {
+      states: &[
+        [
+          [7, 7, 7, 0],
+          [0, 7, 0, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 7, 0, 0],
+          [7, 7, 0, 0],
+          [0, 7, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 7, 0, 0],
+          [7, 7, 7, 0],
+          [0, 0, 0, 0],
+          [0, 0, 0, 0],
+        ],
+        [
+          [0, 7, 0, 0],
+          [0, 7, 7, 0],
+          [0, 7, 0, 0],
+          [0, 0, 0, 0],
+        ],
+      ],
+      x: 4,
+      y: 0,
+      current_state: 0,
+    }
+  }
+}
+
+// The letter of the shape of a piece, "G" for garbage. `TetriminoJ` is shaped
+// like an L and `TetriminoL` like a J.
+pub fn piece_letter(color: u8) -> &'static str {
+  match color {
+    1 => "I",
+    2 => "L",
+    3 => "J",
+    4 => "O",
+    5 => "S",
+    6 => "Z",
+    7 => "T",
+    _ => "G",
+  }
+}
+
+pub fn color_of_letter(letter: char) -> Option<u8> {
+  (1..8).find(|&color| {
+    piece_letter(color).starts_with(letter.to_ascii_uppercase())
+  })
+}
+
+#[derive(Clone, Copy)]
+pub struct Tetrimino {
+  pub states: States,
+  pub x: isize,
+  pub y: usize,
+  pub current_state: u8,
+}
+
+impl Tetrimino {
+  // The color of the piece, which also tells which piece it is.
+  pub fn color(&self) -> u8 {
+    self.states[0].iter().flat_map(|line| line.iter())
+      .cloned()
+      .find(|case| 0 != *case)
+      .unwrap_or(0)
+  }
+
+  pub fn from_color(color: u8) -> Option<Tetrimino> {
+    match color {
+      1 => Some(TetriminoI::new()),
+      2 => Some(TetriminoJ::new()),
+      3 => Some(TetriminoL::new()),
+      4 => Some(TetriminoO::new()),
+      5 => Some(TetriminoS::new()),
+      6 => Some(TetriminoZ::new()),
+      7 => Some(TetriminoT::new()),
+      _ => None,
+    }
+  }
+
+  // The same piece, back where it enters the grid.
+  pub fn spawn(&self) -> Tetrimino {
+    Tetrimino {
+      states: self.states,
+      x: if 4 == self.color() { 5 } else { 4 },
+      y: 0,
+      current_state: 0,
+    }
+  }
+
+  pub fn rotate(&mut self, game_map: &[Line]) {
+    let mut tmp_state = self.current_state + 1;
+    if self.states.len() <= tmp_state as usize {
+      tmp_state = 0;
+    }
+    // Sideways first, then down a row or two, which lets a T turn into the
+    // slot under an overhang.
+    let kicks = [
+      (0, 0), (-1, 0), (1, 0), (-2, 0), (2, 0), (-3, 0),
+      (0, 1), (-1, 1), (1, 1), (0, 2), (-1, 2), (1, 2),
+    ];
+    for &(x, y) in kicks.iter() {
+      let test_result = self.test_position(
+        game_map,
+        tmp_state as usize,
+        self.x + x,
+        self.y + y
+      );
+      if test_result {
+        self.current_state = tmp_state;
+        self.x += x;
+        self.y += y;
+        break
+      }
+    }
+  }
+
+  pub fn test_position(
+    &self,
+    game_map: &[Line],
+    tmp_state: usize,
+    x: isize,
+    y: usize
+  ) -> bool {
+    for shift_y in 0..4 {
+      for shift_x in 0..4 {
+        let x = x + shift_x;
+        if 0 != self.states[tmp_state][shift_y][shift_x as usize] && (
+          game_map.len() <= y + shift_y ||
+          x < 0 ||
+          game_map[y + shift_y].len() <= x as usize ||
+          0 != game_map[y + shift_y][x as usize]
+        ) {
+          return false;
+        }
+      }
+    }
+    true
+  }
+
+  pub fn test_current_position(&self, game_map: &[Line]) -> bool {
+    self.test_position(game_map, self.current_state as usize, self.x, self.y)
+  }
+
+  pub fn change_position(
+    &mut self,
+    game_map: &[Line],
+    new_x: isize,
+    new_y: usize
+  ) -> bool {
+    if self.test_position(
+      game_map,
+      self.current_state as usize,
+      new_x,
+      new_y
+    ) {
+      self.x = new_x;
+      self.y = new_y;
+      true
+    } else {
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use game::WIDTH;
+  use movegen;
+  use super::{TetriminoGenerator, TetriminoT};
+
+  #[test]
+  fn rotations_kick_down_under_overhangs() {
+    let mut piece = TetriminoT::new();
+    piece.y = 2;
+    let mut turned = piece;
+    turned.current_state = 1;
+    turned.y = 3;
+    // Only the piece and the turned piece a row down fit.
+    let mut game_map = vec![[8; WIDTH]; 8];
+    for (x, y) in movegen::cells(&piece).into_iter()
+      .chain(movegen::cells(&turned))
+    {
+      game_map[y][x as usize] = 0;
+    }
+    let mut rotated = piece;
+    rotated.rotate(&game_map);
+    assert_eq!((1, 4, 3), (rotated.current_state, rotated.x, rotated.y));
+
+    // Sideways kicks come first.
+    let mut right = turned;
+    right.x += 1;
+    right.y -= 1;
+    for (x, y) in movegen::cells(&right) {
+      game_map[y][x as usize] = 0;
+    }
+    let mut rotated = piece;
+    rotated.rotate(&game_map);
+    assert_eq!((1, 5, 2), (rotated.current_state, rotated.x, rotated.y));
+
+    // Nothing fits, the piece doesn't turn.
+    let mut game_map = vec![[8; WIDTH]; 8];
+    for (x, y) in movegen::cells(&piece) {
+      game_map[y][x as usize] = 0;
+    }
+    let mut rotated = piece;
+    rotated.rotate(&game_map);
+    assert_eq!((0, 4, 2), (rotated.current_state, rotated.x, rotated.y));
+  }
+}